@@ -1,11 +1,25 @@
-use super::input::{get_input_devices, is_touch_device, optimize_events, read_input};
-use crate::fbink::FbInkWrapper;
-use crate::util::sleep;
+use super::arbitrary::InputOptions;
+use super::benchmark::{BenchmarkJob, BenchmarkOptions, BenchmarkRun};
+use super::devices::{DeviceLocks, EvdevDevices, InputDevices};
+use super::history::{PlayHistory, PlayRecord};
+use super::inject::{EventSink, InjectionBackend, Injector};
+use super::input::{optimize_events, DeviceEvents, DeviceFingerprint};
+use super::player::{PlayJob, Player, PlayerJob};
+use super::recording::RecordingControl;
+use super::synthetic::SyntheticActionOptions;
+use crate::config::Config;
+use crate::fbink::{FbInkWrapper, RotationSource, ScreenSource};
+use crate::nickel_menu::NickelMenu;
+use crate::util::sleep_until;
 
 use std::collections::BTreeMap;
-use std::fs::{self, File};
-use std::io::Write;
+use std::fs;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc as std_mpsc;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Instant;
 
 use anyhow::{anyhow, Context, Result};
 use chrono::{DateTime, Duration, Utc};
@@ -13,83 +27,275 @@ use evdev_rs::util::event_code_to_int;
 use evdev_rs::{InputEvent, TimeVal};
 use fbink_rs::CanonicalRotation;
 use nix::libc;
-use num_enum::FromPrimitive;
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DurationMicroSeconds, DurationMilliSeconds};
 use slug::slugify;
 use tokio::sync::{mpsc, oneshot};
-use tracing::{debug, warn};
+use tracing::{debug, error, warn};
 
 pub struct ActionManager {
     pub actions: ActionsFile,
-    fbink: FbInkWrapper,
+    rotation: Arc<dyn RotationSource>,
+    devices: Arc<dyn InputDevices>,
     pub recordings: RecordingsFile,
-    rx: mpsc::Receiver<ActionMsg>,
-    play_wait_until: DateTime<Utc>,
+    /// What the player sees of the actions and recordings, updated after every change
+    snapshot: SharedActions,
+    locks: DeviceLocks,
+    history: PlayHistory,
+    nickel_menu: Option<NickelMenu>,
+    /// Moves to its own thread when the ActionManager starts managing
+    player: Option<Player>,
 }
 
+/// Path segments that can't be used for actions as they're used by other endpoints
+pub const RESERVED_PATH_SEGMENTS: &[&str] = &[
+    "cancel",
+    "export",
+    "generate",
+    "history",
+    "import",
+    "recordings",
+];
+
+/// Cancels plays that were requested before it was last triggered, including one that's partway
+/// through repeating. Clones share the same state
+#[derive(Clone, Debug, Default)]
+pub struct PlayCanceller(Arc<AtomicU64>);
+
+impl PlayCanceller {
+    /// Identifies the plays that would be dropped by cancelling now
+    pub fn generation(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub fn cancel(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self, generation: u64) -> bool {
+        self.generation() != generation
+    }
+}
+
+/// The actions and their recordings as of the last change
+#[derive(Clone, Debug, Default)]
+pub struct ActionsSnapshot {
+    pub actions: BTreeMap<String, ActionOptions>,
+    pub recordings: Recordings,
+}
+
+/// A snapshot that's replaced rather than changed, so readers only hold the lock long enough to
+/// clone the Arc
+pub type SharedActions = Arc<RwLock<Arc<ActionsSnapshot>>>;
+
 impl ActionManager {
     pub fn from_path(
         actions_path: PathBuf,
         recordings_path: PathBuf,
         fbink: FbInkWrapper,
-        rx: mpsc::Receiver<ActionMsg>,
     ) -> Result<Self> {
-        Ok(Self {
-            actions: ActionsFile::load(actions_path)?,
-            recordings: RecordingsFile::load(recordings_path)?,
-            fbink,
-            rx,
-            play_wait_until: Utc::now(),
-        })
+        let actions = ActionsFile::load(actions_path)?;
+        let recordings = RecordingsFile::load(recordings_path)?;
+        let snapshot = SharedActions::default();
+        let screen: Arc<dyn ScreenSource> = Arc::new(fbink.clone());
+        let rotation: Arc<dyn RotationSource> = Arc::new(fbink);
+        let devices: Arc<dyn InputDevices> = Arc::new(EvdevDevices);
+        let locks = DeviceLocks::default();
+        let history = PlayHistory::default();
+        let player = Player::new(
+            snapshot.clone(),
+            rotation.clone(),
+            devices.clone(),
+            locks.clone(),
+            Injector::new(InjectionBackend::default()),
+            history.clone(),
+            screen,
+        );
+        let manager = Self {
+            actions,
+            recordings,
+            rotation,
+            devices,
+            snapshot,
+            locks,
+            history,
+            nickel_menu: None,
+            player: Some(player),
+        };
+        manager.publish();
+        Ok(manager)
     }
 
-    fn current_rotation(&self) -> Result<CanonicalRotation> {
-        // Use FBInk for rotation detection if possible. Otherwise read it from the framebuffer
-        // ourself.
-        if let Ok(fbink) = self.fbink.try_inner() {
-            Ok(fbink
-                .current_rotation()
-                .context("FBInk failed to read device rotation")?)
-        } else if let Ok(framebuffer) = framebuffer::Framebuffer::new("/dev/fb0") {
-            // This will be the native rotation rather than the canonical, and won't actually
-            // change when some models rotate. We'll just pretend it's the canonical rotation,
-            // so at the very least the remote functions in a single rotation on devices that
-            // can't detect it properly.
-            let rota: u8 = framebuffer
-                .var_screen_info
-                .rotate
-                .try_into()
-                .unwrap_or_default();
-            Ok(CanonicalRotation::from_primitive(rota))
-        } else {
-            Ok(CanonicalRotation::Upright)
+    fn player(&mut self) -> &mut Player {
+        self.player
+            .as_mut()
+            .expect("The player only leaves when the ActionManager starts managing")
+    }
+
+    /// Used to cancel plays that are still waiting to be handled by the ActionManager
+    pub fn play_canceller(&mut self) -> PlayCanceller {
+        self.player().canceller()
+    }
+
+    /// Merge plays of the same action that queue up while another is playing into one batch
+    pub fn set_coalesce_plays(&mut self, coalesce_plays: bool) {
+        self.player().coalesce_plays = coalesce_plays;
+    }
+
+    /// Change how the events of actions are injected when they're played
+    pub fn set_injector(&mut self, injector: Injector) {
+        self.player().injector = injector;
+    }
+
+    /// Spin rather than sleep for the last millisecond before each event is played
+    pub fn set_busy_wait(&mut self, busy_wait: bool) {
+        self.player().busy_wait = busy_wait;
+    }
+
+    /// Change how the screen is captured to verify actions had an effect
+    pub fn set_screen_source(&mut self, screen: impl ScreenSource + 'static) {
+        self.player().screen = Arc::new(screen);
+    }
+
+    /// Change how long to wait for the screen to change after playing a verified action
+    pub fn set_effect_timeout(&mut self, timeout: Duration) {
+        self.player().effect_timeout = timeout;
+    }
+
+    /// Change how the rotation of the screen is detected
+    pub fn set_rotation_source(&mut self, rotation: impl RotationSource + 'static) {
+        self.rotation = Arc::new(rotation);
+        self.player().rotation = self.rotation.clone();
+    }
+
+    /// Change where actions are recorded from and which devices generated actions are written to
+    pub fn set_input_devices(&mut self, devices: impl InputDevices + 'static) {
+        self.devices = Arc::new(devices);
+        self.player().devices = self.devices.clone();
+    }
+
+    /// Change where plays are logged
+    pub fn set_play_history(&mut self, history: PlayHistory) {
+        self.history = history.clone();
+        self.player().history = history;
+    }
+
+    /// Keep a NickelMenu config in sync with the actions
+    pub fn set_nickel_menu(&mut self, nickel_menu: NickelMenu) {
+        self.nickel_menu = Some(nickel_menu);
+        self.sync_nickel_menu();
+    }
+
+    fn sync_nickel_menu(&self) {
+        if let Some(nickel_menu) = &self.nickel_menu {
+            if let Err(e) = nickel_menu.sync(&self.list()) {
+                error!("Failed to update NickelMenu config. {e}");
+            }
+        }
+    }
+
+    /// Let the player see changes to the actions and recordings
+    fn publish(&self) {
+        let snapshot = ActionsSnapshot {
+            actions: self.actions.data.clone(),
+            recordings: self.recordings.data.clone(),
+        };
+        *self.snapshot.write().unwrap() = Arc::new(snapshot);
+    }
+
+    pub fn record(&mut self, opts: RecordActionOptions) -> Result<RecordActionResponse> {
+        let recorded = self.record_job(opts)?.run(&RecordingControl::default())?;
+        self.add_recorded(recorded)
+    }
+
+    /// Existing actions can be recorded or generated for another rotation even if their path
+    /// segment is from before they were checked
+    fn check_new_path_segment(&self, path_segment: &str) -> Result<()> {
+        if self.actions.data.contains_key(path_segment) {
+            return Ok(());
         }
+        check_path_segment(path_segment)
     }
 
-    fn record(&mut self, opts: RecordActionOptions) -> Result<RecordActionResponse> {
+    /// Check the options for a recording and prepare it to run away from the ActionManager
+    pub fn record_job(&self, opts: RecordActionOptions) -> Result<RecordJob> {
         let path_segment = opts.path_segment.clone().unwrap_or(slugify(&opts.name));
+        self.check_new_path_segment(&path_segment)?;
+        Ok(RecordJob {
+            path_segment,
+            opts,
+            rotation: self.rotation.clone(),
+            devices: self.devices.clone(),
+            locks: self.locks.clone(),
+        })
+    }
 
-        if !self.actions.data.contains_key(&path_segment) {
-            self.actions.data.insert(
-                path_segment.clone(),
-                ActionOptions {
-                    sort_value: opts.sort_value.clone().unwrap_or(opts.name.clone()),
-                    name: opts.name.clone(),
-                    keyboard_shortcut: opts.keyboard_shortcut,
-                    voice_trigger: opts.voice_trigger.clone(),
-                    post_playback_delay: opts.post_playback_delay,
-                },
-            );
+    /// Generate an action that can't easily be recorded, like a pinch, for the current rotation
+    pub fn generate(
+        &mut self,
+        opts: SyntheticActionOptions,
+        input: &InputOptions,
+    ) -> Result<RecordActionResponse> {
+        let path_segment = opts.path_segment.clone().unwrap_or(slugify(&opts.name));
+        self.check_new_path_segment(&path_segment)?;
+        let rotation = self.rotation.current_rotation()?;
+        let transform = input.touch_transform(self.rotation.native_touch_transform(), rotation);
+        let (device, events) =
+            opts.action
+                .generate(self.devices.as_ref(), opts.use_by_path, transform)?;
+        log_events(&events);
+
+        let recording = ActionRecording {
+            rotation,
+            events: create_action_events(&events),
+            dev_name: device.name,
+            fingerprint: device.fingerprint,
+            dev_path: device.path,
+            is_optimized: false,
+            resynced: false,
+        };
+        self.add_recorded(RecordedAction {
+            path_segment,
+            options: ActionOptions {
+                sort_value: opts.sort_value.clone().unwrap_or(opts.name.clone()),
+                name: opts.name,
+                keyboard_shortcut: opts.keyboard_shortcut,
+                voice_trigger: opts.voice_trigger,
+                post_playback_delay: opts.post_playback_delay,
+                speed: 1.0,
+                min_gap: None,
+                max_gap: None,
+                verify_effect: false,
+            },
+            recording,
+            rotation,
+        })
+    }
+
+    /// Save a finished recording, adding its action if it doesn't exist yet
+    pub fn add_recorded(&mut self, recorded: RecordedAction) -> Result<RecordActionResponse> {
+        self.add_action(&recorded.path_segment, recorded.options)?;
+        self.add_recording(recorded.path_segment, recorded.recording, recorded.rotation)
+    }
+
+    /// Add the options for a new action. Existing actions keep their options
+    fn add_action(&mut self, path_segment: &str, opts: ActionOptions) -> Result<()> {
+        if !self.actions.data.contains_key(path_segment) {
+            self.actions.data.insert(path_segment.to_string(), opts);
             self.actions.write()?;
+            self.publish();
+            self.sync_nickel_menu();
         }
+        Ok(())
+    }
 
+    fn add_recording(
+        &mut self,
+        path_segment: String,
+        recording: ActionRecording,
+        rotation: CanonicalRotation,
+    ) -> Result<RecordActionResponse> {
         let action = self.actions.data.get(&path_segment).unwrap();
-        let rotation = self.current_rotation()?;
-        let recording = ActionRecording::record(&opts, rotation)?;
-        if rotation != self.current_rotation()? {
-            return Err(anyhow!("The rotation changed during recording."));
-        }
         let response = RecordActionResponse {
             name: action.name.clone(),
             path_segment: path_segment.clone(),
@@ -97,70 +303,215 @@ impl ActionManager {
             keyboard_shortcut: action.keyboard_shortcut,
             rotation: rotation.to_string(),
             was_optimized: recording.is_optimized,
+            resynced: recording.resynced,
             device: recording.dev_name.clone(),
         };
         self.recordings.add(path_segment, recording, rotation)?;
-
+        self.publish();
         Ok(response)
     }
 
-    fn play(&mut self, path_segment: &str) -> Result<()> {
-        // Don't play consecutive actions immediately so the device has time to act on the input.
-        // Allows a user to spam page turns and have them all register
-        if Utc::now() < self.play_wait_until {
-            sleep(self.play_wait_until - Utc::now());
-        }
-        let rotation = self.current_rotation()?;
-        let recording = self.recordings.get(path_segment, rotation)?;
-        let opts = self.actions.data.get(path_segment).unwrap();
-        recording.play(path_segment)?;
-        self.play_wait_until = Utc::now() + opts.post_playback_delay;
-        Ok(())
+    /// Play an action, optionally overriding its speed
+    pub fn play(&mut self, path_segment: &str, speed: Option<f64>) -> Result<PlaybackDrift> {
+        self.player().play(path_segment, speed)
+    }
+
+    /// Play an action `count` times, stopping early if plays from `generation` are cancelled
+    pub fn play_repeated(
+        &mut self,
+        path_segment: &str,
+        speed: Option<f64>,
+        count: u32,
+        generation: u64,
+    ) -> Result<PlayResult> {
+        let requested = Utc::now();
+        let result = self
+            .player()
+            .play_repeated(path_segment, speed, count, generation);
+        let rotation = self.rotation.current_rotation().ok();
+        self.history.add(PlayRecord::new(
+            path_segment,
+            None,
+            requested,
+            rotation,
+            &result,
+        ));
+        result
     }
 
-    fn delete(&mut self, path_segment: &str) -> Result<()> {
+    /// Play an action repeatedly, timing how long the screen takes to change and then settle
+    pub fn benchmark(
+        &mut self,
+        path_segment: &str,
+        opts: &BenchmarkOptions,
+    ) -> Result<Vec<BenchmarkRun>> {
+        let player = self.player();
+        let generation = player.canceller().generation();
+        player.benchmark(path_segment, opts, generation)
+    }
+
+    pub fn delete(&mut self, path_segment: &str) -> Result<()> {
         if self.actions.data.remove(path_segment).is_some() {
             self.actions.write()?;
             self.recordings.data.remove(path_segment);
             self.recordings.write()?;
+            self.publish();
+            self.sync_nickel_menu();
             Ok(())
         } else {
             Err(anyhow!("No action exists for {path_segment}"))
         }
     }
 
-    fn update(&mut self, path_segment: String, opts: ActionOptions) -> Result<()> {
+    pub fn update(&mut self, path_segment: String, opts: ActionOptions) -> Result<()> {
         if !self.actions.data.contains_key(&path_segment) {
             return Err(anyhow!("{path_segment} doesn't exist"));
         }
+        opts.timing().check()?;
 
         self.actions.data.insert(path_segment, opts);
         self.actions.write()?;
+        self.publish();
+        self.sync_nickel_menu();
         Ok(())
     }
 
-    pub fn manage(&mut self) {
+    /// Change the display name and/or path segment of an existing action
+    pub fn rename(&mut self, path_segment: &str, opts: RenameActionOptions) -> Result<String> {
+        let Some(mut action) = self.actions.data.get(path_segment).cloned() else {
+            return Err(anyhow!("No action exists for {path_segment}"));
+        };
+        let new_path_segment = opts.path_segment.unwrap_or(path_segment.to_string());
+        if new_path_segment != path_segment {
+            check_path_segment(&new_path_segment)?;
+            if self.actions.data.contains_key(&new_path_segment) {
+                return Err(anyhow!("An action already exists for {new_path_segment}"));
+            }
+        }
+        if let Some(name) = opts.name {
+            action.name = name;
+        }
+        self.actions.data.remove(path_segment);
+        self.actions.data.insert(new_path_segment.clone(), action);
+        self.actions.write()?;
+        if let Some(recordings) = self.recordings.data.remove(path_segment) {
+            self.recordings
+                .data
+                .insert(new_path_segment.clone(), recordings);
+            self.recordings.write()?;
+        }
+        self.publish();
+        self.sync_nickel_menu();
+        Ok(new_path_segment)
+    }
+
+    /// Bundle all actions and their recordings so they can be imported elsewhere
+    pub fn export(&self) -> ActionsExport {
+        ActionsExport {
+            version: Config::version().to_string(),
+            actions: self.actions.data.clone(),
+            recordings: self.recordings.data.clone(),
+        }
+    }
+
+    /// Add previously exported actions. Existing actions are skipped unless `overwrite` is true.
+    /// Returns the path segments of the imported actions.
+    pub fn import(&mut self, export: ActionsExport, overwrite: bool) -> Result<Vec<String>> {
+        for path_segment in export.actions.keys() {
+            check_path_segment(path_segment)?;
+        }
+        let mut imported = Vec::new();
+        for (path_segment, opts) in export.actions {
+            if self.actions.data.contains_key(&path_segment) && !overwrite {
+                debug!("Skipped importing existing action {path_segment}");
+                continue;
+            }
+            if let Some(recordings) = export.recordings.get(&path_segment) {
+                self.recordings
+                    .data
+                    .insert(path_segment.clone(), recordings.clone());
+            } else {
+                self.recordings.data.remove(&path_segment);
+            }
+            self.actions.data.insert(path_segment.clone(), opts);
+            imported.push(path_segment);
+        }
+        if !imported.is_empty() {
+            self.actions.write()?;
+            self.recordings.write()?;
+            self.publish();
+            self.sync_nickel_menu();
+        }
+        Ok(imported)
+    }
+
+    pub fn list(&self) -> Vec<ActionDetails> {
+        let mut actions = Vec::new();
+        for (path_segment, opts) in self.actions.data.iter() {
+            actions.push(ActionDetails::new(path_segment, opts))
+        }
+        actions.sort_by(|a, b| a.sort_value.partial_cmp(&b.sort_value).unwrap());
+        actions
+    }
+
+    /// The rotations each action has been recorded in
+    pub fn coverage(&self) -> Vec<ActionCoverage> {
+        self.list()
+            .into_iter()
+            .map(|a| {
+                let rotations = self
+                    .recordings
+                    .data
+                    .get(&a.path_segment)
+                    .map(|r| r.iter().flatten().map(|r| r.rotation).collect())
+                    .unwrap_or_default();
+                ActionCoverage {
+                    name: a.name,
+                    path_segment: a.path_segment,
+                    rotations,
+                }
+            })
+            .collect()
+    }
+
+    /// Answer messages until every sender is dropped. Plays are handed to the player's thread and
+    /// recordings run in the caller, so neither holds up other messages
+    pub fn manage(mut self, mut rx: mpsc::Receiver<ActionMsg>) {
+        let (play_tx, play_rx) = std_mpsc::channel();
+        let mut player = self.player.take().unwrap();
+        thread::spawn(move || player.manage(play_rx));
         loop {
-            match self.rx.blocking_recv() {
+            match rx.blocking_recv() {
                 Some(ActionMsg::Record { opts, resp }) => {
-                    let result = self.record(opts);
+                    let result = self.record_job(opts);
                     if resp.send(result).is_err() {
-                        warn!("Unable to send Record result. Receiver dropped")
+                        warn!("Unable to send Record job. Receiver dropped")
                     }
                 }
-                Some(ActionMsg::Play { path_segment, resp }) => {
-                    let result = self.play(&path_segment);
+                Some(ActionMsg::AddRecorded { recorded, resp }) => {
+                    let result = self.add_recorded(*recorded);
                     if resp.send(result).is_err() {
-                        warn!("Unable to send Play result. Receiver dropped")
+                        warn!("Unable to send AddRecorded result. Receiver dropped")
                     }
                 }
-                Some(ActionMsg::List { resp }) => {
-                    let mut actions = Vec::new();
-                    for (path_segment, opts) in self.actions.data.iter() {
-                        actions.push(ActionDetails::new(path_segment, opts))
+                Some(ActionMsg::Generate { opts, input, resp }) => {
+                    let result = self.generate(opts, &input);
+                    if resp.send(result).is_err() {
+                        warn!("Unable to send Generate result. Receiver dropped")
                     }
-                    actions.sort_by(|a, b| a.sort_value.partial_cmp(&b.sort_value).unwrap());
-                    if resp.send(actions).is_err() {
+                }
+                Some(ActionMsg::Play(job)) => {
+                    if let Err(e) = play_tx.send(PlayerJob::Play(job)) {
+                        e.0.player_stopped();
+                    }
+                }
+                Some(ActionMsg::Benchmark(job)) => {
+                    if let Err(e) = play_tx.send(PlayerJob::Benchmark(job)) {
+                        e.0.player_stopped();
+                    }
+                }
+                Some(ActionMsg::List { resp }) => {
+                    if resp.send(self.list()).is_err() {
                         warn!("Unable to send actions list. Receiver dropped")
                     }
                 }
@@ -180,6 +531,42 @@ impl ActionManager {
                         warn!("Unable to send Update result. Receiver dropped")
                     }
                 }
+                Some(ActionMsg::Rename {
+                    path_segment,
+                    opts,
+                    resp,
+                }) => {
+                    let result = self.rename(&path_segment, opts);
+                    if resp.send(result).is_err() {
+                        warn!("Unable to send Rename result. Receiver dropped")
+                    }
+                }
+                Some(ActionMsg::Export { resp }) => {
+                    if resp.send(self.export()).is_err() {
+                        warn!("Unable to send Export result. Receiver dropped")
+                    }
+                }
+                Some(ActionMsg::Import {
+                    export,
+                    overwrite,
+                    resp,
+                }) => {
+                    let result = self.import(*export, overwrite);
+                    if resp.send(result).is_err() {
+                        warn!("Unable to send Import result. Receiver dropped")
+                    }
+                }
+                Some(ActionMsg::GetPlayHistory { resp }) => {
+                    if resp.send(self.history.entries()).is_err() {
+                        warn!("Unable to send GetPlayHistory result. Receiver dropped")
+                    }
+                }
+                Some(ActionMsg::GetCoverage { resp }) => {
+                    if resp.send(self.coverage()).is_err() {
+                        warn!("Unable to send GetCoverage result. Receiver dropped")
+                    }
+                }
+                Some(ActionMsg::SetNickelMenu(nickel_menu)) => self.set_nickel_menu(nickel_menu),
                 Some(ActionMsg::GetPageTurns { resp }) => {
                     let next = self.actions.data.get("next-page");
                     let prev = self.actions.data.get("prev-page");
@@ -205,6 +592,14 @@ impl ActionManager {
                         warn!("Unable to send GetRecording result. Receiver dropped")
                     }
                 }
+                Some(ActionMsg::GetInputDevices { resp }) => {
+                    if resp
+                        .send((self.devices.clone(), self.locks.clone()))
+                        .is_err()
+                    {
+                        warn!("Unable to send GetInputDevices result. Receiver dropped")
+                    }
+                }
                 None => break,
             }
         }
@@ -212,14 +607,24 @@ impl ActionManager {
 }
 
 pub enum ActionMsg {
+    /// Prepare a recording. The caller runs it, then sends the result back with AddRecorded
     Record {
         opts: RecordActionOptions,
+        resp: oneshot::Sender<Result<RecordJob>>,
+    },
+    AddRecorded {
+        recorded: Box<RecordedAction>,
         resp: oneshot::Sender<Result<RecordActionResponse>>,
     },
-    Play {
-        path_segment: String,
-        resp: oneshot::Sender<Result<()>>,
+    Generate {
+        opts: SyntheticActionOptions,
+        /// The user's arbitrary input options, for any touchscreen calibration
+        input: InputOptions,
+        resp: oneshot::Sender<Result<RecordActionResponse>>,
     },
+    Play(PlayJob),
+    /// Play an action repeatedly on the player's thread, timing how the screen responds
+    Benchmark(BenchmarkJob),
     List {
         resp: oneshot::Sender<Vec<ActionDetails>>,
     },
@@ -232,6 +637,26 @@ pub enum ActionMsg {
         opts: ActionOptions,
         resp: oneshot::Sender<Result<()>>,
     },
+    Rename {
+        path_segment: String,
+        opts: RenameActionOptions,
+        resp: oneshot::Sender<Result<String>>,
+    },
+    Export {
+        resp: oneshot::Sender<ActionsExport>,
+    },
+    Import {
+        export: Box<ActionsExport>,
+        overwrite: bool,
+        resp: oneshot::Sender<Result<Vec<String>>>,
+    },
+    GetCoverage {
+        resp: oneshot::Sender<Vec<ActionCoverage>>,
+    },
+    GetPlayHistory {
+        resp: oneshot::Sender<Vec<PlayRecord>>,
+    },
+    SetNickelMenu(NickelMenu),
     GetPageTurns {
         resp: oneshot::Sender<PageTurnActions>,
     },
@@ -240,6 +665,77 @@ pub enum ActionMsg {
         rotation: Option<CanonicalRotation>,
         resp: oneshot::Sender<Result<ActionRecording>>,
     },
+    /// The input devices actions are recorded from, for reading touches outside a recording, and
+    /// the locks that keep plays off them while they're read
+    GetInputDevices {
+        resp: oneshot::Sender<(Arc<dyn InputDevices>, DeviceLocks)>,
+    },
+}
+
+/// A recording that reads from input devices without involving the ActionManager, so it can run
+/// on another thread. Plays to the same devices wait until it's finished
+pub struct RecordJob {
+    path_segment: String,
+    opts: RecordActionOptions,
+    rotation: Arc<dyn RotationSource>,
+    devices: Arc<dyn InputDevices>,
+    locks: DeviceLocks,
+}
+
+impl RecordJob {
+    pub fn run(self, control: &RecordingControl) -> Result<RecordedAction> {
+        let _guard = self.locks.lock(self.devices.paths(&self.opts)?);
+        let rotation = self.rotation.current_rotation()?;
+        let recording =
+            ActionRecording::record(&self.opts, rotation, self.devices.as_ref(), control)?;
+        if rotation != self.rotation.current_rotation()? {
+            return Err(anyhow!("The rotation changed during recording."));
+        }
+        let opts = self.opts;
+        Ok(RecordedAction {
+            path_segment: self.path_segment,
+            options: ActionOptions {
+                sort_value: opts.sort_value.unwrap_or(opts.name.clone()),
+                name: opts.name,
+                keyboard_shortcut: opts.keyboard_shortcut,
+                voice_trigger: opts.voice_trigger,
+                post_playback_delay: opts.post_playback_delay,
+                speed: 1.0,
+                min_gap: None,
+                max_gap: None,
+                verify_effect: false,
+            },
+            recording,
+            rotation,
+        })
+    }
+}
+
+/// A recording that's ready to be saved, along with the options to use if its action is new
+pub struct RecordedAction {
+    pub path_segment: String,
+    pub options: ActionOptions,
+    pub recording: ActionRecording,
+    pub rotation: CanonicalRotation,
+}
+
+/// Path segments are used unencoded in URLs and NickelMenu commands, so they're limited to what
+/// slugify produces: lowercase letters, numbers and hyphens
+fn check_path_segment(path_segment: &str) -> Result<()> {
+    if path_segment.is_empty() {
+        Err(anyhow!("The path segment can't be empty"))
+    } else if slugify(path_segment) != path_segment {
+        Err(anyhow!(
+            "{path_segment:?} isn't a valid path segment. Use only lowercase letters, numbers and single hyphens, e.g. {}",
+            slugify(path_segment)
+        ))
+    } else if RESERVED_PATH_SEGMENTS.contains(&path_segment) {
+        Err(anyhow!(
+            "{path_segment} is reserved and can't be used for an action"
+        ))
+    } else {
+        Ok(())
+    }
 }
 
 pub struct PageTurnActions {
@@ -255,6 +751,7 @@ pub struct RecordActionResponse {
     pub keyboard_shortcut: Option<keyboard_types::Code>,
     pub rotation: String,
     pub was_optimized: bool,
+    pub resynced: bool,
     pub device: String,
 }
 
@@ -267,6 +764,49 @@ pub struct ActionOptions {
     #[serde_as(as = "DurationMilliSeconds<i64>")]
     pub post_playback_delay: Duration,
     pub voice_trigger: Option<String>,
+    /// How many times faster than recorded to play the action, e.g. 2 to halve the gaps between
+    /// events
+    #[serde(default = "default_speed")]
+    pub speed: f64,
+    /// The shortest gap between events after scaling by `speed`
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationMilliSeconds<i64>>")]
+    pub min_gap: Option<Duration>,
+    /// The longest gap between events after scaling by `speed`
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationMilliSeconds<i64>>")]
+    pub max_gap: Option<Duration>,
+    /// Check the screen changed after playing, and play again once if it didn't
+    #[serde(default)]
+    pub verify_effect: bool,
+}
+
+fn default_speed() -> f64 {
+    1.0
+}
+
+impl ActionOptions {
+    pub fn timing(&self) -> PlaybackTiming {
+        PlaybackTiming {
+            speed: self.speed,
+            min_gap: self.min_gap,
+            max_gap: self.max_gap,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RenameActionOptions {
+    pub name: Option<String>,
+    pub path_segment: Option<String>,
+}
+
+/// All actions and their recordings, used to transfer them between devices or installations
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ActionsExport {
+    pub version: String,
+    pub actions: BTreeMap<String, ActionOptions>,
+    pub recordings: BTreeMap<String, [Option<ActionRecording>; 4]>,
 }
 
 #[serde_with::serde_as]
@@ -279,6 +819,12 @@ pub struct ActionDetails {
     #[serde_as(as = "DurationMilliSeconds<i64>")]
     pub post_playback_delay: Duration,
     pub voice_trigger: Option<String>,
+    pub speed: f64,
+    #[serde_as(as = "Option<DurationMilliSeconds<i64>>")]
+    pub min_gap: Option<Duration>,
+    #[serde_as(as = "Option<DurationMilliSeconds<i64>>")]
+    pub max_gap: Option<Duration>,
+    pub verify_effect: bool,
 }
 
 impl ActionDetails {
@@ -290,6 +836,10 @@ impl ActionDetails {
             keyboard_shortcut: opts.keyboard_shortcut,
             post_playback_delay: opts.post_playback_delay,
             voice_trigger: opts.voice_trigger.clone(),
+            speed: opts.speed,
+            min_gap: opts.min_gap,
+            max_gap: opts.max_gap,
+            verify_effect: opts.verify_effect,
         }
     }
     pub fn shortcut_name(&self) -> String {
@@ -298,6 +848,13 @@ impl ActionDetails {
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ActionCoverage {
+    pub name: String,
+    pub path_segment: String,
+    pub rotations: Vec<CanonicalRotation>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ActionRecording {
     pub rotation: CanonicalRotation,
@@ -305,49 +862,98 @@ pub struct ActionRecording {
     pub dev_path: PathBuf,
     pub dev_name: String,
     pub is_optimized: bool,
+    /// Events were dropped during recording, but enough of the action was recovered to use it
+    #[serde(default)]
+    pub resynced: bool,
+    /// Used to find the device if its path changes. Recordings made by older versions have none
+    #[serde(default)]
+    pub fingerprint: Option<DeviceFingerprint>,
+}
+
+/// The layout of [ActionRecording] in 0.3.x, before the recordings file was versioned
+#[derive(Deserialize)]
+struct LegacyActionRecording {
+    rotation: CanonicalRotation,
+    events: Vec<ActionEvent>,
+    dev_path: PathBuf,
+    dev_name: String,
+    is_optimized: bool,
+}
+
+impl From<LegacyActionRecording> for ActionRecording {
+    fn from(r: LegacyActionRecording) -> Self {
+        Self {
+            rotation: r.rotation,
+            events: r.events,
+            dev_path: r.dev_path,
+            dev_name: r.dev_name,
+            is_optimized: r.is_optimized,
+            resynced: false,
+            fingerprint: None,
+        }
+    }
+}
+
+/// The layout of [ActionRecording] in version 1 of the recordings file
+#[derive(Deserialize)]
+struct ActionRecordingV1 {
+    rotation: CanonicalRotation,
+    events: Vec<ActionEvent>,
+    dev_path: PathBuf,
+    dev_name: String,
+    is_optimized: bool,
+    resynced: bool,
+}
+
+impl From<ActionRecordingV1> for ActionRecording {
+    fn from(r: ActionRecordingV1) -> Self {
+        Self {
+            rotation: r.rotation,
+            events: r.events,
+            dev_path: r.dev_path,
+            dev_name: r.dev_name,
+            is_optimized: r.is_optimized,
+            resynced: r.resynced,
+            fingerprint: None,
+        }
+    }
 }
 
 impl ActionRecording {
     pub fn record(
         opts: &RecordActionOptions,
         rotation: CanonicalRotation,
+        devices: &dyn InputDevices,
+        control: &RecordingControl,
     ) -> Result<ActionRecording> {
-        let devices = get_input_devices(opts.use_by_path)?;
-
-        let devices_with_events = if opts.only_check_touch {
-            read_input(
-                devices.into_iter().filter(is_touch_device),
-                opts.poll_wait,
-                opts.no_input_timeout,
-                opts.new_event_timeout,
-            )?
-        } else {
-            read_input(
-                devices.into_iter(),
-                opts.poll_wait,
-                opts.no_input_timeout,
-                opts.new_event_timeout,
-            )?
-        };
+        let devices_with_events = devices.read(opts, control)?;
+        if control.is_aborted() {
+            return Err(anyhow!("The recording was aborted"));
+        }
 
         if devices_with_events.is_empty() {
             return Err(anyhow!("No input detected"));
         }
         for (d, e) in &devices_with_events {
-            debug!("Input detected on {d} ({} events)", e.len());
+            debug!("Input detected on {d} ({} events)", e.events.len());
         }
 
-        let (device, mut events) = if devices_with_events.len() > 1 {
+        let (device, events) = if devices_with_events.len() > 1 {
             // TODO: It's unlikely that there will ever be multiple devices with events detected,
             // but the ideal way to handle this would be to enable the user to select which device
             // they intended to record
             devices_with_events
                 .into_iter()
-                .max_by_key(|(_d, e)| e.len())
+                .max_by_key(|(_d, e)| e.events.len())
                 .unwrap()
         } else {
             devices_with_events.into_iter().next().unwrap()
         };
+        events.check_resync()?;
+        let DeviceEvents {
+            mut events,
+            resynced,
+        } = events;
 
         log_events(&events);
         let is_optimized = if opts.optimize {
@@ -359,42 +965,155 @@ impl ActionRecording {
             log_events(&events);
         }
 
-        let dev_name = device.to_string();
         Ok(ActionRecording {
+            fingerprint: device.fingerprint,
             dev_path: device.path,
             events: create_action_events(&events),
             rotation,
-            dev_name,
+            dev_name: device.name,
             is_optimized,
+            resynced,
         })
     }
 
-    pub fn open_device(&self) -> Result<File> {
-        File::options()
-            .read(true)
-            .write(true)
-            .open(&self.dev_path)
-            .with_context(|| format!("Failed to open {} to write events", self.dev_path.display()))
-    }
-
-    pub fn play(&self, path_segment: &str) -> Result<()> {
-        let mut f = self.open_device()?;
-        debug!(
-            "Writing events for {} to {}",
-            path_segment,
-            &self.dev_path.display()
-        );
+    /// Write the events to `sink` at the same times relative to the first event as they were
+    /// recorded, with the gaps between them adjusted by `timing`. Each event has a deadline from
+    /// the start of playback rather than a sleep after the previous event, so time spent writing
+    /// and oversleeping doesn't accumulate.
+    pub fn play(
+        &self,
+        path_segment: &str,
+        sink: &mut dyn EventSink,
+        busy_wait: bool,
+        timing: &PlaybackTiming,
+    ) -> Result<PlaybackDrift> {
+        let mut offsets = Vec::with_capacity(self.events.len());
+        let mut previous = None;
+        let mut offset = Duration::zero();
         for ev in &self.events {
-            f.write_all(&ev.buf)
-                .with_context(|| format!("Failed to write event to {}", self.dev_path.display()))?;
-            if let Some(dur) = ev.sleep_duration {
-                sleep(dur);
+            let recorded = ev.offset()?;
+            if let Some(previous) = previous {
+                offset += timing.gap(recorded - previous);
             }
+            previous = Some(recorded);
+            offsets.push(offset);
+        }
+        let mut drift = PlaybackDrift::default();
+        let mut total_drift = Duration::zero();
+
+        debug!("Writing events for {}", path_segment);
+        let start = Instant::now();
+        for (ev, offset) in self.events.iter().zip(offsets) {
+            let deadline = start + offset.to_std().unwrap_or_default();
+            sleep_until(deadline, busy_wait);
+            sink.write_event(ev)?;
+            let late = Duration::from_std(Instant::now().saturating_duration_since(deadline))?;
+            total_drift += late;
+            drift.max = drift.max.max(late);
+            drift.end = late;
         }
         debug!("Finished writing events for {}", path_segment);
 
+        if !self.events.is_empty() {
+            drift.mean = total_drift / self.events.len() as i32;
+        }
+        debug!(
+            "Playback drift: max {}us, mean {}us, end {}us",
+            drift.max.num_microseconds().unwrap_or_default(),
+            drift.mean.num_microseconds().unwrap_or_default(),
+            drift.end.num_microseconds().unwrap_or_default(),
+        );
+        Ok(drift)
+    }
+}
+
+/// The slowest and fastest an action can be played relative to how it was recorded
+const MIN_SPEED: f64 = 0.1;
+const MAX_SPEED: f64 = 10.0;
+
+/// Changes to the recorded gaps between events when playing an action
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlaybackTiming {
+    /// How many times faster than recorded to play the action, from [MIN_SPEED] to [MAX_SPEED]
+    pub speed: f64,
+    pub min_gap: Option<Duration>,
+    pub max_gap: Option<Duration>,
+}
+
+impl Default for PlaybackTiming {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            min_gap: None,
+            max_gap: None,
+        }
+    }
+}
+
+impl PlaybackTiming {
+    pub fn check(&self) -> Result<()> {
+        if !(MIN_SPEED..=MAX_SPEED).contains(&self.speed) {
+            return Err(anyhow!(
+                "The speed must be between {MIN_SPEED} and {MAX_SPEED}"
+            ));
+        }
+        if let (Some(min), Some(max)) = (self.min_gap, self.max_gap) {
+            if min > max {
+                return Err(anyhow!(
+                    "The minimum gap can't be longer than the maximum gap"
+                ));
+            }
+        }
         Ok(())
     }
+
+    /// Scale and clamp a recorded gap between events. Events recorded at the same time are part of
+    /// the same batch, so are always written together
+    pub fn gap(&self, recorded: Duration) -> Duration {
+        if recorded <= Duration::zero() {
+            return Duration::zero();
+        }
+        let micros = recorded.num_microseconds().unwrap_or(i64::MAX) as f64 / self.speed;
+        let mut gap = Duration::microseconds(micros.round() as i64);
+        if let Some(min) = self.min_gap {
+            gap = gap.max(min);
+        }
+        if let Some(max) = self.max_gap {
+            gap = gap.min(max);
+        }
+        gap
+    }
+}
+
+/// The result of playing an action one or more times
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlayResult {
+    /// How many times the action was played. Less than requested if the plays were cancelled
+    pub played: u32,
+    /// The name of the device the last play was written to
+    pub device: Option<String>,
+    /// How long the plays took, not counting any wait before or between them
+    #[serde_as(as = "DurationMilliSeconds<i64>")]
+    pub time_taken: Duration,
+    /// The drift of the last play
+    pub drift: PlaybackDrift,
+    /// Whether the screen changed after the last play. None if the action isn't verified or the
+    /// screen couldn't be captured
+    pub effect: Option<bool>,
+}
+
+/// How late events were written compared to when they were recorded, relative to the first event
+#[serde_with::serde_as]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlaybackDrift {
+    #[serde_as(as = "DurationMicroSeconds<i64>")]
+    pub max: Duration,
+    #[serde_as(as = "DurationMicroSeconds<i64>")]
+    pub mean: Duration,
+    /// How late the last event was, i.e. how much longer playback took than the recording
+    #[serde_as(as = "DurationMicroSeconds<i64>")]
+    pub end: Duration,
 }
 
 fn log_events(events: &[InputEvent]) {
@@ -416,6 +1135,9 @@ pub struct RecordActionOptions {
     pub keyboard_shortcut: Option<keyboard_types::Code>,
     pub voice_trigger: Option<String>,
     pub only_check_touch: bool,
+    /// Only record physical buttons, like page turn or power buttons. Takes precedence over
+    /// `only_check_touch`
+    pub only_check_keys: bool,
     pub optimize: bool,
     pub use_by_path: bool,
     #[serde_as(as = "DurationMilliSeconds<i64>")]
@@ -428,8 +1150,11 @@ pub struct RecordActionOptions {
     pub no_input_timeout: Duration,
     #[serde_as(as = "DurationMilliSeconds<i64>")]
     pub new_event_timeout: Duration,
+    /// Deprecated and ignored. Input is now read as soon as it arrives
     #[serde_as(as = "DurationMilliSeconds<i64>")]
     pub poll_wait: Duration,
+    /// Grab the input devices while recording so the input isn't also delivered to the reader
+    pub exclusive_grab: bool,
 }
 
 impl Default for RecordActionOptions {
@@ -441,6 +1166,7 @@ impl Default for RecordActionOptions {
             keyboard_shortcut: None,
             voice_trigger: None,
             only_check_touch: true,
+            only_check_keys: false,
             optimize: false,
             use_by_path: false,
             optimize_max_duration: Duration::milliseconds(1000),
@@ -449,6 +1175,7 @@ impl Default for RecordActionOptions {
             no_input_timeout: Duration::milliseconds(5000),
             new_event_timeout: Duration::milliseconds(4000),
             poll_wait: Duration::milliseconds(10),
+            exclusive_grab: false,
         }
     }
 }
@@ -459,7 +1186,8 @@ impl Default for RecordActionOptions {
 pub struct ActionEvent {
     /// native endian bytes ready to write to the input device
     pub buf: Vec<u8>,
-    /// How long to sleep for after writing the input event
+    /// How long to sleep for after writing the input event. Playback now waits for each event's
+    /// own time instead, but this is kept so the layout of the recordings file doesn't change
     #[serde_as(as = "Option<DurationMicroSeconds<i64>>")]
     pub sleep_duration: Option<Duration>,
 }
@@ -505,6 +1233,11 @@ impl ActionEvent {
         Ok(InputEvent::from_raw(&raw))
     }
 
+    /// The time of the event relative to the start of the recording
+    pub fn offset(&self) -> Result<Duration> {
+        Ok(parse_timeval(self.input_event()?.time) - DateTime::UNIX_EPOCH)
+    }
+
     pub fn set_value(&mut self, value: i32) {
         let mut buf = self.buf.clone();
         buf.truncate(buf.len() - 4);
@@ -525,7 +1258,7 @@ impl ActionEvent {
 }
 
 #[allow(deprecated)]
-fn create_action_events(events: &[InputEvent]) -> Vec<ActionEvent> {
+pub(crate) fn create_action_events(events: &[InputEvent]) -> Vec<ActionEvent> {
     let mut action_events = Vec::new();
     if events.is_empty() {
         return action_events;
@@ -615,9 +1348,35 @@ impl ActionsFile {
     }
 }
 
+/// Written at the start of the recordings file, followed by the version of its layout. Bincode
+/// isn't self-describing, so this lets us convert recordings made by older versions.
+const RECORDINGS_MAGIC: &[u8; 4] = b"WFRR";
+const RECORDINGS_VERSION: u32 = 2;
+
+pub type Recordings = BTreeMap<String, [Option<ActionRecording>; 4]>;
+
+/// The recording of an action for a rotation
+pub fn get_recording<'a>(
+    recordings: &'a Recordings,
+    path_segment: &str,
+    rotation: CanonicalRotation,
+) -> Result<&'a ActionRecording> {
+    let Some(recordings) = recordings.get(path_segment) else {
+        return Err(anyhow!(
+            "No recording for {path_segment} in {rotation} rotation"
+        ));
+    };
+    match recordings[rotation as usize] {
+        Some(ref recording) => Ok(recording),
+        None => Err(anyhow!(
+            "No recording for {path_segment} in {rotation} rotation"
+        )),
+    }
+}
+
 pub struct RecordingsFile {
     pub path: PathBuf,
-    pub data: BTreeMap<String, [Option<ActionRecording>; 4]>,
+    pub data: Recordings,
 }
 
 impl RecordingsFile {
@@ -626,7 +1385,7 @@ impl RecordingsFile {
             debug!("Loading recordings from {}", path.display());
             let bytes = fs::read(&path)
                 .with_context(|| format!("Failed to read recordings from {}", &path.display()))?;
-            bincode::deserialize(&bytes).with_context(|| {
+            Self::deserialize(&bytes).with_context(|| {
                 format!("Failed to deserialize recordings from {}", &path.display())
             })?
         } else {
@@ -635,8 +1394,44 @@ impl RecordingsFile {
         };
         Ok(Self { path, data })
     }
+
+    fn deserialize(bytes: &[u8]) -> Result<Recordings> {
+        let Some(data) = bytes.strip_prefix(RECORDINGS_MAGIC) else {
+            debug!("Converting recordings from 0.3.x");
+            let legacy: BTreeMap<String, [Option<LegacyActionRecording>; 4]> =
+                bincode::deserialize(bytes)?;
+            return Ok(legacy
+                .into_iter()
+                .map(|(k, v)| (k, v.map(|r| r.map(ActionRecording::from))))
+                .collect());
+        };
+        if data.len() < 4 {
+            return Err(anyhow!("Recordings file is missing its version"));
+        }
+        let (version, data) = data.split_at(4);
+        let version = u32::from_le_bytes(version.try_into()?);
+        match version {
+            1 => {
+                debug!("Converting recordings from version 1");
+                let v1: BTreeMap<String, [Option<ActionRecordingV1>; 4]> =
+                    bincode::deserialize(data)?;
+                Ok(v1
+                    .into_iter()
+                    .map(|(k, v)| (k, v.map(|r| r.map(ActionRecording::from))))
+                    .collect())
+            }
+            RECORDINGS_VERSION => Ok(bincode::deserialize(data)?),
+            _ => Err(anyhow!(
+                "Unsupported recordings version {version}. Expected {RECORDINGS_VERSION}"
+            )),
+        }
+    }
+
     pub fn write(&self) -> Result<()> {
-        let bytes = bincode::serialize(&self.data).context("Failed to serialize recordings")?;
+        let mut bytes = RECORDINGS_MAGIC.to_vec();
+        bytes.extend(RECORDINGS_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut bytes, &self.data)
+            .context("Failed to serialize recordings")?;
         if self.path.exists() {
             fs::copy(&self.path, self.path.with_extension("bin.bkp"))
                 .context("Failed to backup recordings file")?;
@@ -662,17 +1457,7 @@ impl RecordingsFile {
     }
 
     pub fn get(&self, path_segment: &str, rotation: CanonicalRotation) -> Result<&ActionRecording> {
-        let Some(recordings) = self.data.get(path_segment) else {
-            return Err(anyhow!(
-                "No recording for {path_segment} in {rotation} rotation"
-            ));
-        };
-        match recordings[rotation as usize] {
-            Some(ref recording) => Ok(recording),
-            None => Err(anyhow!(
-                "No recording for {path_segment} in {rotation} rotation"
-            )),
-        }
+        get_recording(&self.data, path_segment, rotation)
     }
 
     pub fn add(
@@ -687,3 +1472,91 @@ impl RecordingsFile {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::PlaybackTiming;
+    use crate::actions::inject::MemorySink;
+    use crate::fakes::fake_recording;
+    use chrono::Duration;
+    use evdev_rs::enums::EventCode::{EV_ABS, EV_SYN};
+    use evdev_rs::enums::EV_ABS::ABS_X;
+    use evdev_rs::enums::EV_SYN::SYN_REPORT;
+    use evdev_rs::{InputEvent, TimeVal};
+    use pretty_assertions::assert_eq;
+    use std::time::Instant;
+
+    #[test]
+    fn play_waits_until_recorded_times() {
+        let event = |tv_usec, event_code, value| InputEvent {
+            time: TimeVal {
+                tv_sec: 1705963814,
+                tv_usec,
+            },
+            event_code,
+            value,
+        };
+        let events = vec![
+            event(0, EV_ABS(ABS_X), 522),
+            event(0, EV_SYN(SYN_REPORT), 0),
+            event(20_000, EV_ABS(ABS_X), 600),
+            event(20_000, EV_SYN(SYN_REPORT), 0),
+            event(40_000, EV_ABS(ABS_X), 700),
+            event(40_000, EV_SYN(SYN_REPORT), 0),
+        ];
+        let recording = fake_recording(&events);
+        let memory = MemorySink::default();
+        let mut sink = memory.clone();
+        let start = Instant::now();
+        let drift = recording
+            .play("test", &mut sink, true, &PlaybackTiming::default())
+            .unwrap();
+        let elapsed = start.elapsed();
+
+        assert_eq!(6, memory.take().len());
+        assert!(elapsed >= std::time::Duration::from_millis(40));
+        assert!(drift.max >= drift.mean);
+        assert!(drift.end <= drift.max);
+    }
+
+    #[test]
+    fn playback_timing_gaps() {
+        let ms = Duration::milliseconds;
+        let timing = PlaybackTiming {
+            speed: 2.0,
+            min_gap: Some(ms(5)),
+            max_gap: Some(ms(50)),
+        };
+        assert_eq!(Duration::zero(), timing.gap(Duration::zero()));
+        assert_eq!(ms(5), timing.gap(ms(4)));
+        assert_eq!(ms(20), timing.gap(ms(40)));
+        assert_eq!(ms(50), timing.gap(ms(1000)));
+
+        let slower = PlaybackTiming {
+            speed: 0.5,
+            ..Default::default()
+        };
+        assert_eq!(ms(80), slower.gap(ms(40)));
+        for speed in [0.0, 0.05, 10.5, f64::INFINITY, f64::NAN] {
+            let timing = PlaybackTiming {
+                speed,
+                ..Default::default()
+            };
+            assert!(timing.check().is_err(), "{speed}");
+        }
+        for speed in [0.1, 10.0] {
+            let timing = PlaybackTiming {
+                speed,
+                ..Default::default()
+            };
+            assert!(timing.check().is_ok(), "{speed}");
+        }
+
+        let inverted = PlaybackTiming {
+            min_gap: Some(ms(50)),
+            max_gap: Some(ms(5)),
+            ..Default::default()
+        };
+        assert!(inverted.check().is_err());
+    }
+}
@@ -1,12 +1,16 @@
 //! Handles performing input and printing a cursor at arbitrary locations on the screen
+use super::devices::{EvdevDevices, InputDevices};
+use super::inject::{EventSink, Injector};
 use super::input::{is_x_coord, is_y_coord};
 use super::{ActionEvent, ActionRecording};
 use crate::config::Config;
+use crate::errors::AppError;
+use crate::fbink::Screen;
 use crate::server::AppState;
 use crate::util::sleep;
 
+use std::cell::RefCell;
 use std::fmt::Display;
-use std::io::Write;
 use std::ops::ControlFlow;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -17,9 +21,9 @@ use anyhow::{anyhow, Context, Result};
 use axum::{
     extract::ws::{WebSocket, WebSocketUpgrade},
     extract::State,
-    response::Response,
-    routing::get,
-    Router,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
 };
 
 use chrono::{DateTime, Duration, Utc};
@@ -49,6 +53,8 @@ pub struct InputOptions {
     pub swap_axes_override: bool,
     pub mirror_x_override: bool,
     pub mirror_y_override: bool,
+    /// Transforms found by calibrating in each rotation. Used instead of the overrides
+    pub calibration: TouchCalibration,
 }
 
 impl Default for InputOptions {
@@ -66,10 +72,34 @@ impl Default for InputOptions {
             swap_axes_override: true,
             mirror_x_override: true,
             mirror_y_override: false,
+            calibration: TouchCalibration::default(),
         }
     }
 }
 
+impl InputOptions {
+    /// How to translate canonical coordinates to the touchscreen's in `rota`, given how the
+    /// touchscreen is transformed in its native rotation
+    pub fn touch_transform(
+        &self,
+        native: TouchTransform,
+        rota: CanonicalRotation,
+    ) -> TouchTransform {
+        if let Some(transform) = self.calibration.get(rota) {
+            return transform;
+        }
+        // Allow users to override the computed values in case they're wrong
+        if self.use_overrides {
+            return TouchTransform {
+                swap_axes: self.swap_axes_override,
+                mirror_x: self.mirror_x_override,
+                mirror_y: self.mirror_y_override,
+            };
+        }
+        native.rotated(rota)
+    }
+}
+
 #[serde_with::serde_as]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ClientInputOptions {
@@ -123,16 +153,16 @@ impl Default for ClientInputOptions {
 /// an input device and sends CursorMsgs to the CursorManager
 pub struct InputManager {
     opts: InputOptions,
-    template: ActionRecording,
-    fbink: Arc<FbInk>,
+    /// Where to write events. Only borrowed mutably while writing a batch
+    sink: RefCell<Box<dyn EventSink>>,
+    screen: Arc<dyn Screen>,
     rota: CanonicalRotation,
     start_events: Vec<ActionEvent>,
     move_events: Vec<ActionEvent>,
     stop_events: Vec<ActionEvent>,
     start_time: Option<DateTime<Utc>>,
-    swap_axes: bool,
-    mirror_x: bool,
-    mirror_y: bool,
+    /// How the touchscreen is transformed in its native rotation
+    native_transform: TouchTransform,
     screen_width: u32,
     screen_height: u32,
     cursor_x_max: f64,
@@ -149,6 +179,8 @@ pub enum InputMsg {
     StopInput(Option<Coord>),
     MoveAbsolute(Coord),
     MoveRelative(Coord),
+    /// Press and release at a point on a screenshot, without the cursor
+    Tap(ScreenTap),
     Reinit,
     ClientConnect,
     ClientDisconnect,
@@ -166,13 +198,13 @@ pub type InputSender = tokio::sync::mpsc::Sender<InputMsgWrapper>;
 type CursorSender = std::sync::mpsc::Sender<CursorMsg>;
 type CursorReceiver = std::sync::mpsc::Receiver<CursorMsg>;
 
-/// Manages drawing a cursor on the screen with FBInk
+/// Manages drawing a cursor on the screen
 pub struct CursorManager {
     cursor_min_refresh: Duration,
     current_coord: Option<Coord>,
     min_change: i32,
     last_draw: Option<LastDraw>,
-    fbink: Arc<FbInk>,
+    canvas: Box<dyn CursorCanvas>,
     cursor: DynamicImage,
     rx: CursorReceiver,
 }
@@ -196,7 +228,8 @@ pub enum ClientMsg {
 impl InputManager {
     pub fn new(
         template: ActionRecording,
-        fbink: Arc<FbInk>,
+        screen: Arc<dyn Screen>,
+        injector: &Injector,
         config: Config,
         rx: InputReceiver,
     ) -> Result<Self> {
@@ -205,9 +238,9 @@ impl InputManager {
                 "An optimized recording is required to use as a template"
             ));
         }
-        let state = fbink.state();
-
-        let rota = state.canonical_rotation();
+        let info = screen.refresh()?;
+        let dev_path = EvdevDevices.resolve(template.fingerprint.as_ref(), &template.dev_path)?;
+        let sink = RefCell::new(injector.sink(&dev_path)?);
         let mut iter = template.events.iter();
         let start_events = get_event_batch(&mut iter)?;
         let move_events = get_event_batch(&mut iter)?;
@@ -217,20 +250,18 @@ impl InputManager {
         let opts = config.user.arbitrary_input;
         Ok(Self {
             opts,
-            template,
-            rota,
-            fbink,
+            sink,
+            rota: info.rotation,
+            screen,
             start_events,
             move_events,
             stop_events,
             start_time: None,
-            swap_axes: state.touch_swap_axes,
-            mirror_x: state.touch_mirror_x,
-            mirror_y: state.touch_mirror_y,
-            screen_width: state.screen_width,
-            screen_height: state.screen_height,
-            cursor_x_max: (state.screen_width - cursor.width()) as f64,
-            cursor_y_max: (state.screen_height - cursor.height()) as f64,
+            native_transform: info.native_touch,
+            screen_width: info.width,
+            screen_height: info.height,
+            cursor_x_max: (info.width - cursor.width()) as f64,
+            cursor_y_max: (info.height - cursor.height()) as f64,
             current_coord,
             cursor,
             tx: None,
@@ -264,10 +295,15 @@ impl InputManager {
 
     fn start_cursor_manager(&mut self) -> Result<()> {
         let (tx, rx) = std::sync::mpsc::channel();
-        let mut cursor =
-            CursorManager::new(self.fbink.clone(), self.cursor.clone(), rx, &self.opts);
+        let screen = self.screen.clone();
+        let cursor = self.cursor.clone();
+        let opts = self.opts.clone();
         self.tx = Some(tx);
-        spawn(move || cursor.manage());
+        // FBInk dumps might not be Send, so the canvas is created on the cursor's thread
+        spawn(move || match screen.canvas() {
+            Ok(canvas) => CursorManager::new(canvas, cursor, rx, &opts).manage(),
+            Err(e) => error!("Failed to start CursorManager. {e:#}"),
+        });
         let start = self.get_coord(None);
         self.send(CursorMsg::Draw(start))?;
         Ok(())
@@ -295,6 +331,7 @@ impl InputManager {
             StopInput(coord) => self.input_stop(coord)?,
             MoveAbsolute(coord) => self.input_move_abs(coord)?,
             MoveRelative(coord) => self.input_move_rel(coord)?,
+            Tap(tap) => self.tap(tap)?,
             Reinit => self.reinit()?,
             ClientDisconnect => self.stop_cursor_manager()?,
             Shutdown => {
@@ -306,8 +343,7 @@ impl InputManager {
     }
 
     fn write_events(&self, events: &[ActionEvent], change_time: bool, coord: &Coord) -> Result<()> {
-        let p = self.template.dev_path.display();
-        let mut f = self.template.open_device()?;
+        let mut sink = self.sink.borrow_mut();
 
         for action_event in events {
             let mut ae = action_event.clone();
@@ -326,8 +362,7 @@ impl InputManager {
             } else if is_y_coord(&ie) {
                 ae.set_value(coord.y as i32);
             };
-            f.write_all(&ae.buf)
-                .with_context(|| format!("Failed to write event to {p}"))?;
+            sink.write_event(&ae)?;
         }
         Ok(())
     }
@@ -352,9 +387,25 @@ impl InputManager {
         self.send(CursorMsg::Hide)?;
         let mut coord = self.get_coord(coord);
         self.translate_coord(&mut coord);
+        self.press(&coord)
+    }
 
+    /// Start touching at native coordinates
+    fn press(&mut self, coord: &Coord) -> Result<()> {
         self.start_time = Some(Utc::now());
-        self.write_events(&self.start_events, false, &coord)?;
+        self.write_events(&self.start_events, false, coord)?;
+        Ok(())
+    }
+
+    /// Stop touching at native coordinates
+    fn release(&mut self, coord: &Coord) -> Result<()> {
+        let coord = Coord {
+            x: coord.x + 1.0,
+            y: coord.y + 1.0,
+        };
+        self.write_events(&self.move_events, true, &coord)?;
+        self.write_events(&self.stop_events, true, &coord)?;
+        self.start_time = None;
         Ok(())
     }
 
@@ -381,11 +432,7 @@ impl InputManager {
     fn input_stop(&mut self, coord: Option<Coord>) -> Result<()> {
         let mut coord = self.get_coord(coord);
         self.translate_coord(&mut coord);
-        coord.x += 1.0;
-        coord.y += 1.0;
-        self.write_events(&self.move_events, true, &coord)?;
-        self.write_events(&self.stop_events, true, &coord)?;
-        self.start_time = None;
+        self.release(&coord)?;
         if self.opts.reload_background_after_input {
             sleep(self.opts.reload_background_delay);
             self.send(CursorMsg::ReloadBackground)?;
@@ -393,6 +440,23 @@ impl InputManager {
         Ok(())
     }
 
+    fn tap(&mut self, tap: ScreenTap) -> Result<()> {
+        // The screenshot was taken in the current rotation, which might have changed since the
+        // InputManager started
+        self.reinit_screen()?;
+        let mut coord = tap.coord(self.screen_width, self.screen_height);
+        self.translate_coord(&mut coord);
+        self.press(&coord)?;
+        sleep(tap.hold.min(MAX_TAP_HOLD));
+        self.release(&coord)?;
+        // Only redraw the cursor if a trackpad client is also connected
+        if self.tx.is_some() && self.opts.reload_background_after_input {
+            sleep(self.opts.reload_background_delay);
+            self.send(CursorMsg::ReloadBackground)?;
+        }
+        Ok(())
+    }
+
     fn send(&self, msg: CursorMsg) -> Result<(), std::sync::mpsc::SendError<CursorMsg>> {
         if let Some(tx) = &self.tx {
             tx.send(msg)
@@ -416,13 +480,12 @@ impl InputManager {
     }
 
     fn reinit_screen(&mut self) -> Result<()> {
-        self.fbink.reinit()?;
-        let state = self.fbink.state();
-        self.rota = state.canonical_rotation();
-        self.screen_width = state.screen_width;
-        self.screen_height = state.screen_height;
-        self.cursor_x_max = (state.screen_width - self.cursor.width()) as f64;
-        self.cursor_y_max = (state.screen_height - self.cursor.height()) as f64;
+        let info = self.screen.refresh()?;
+        self.rota = info.rotation;
+        self.screen_width = info.width;
+        self.screen_height = info.height;
+        self.cursor_x_max = (info.width - self.cursor.width()) as f64;
+        self.cursor_y_max = (info.height - self.cursor.height()) as f64;
         Ok(())
     }
 
@@ -452,15 +515,57 @@ impl InputManager {
 
     /// Translate coordinate from canonical rotation to native rotation
     fn translate_coord(&self, coord: &mut Coord) {
+        debug!("Input coordinates: {coord}");
+        self.transform()
+            .apply(coord, self.screen_width, self.screen_height);
+    }
+
+    /// How coordinates are translated in the current rotation
+    fn transform(&self) -> TouchTransform {
+        self.opts.touch_transform(self.native_transform, self.rota)
+    }
+}
+
+/// How to get from canonical coordinates to the touchscreen's native coordinates
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TouchTransform {
+    pub swap_axes: bool,
+    pub mirror_x: bool,
+    pub mirror_y: bool,
+}
+
+impl TouchTransform {
+    /// How FBInk detected the touchscreen is transformed in its native rotation
+    pub fn native(fbink: &FbInk) -> Self {
+        let state = fbink.state();
+        Self {
+            swap_axes: state.touch_swap_axes,
+            mirror_x: state.touch_mirror_x,
+            mirror_y: state.touch_mirror_y,
+        }
+    }
+
+    /// Every combination of swapping and mirroring
+    pub fn all() -> impl Iterator<Item = Self> {
+        (0..8).map(|i| Self {
+            swap_axes: i & 1 != 0,
+            mirror_x: i & 2 != 0,
+            mirror_y: i & 4 != 0,
+        })
+    }
+
+    /// Adjust the transform of the touchscreen in its native rotation for `rota`
+    pub fn rotated(self, rota: CanonicalRotation) -> Self {
         // Adapted from FBInk https://github.com/NiLuJe/FBInk/blob/master/utils/finger_trace.c
         // Note that we swap the axes at the end rather than at the start.  I assume this is
         // required due to translating canonical -> native not native -> canonical, but I'm bad at
         // maths and don't really understand why this works (tested on a Glo and Sage).
-        debug!("Input coordinates: {coord}");
-        let mut swap_axes = self.swap_axes;
-        let mut mirror_x = self.mirror_x;
-        let mut mirror_y = self.mirror_y;
-        match self.rota {
+        let Self {
+            mut swap_axes,
+            mut mirror_x,
+            mut mirror_y,
+        } = self;
+        match rota {
             CanonicalRotation::Upright => (),
             CanonicalRotation::Clockwise => {
                 swap_axes = !swap_axes;
@@ -475,37 +580,136 @@ impl InputManager {
                 mirror_x = !mirror_x;
             }
         }
-        // Allow users to override the computed values in case they're wrong
-        if self.opts.use_overrides {
-            swap_axes = self.opts.swap_axes_override;
-            mirror_x = self.opts.mirror_x_override;
-            mirror_y = self.opts.mirror_x_override;
+        Self {
+            swap_axes,
+            mirror_x,
+            mirror_y,
+        }
+    }
+
+    /// Translate a position given as fractions of the screen's width and height
+    pub fn apply_fraction(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let x = if self.mirror_x { 1.0 - x } else { x };
+        let y = if self.mirror_y { 1.0 - y } else { y };
+        if self.swap_axes {
+            (y, x)
+        } else {
+            (x, y)
         }
+    }
 
-        if mirror_x {
-            coord.x = self.screen_width as f64 - 1.0 - coord.x;
+    /// Translate a coordinate on a screen of the given canonical size
+    pub fn apply(&self, coord: &mut Coord, screen_width: u32, screen_height: u32) {
+        if self.mirror_x {
+            coord.x = screen_width as f64 - 1.0 - coord.x;
             debug!("Mirrored x coordinates: {coord}");
         }
-        if mirror_y {
-            coord.y = self.screen_height as f64 - 1.0 - coord.y;
+        if self.mirror_y {
+            coord.y = screen_height as f64 - 1.0 - coord.y;
             debug!("Mirrored y coordinates: {coord}");
         }
-        if swap_axes {
+        if self.swap_axes {
             std::mem::swap(&mut coord.x, &mut coord.y);
             debug!("Swapped coordinates: {coord}");
         }
     }
 }
 
+/// The calibrated transform for each rotation, if it's been calibrated
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TouchCalibration {
+    pub upright: Option<TouchTransform>,
+    pub clockwise: Option<TouchTransform>,
+    pub upside_down: Option<TouchTransform>,
+    pub counter_clockwise: Option<TouchTransform>,
+}
+
+impl TouchCalibration {
+    pub fn get(&self, rota: CanonicalRotation) -> Option<TouchTransform> {
+        match rota {
+            CanonicalRotation::Upright => self.upright,
+            CanonicalRotation::Clockwise => self.clockwise,
+            CanonicalRotation::UpsideDown => self.upside_down,
+            CanonicalRotation::CounterClockwise => self.counter_clockwise,
+        }
+    }
+
+    pub fn set(&mut self, rota: CanonicalRotation, transform: TouchTransform) {
+        let calibrated = match rota {
+            CanonicalRotation::Upright => &mut self.upright,
+            CanonicalRotation::Clockwise => &mut self.clockwise,
+            CanonicalRotation::UpsideDown => &mut self.upside_down,
+            CanonicalRotation::CounterClockwise => &mut self.counter_clockwise,
+        };
+        *calibrated = Some(transform);
+    }
+}
+
 pub struct LastDraw {
     pub time: DateTime<Utc>,
     pub coord: Coord,
     pub rect: FbInkRect,
 }
 
+/// The drawing operations needed by the CursorManager
+pub trait CursorCanvas {
+    /// Save what's currently on the screen so it can be restored where the cursor was drawn
+    fn save_background(&mut self) -> Result<()>;
+    /// Restore the saved background within `rect`
+    fn restore(&mut self, rect: FbInkRect) -> Result<()>;
+    /// Draw the cursor over the saved background, returning the area that was drawn
+    fn draw(&mut self, cursor: &DynamicImage, x: u32, y: u32) -> Result<FbInkRect>;
+}
+
+/// Draws the cursor on the framebuffer with FBInk
+pub struct FbInkCanvas {
+    fbink: Arc<FbInk>,
+    dump: Option<Box<dyn Dump>>,
+}
+
+impl FbInkCanvas {
+    pub fn new(fbink: Arc<FbInk>) -> Self {
+        Self { fbink, dump: None }
+    }
+
+    fn dump(&mut self) -> Result<&mut Box<dyn Dump>> {
+        self.dump
+            .as_mut()
+            .ok_or_else(|| anyhow!("No FBInk dump of the background has been taken"))
+    }
+}
+
+impl CursorCanvas for FbInkCanvas {
+    fn save_background(&mut self) -> Result<()> {
+        let dump = self
+            .fbink
+            .dump_workaround_sunxi()
+            .context("Failed to get FBInk dump")?;
+        self.dump = Some(dump);
+        Ok(())
+    }
+
+    fn restore(&mut self, rect: FbInkRect) -> Result<()> {
+        let fbink = self.fbink.clone();
+        let dump = self.dump()?;
+        dump.crop_rect(rect);
+        dump.restore(&fbink).context("Failed to restore dump")?;
+        Ok(())
+    }
+
+    fn draw(&mut self, cursor: &DynamicImage, x: u32, y: u32) -> Result<FbInkRect> {
+        let fbink = self.fbink.clone();
+        self.dump()?
+            .print_overlay(&fbink, cursor, x, y)
+            .context("Failed to print cursor")?;
+        Ok(fbink.get_last_rect(false))
+    }
+}
+
 impl CursorManager {
     pub fn new(
-        fbink: Arc<FbInk>,
+        canvas: Box<dyn CursorCanvas>,
         cursor: DynamicImage,
         rx: CursorReceiver,
         opts: &InputOptions,
@@ -515,7 +719,7 @@ impl CursorManager {
             last_draw: None,
             current_coord: None,
             min_change: 5,
-            fbink,
+            canvas,
             cursor,
             rx,
         }
@@ -525,13 +729,10 @@ impl CursorManager {
             .cursor_min_refresh
             .to_std()
             .unwrap_or(std::time::Duration::from_millis(100));
-        let mut dump = match self.fbink.dump_workaround_sunxi() {
-            Ok(dump) => dump,
-            Err(e) => {
-                error!("Failed to get FBInk dump. {e}");
-                return;
-            }
-        };
+        if let Err(e) = self.canvas.save_background() {
+            error!("{e}");
+            return;
+        }
         let min_range = -self.min_change..self.min_change;
         loop {
             match self.rx.recv_timeout(timeout) {
@@ -555,20 +756,17 @@ impl CursorManager {
                         } else {
                             debug!("Drawing initial cursor");
                         }
-                        self.draw_cursor(&mut dump, coord)
+                        self.draw_cursor(coord)
                     }
                     CursorMsg::Hide => {
-                        self.hide_cursor(&mut dump);
+                        self.hide_cursor();
                     }
                     CursorMsg::ReloadBackground => {
                         debug!("Reloading background");
-                        dump = match self.fbink.dump_workaround_sunxi() {
-                            Ok(dump) => dump,
-                            Err(e) => {
-                                error!("Failed to get FBInk dump. {e}");
-                                return;
-                            }
-                        };
+                        if let Err(e) = self.canvas.save_background() {
+                            error!("{e}");
+                            return;
+                        }
                     }
                     CursorMsg::Reinit => {
                         self.current_coord = None;
@@ -595,7 +793,7 @@ impl CursorManager {
                             debug!("Cursor hasn't moved enough to trigger redraw");
                             continue;
                         }
-                        self.draw_cursor(&mut dump, current)
+                        self.draw_cursor(current)
                     }
                     sleep(self.cursor_min_refresh)
                 }
@@ -607,31 +805,31 @@ impl CursorManager {
         }
     }
     // Remove the last drawn cursor from the screen
-    fn hide_cursor(&mut self, dump: &mut Box<dyn Dump>) {
+    fn hide_cursor(&mut self) {
         if let Some(last) = &self.last_draw {
             debug!("Restoring dump where last cursor was drawn");
-            dump.crop_rect(last.rect);
-            if let Err(e) = dump.restore(&self.fbink) {
-                error!("Failed to restore dump. {e}");
+            if let Err(e) = self.canvas.restore(last.rect) {
+                error!("{e}");
             }
             self.last_draw = None;
         }
     }
 
-    fn draw_cursor(&mut self, dump: &mut Box<dyn Dump>, coord: Coord) {
+    fn draw_cursor(&mut self, coord: Coord) {
         let x = coord.x as i64;
         let y = coord.y as i64;
 
-        self.hide_cursor(dump);
-        if let Err(e) = dump.print_overlay(&self.fbink, &self.cursor, x as u32, y as u32) {
-            error!("Failed to print cursor. {e}");
+        self.hide_cursor();
+        match self.canvas.draw(&self.cursor, x as u32, y as u32) {
+            Ok(rect) => {
+                self.last_draw = Some(LastDraw {
+                    time: Utc::now(),
+                    coord,
+                    rect,
+                })
+            }
+            Err(e) => error!("{e}"),
         }
-
-        self.last_draw = Some(LastDraw {
-            time: Utc::now(),
-            coord,
-            rect: self.fbink.get_last_rect(false),
-        });
     }
 }
 
@@ -655,6 +853,36 @@ fn get_event_batch<'a>(
     Ok(events)
 }
 
+/// The longest a tap on a screenshot can be held for
+const MAX_TAP_HOLD: Duration = Duration::milliseconds(5000);
+
+/// A touch at a point on a screenshot of the e-reader
+#[serde_with::serde_as]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScreenTap {
+    /// From 0 at the left edge of the screenshot to 1 at the right
+    pub x: f64,
+    /// From 0 at the top of the screenshot to 1 at the bottom
+    pub y: f64,
+    /// How long to touch for. Hold for longer to long press
+    #[serde_as(as = "DurationMilliSeconds<i64>")]
+    pub hold: Duration,
+}
+
+impl ScreenTap {
+    /// The canonical coordinates of the tap on a screen of the given size
+    pub fn coord(&self, screen_width: u32, screen_height: u32) -> Coord {
+        let scale = |fraction: f64, size: u32| {
+            let max = size.saturating_sub(1) as f64;
+            (fraction * max).round().clamp(0.0, max)
+        };
+        Coord {
+            x: scale(self.x, screen_width),
+            y: scale(self.y, screen_height),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Coord {
     pub x: f64,
@@ -668,7 +896,30 @@ impl Display for Coord {
 }
 
 pub fn routes() -> Router<AppState> {
-    Router::new().route("/ws", get(handler))
+    Router::new()
+        .route("/ws", get(handler))
+        .route("/screen/tap", post(tap_handler))
+}
+
+async fn tap_handler(
+    State(state): State<AppState>,
+    Json(tap): Json<ScreenTap>,
+) -> Result<impl IntoResponse, AppError> {
+    debug!("Received request to tap the screen at {tap:?}");
+    if !state.config().user.remote_control.tap_on_screen {
+        return Err(anyhow!("Tapping the screen is disabled in the user config").into());
+    }
+    if !state.arbitrary_input_running().await {
+        state.start_arbitrary_input().await?;
+        if !state.arbitrary_input_running().await {
+            return Err(anyhow!(
+                "Tapping the screen needs a next-page action to use as a template"
+            )
+            .into());
+        }
+    }
+    state.send_input_msg(InputMsg::Tap(tap)).await?;
+    Ok(())
 }
 
 async fn handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> Response {
@@ -715,3 +966,66 @@ async fn send_input_msg(state: &AppState, socket: &mut WebSocket, msg: InputMsg)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Coord, CursorManager, CursorMsg, InputOptions, ScreenTap};
+    use crate::fakes::{CanvasCall, FakeCanvas};
+    use chrono::Duration;
+    use fbink_rs::image::DynamicImage;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn cursor_background_restored_before_redraw() {
+        let canvas = FakeCanvas::default();
+        let opts = InputOptions {
+            cursor_min_refresh: Duration::zero(),
+            ..Default::default()
+        };
+        let (tx, rx) = std::sync::mpsc::channel();
+        let cursor = DynamicImage::new_rgba8(32, 50);
+        let mut cursor_manager = CursorManager::new(Box::new(canvas.clone()), cursor, rx, &opts);
+        for msg in [
+            CursorMsg::Draw(Coord { x: 100.0, y: 200.0 }),
+            // Too small a change to redraw
+            CursorMsg::Draw(Coord { x: 102.0, y: 201.0 }),
+            CursorMsg::Draw(Coord { x: 300.0, y: 400.0 }),
+            CursorMsg::Hide,
+            CursorMsg::Stop,
+        ] {
+            tx.send(msg).unwrap();
+        }
+        cursor_manager.manage();
+
+        let expected = vec![
+            CanvasCall::SaveBackground,
+            CanvasCall::Draw(100, 200),
+            CanvasCall::Restore(100, 200),
+            CanvasCall::Draw(300, 400),
+            CanvasCall::Restore(300, 400),
+        ];
+        assert_eq!(expected, canvas.calls());
+    }
+
+    #[test]
+    fn screen_tap_scaled_to_screen() {
+        let tap = |x, y| ScreenTap {
+            x,
+            y,
+            hold: Duration::milliseconds(50),
+        };
+        assert_eq!(Coord { x: 0.0, y: 0.0 }, tap(0.0, 0.0).coord(1072, 1448));
+        assert_eq!(
+            Coord {
+                x: 536.0,
+                y: 1447.0
+            },
+            tap(0.5, 1.0).coord(1072, 1448)
+        );
+        // Taps on the edge of the image can land just outside it
+        assert_eq!(
+            Coord { x: 1071.0, y: 0.0 },
+            tap(1.01, -0.01).coord(1072, 1448)
+        );
+    }
+}
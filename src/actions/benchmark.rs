@@ -0,0 +1,261 @@
+//! Measures how long the reader takes to respond to an action, so the setup options can be tuned
+//! for each model
+use super::history::percentile;
+use super::ActionMsg;
+use crate::config::SetupOptions;
+use crate::errors::AppError;
+use crate::server::AppState;
+
+use anyhow::{bail, Result};
+use axum::{
+    extract::{Path as AxumPath, State},
+    response::IntoResponse,
+    routing::post,
+    Json, Router,
+};
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DurationMicroSeconds, DurationMilliSeconds};
+use tokio::sync::oneshot;
+use tracing::debug;
+
+/// The shortest syn_gap suggested when plays are missed
+const MIN_SUGGESTED_SYN_GAP: Duration = Duration::milliseconds(1);
+
+/// The most times one benchmark can play an action
+pub const MAX_BENCHMARK_RUNS: u32 = 100;
+
+/// The longest a benchmark can wait for the screen after each play
+const MAX_BENCHMARK_TIMEOUT: Duration = Duration::seconds(30);
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/actions/:path_segment/benchmark", post(benchmark_handler))
+}
+
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BenchmarkOptions {
+    /// How many times to play the action
+    pub runs: u32,
+    /// How long to wait for the screen to change and then settle after each play
+    #[serde_as(as = "DurationMilliSeconds<i64>")]
+    pub timeout: Duration,
+    /// How long the screen has to stay the same to count as stable
+    #[serde_as(as = "DurationMilliSeconds<i64>")]
+    pub settle_time: Duration,
+}
+
+impl BenchmarkOptions {
+    pub fn check(&self) -> Result<()> {
+        if !(1..=MAX_BENCHMARK_RUNS).contains(&self.runs) {
+            bail!("A benchmark needs between 1 and {MAX_BENCHMARK_RUNS} runs");
+        }
+        if self.timeout > MAX_BENCHMARK_TIMEOUT {
+            bail!(
+                "The benchmark timeout can't be more than {} seconds",
+                MAX_BENCHMARK_TIMEOUT.num_seconds()
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Default for BenchmarkOptions {
+    fn default() -> Self {
+        Self {
+            runs: 10,
+            timeout: Duration::milliseconds(3000),
+            settle_time: Duration::milliseconds(500),
+        }
+    }
+}
+
+/// Times of a single play, from the last event being written. They can't be more precise than
+/// how long it takes to capture the screen
+#[serde_with::serde_as]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BenchmarkRun {
+    /// Until the screen first changed. None if it didn't change before the timeout
+    #[serde_as(as = "Option<DurationMilliSeconds<i64>>")]
+    pub first_change: Option<Duration>,
+    /// Until the last change before the screen settled. None if it didn't settle before the
+    /// timeout
+    #[serde_as(as = "Option<DurationMilliSeconds<i64>>")]
+    pub stable: Option<Duration>,
+}
+
+/// Handed to the player's thread so benchmarks don't overlap with plays
+pub struct BenchmarkJob {
+    pub path_segment: String,
+    pub opts: BenchmarkOptions,
+    /// From [super::PlayCanceller::generation] when the benchmark was requested
+    pub generation: u64,
+    pub resp: oneshot::Sender<Result<Vec<BenchmarkRun>>>,
+}
+
+#[serde_with::serde_as]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LatencyStats {
+    #[serde_as(as = "DurationMilliSeconds<i64>")]
+    pub min: Duration,
+    #[serde_as(as = "DurationMilliSeconds<i64>")]
+    pub p50: Duration,
+    #[serde_as(as = "DurationMilliSeconds<i64>")]
+    pub p95: Duration,
+    #[serde_as(as = "DurationMilliSeconds<i64>")]
+    pub max: Duration,
+    #[serde_as(as = "DurationMilliSeconds<i64>")]
+    pub mean: Duration,
+}
+
+impl LatencyStats {
+    /// None if there are no times
+    pub fn new(mut times: Vec<Duration>) -> Option<Self> {
+        times.sort();
+        let total = times.iter().fold(Duration::zero(), |t, &d| t + d);
+        Some(Self {
+            min: *times.first()?,
+            p50: percentile(&times, 0.5)?,
+            p95: percentile(&times, 0.95)?,
+            max: *times.last()?,
+            mean: total / times.len() as i32,
+        })
+    }
+}
+
+/// Values for the [SetupOptions] that suit how the reader responded
+#[serde_with::serde_as]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SuggestedSetup {
+    #[serde_as(as = "DurationMilliSeconds<i64>")]
+    pub post_playback_delay: Duration,
+    #[serde_as(as = "DurationMicroSeconds<i64>")]
+    pub syn_gap: Duration,
+    pub optimize: bool,
+}
+
+impl SuggestedSetup {
+    /// The delay is the 95th percentile of the time to the first change, rounded up to the next
+    /// 50ms, so most page turns have registered before the next is played. Missed plays suggest
+    /// the reader is dropping input that's played too quickly, so they double the syn_gap and turn
+    /// off optimizing
+    pub fn new(first_change: Option<&LatencyStats>, missed: u32, setup: &SetupOptions) -> Self {
+        let post_playback_delay = first_change.map_or(setup.post_playback_delay, |s| {
+            let ms = s.p95.num_milliseconds();
+            Duration::milliseconds((ms + 49) / 50 * 50)
+        });
+        if missed == 0 {
+            return Self {
+                post_playback_delay,
+                syn_gap: setup.syn_gap,
+                optimize: setup.optimize,
+            };
+        }
+        Self {
+            post_playback_delay,
+            syn_gap: (setup.syn_gap * 2).max(MIN_SUGGESTED_SYN_GAP),
+            optimize: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BenchmarkReport {
+    pub path_segment: String,
+    pub runs: Vec<BenchmarkRun>,
+    /// Plays that didn't change the screen
+    pub missed: u32,
+    pub first_change: Option<LatencyStats>,
+    pub stable: Option<LatencyStats>,
+    pub suggested: SuggestedSetup,
+}
+
+impl BenchmarkReport {
+    pub fn new(path_segment: &str, runs: Vec<BenchmarkRun>, setup: &SetupOptions) -> Self {
+        let first_change = LatencyStats::new(runs.iter().filter_map(|r| r.first_change).collect());
+        let stable = LatencyStats::new(runs.iter().filter_map(|r| r.stable).collect());
+        let missed = runs.iter().filter(|r| r.first_change.is_none()).count() as u32;
+        Self {
+            path_segment: path_segment.to_string(),
+            suggested: SuggestedSetup::new(first_change.as_ref(), missed, setup),
+            runs,
+            missed,
+            first_change,
+            stable,
+        }
+    }
+}
+
+async fn benchmark_handler(
+    State(state): State<AppState>,
+    AxumPath(path_segment): AxumPath<String>,
+    Json(opts): Json<BenchmarkOptions>,
+) -> Result<impl IntoResponse, AppError> {
+    debug!("Received request to benchmark {path_segment}");
+    let (tx, rx) = oneshot::channel();
+    let msg = ActionMsg::Benchmark(BenchmarkJob {
+        path_segment: path_segment.clone(),
+        opts,
+        generation: state.plays.generation(),
+        resp: tx,
+    });
+    state.tx.send(msg).await?;
+    let runs = rx.await??;
+    let setup = state.config().user.setup.clone();
+    Ok(Json(BenchmarkReport::new(&path_segment, runs, &setup)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn run(first_change: Option<i64>) -> BenchmarkRun {
+        BenchmarkRun {
+            first_change: first_change.map(Duration::milliseconds),
+            stable: first_change.map(|ms| Duration::milliseconds(ms + 200)),
+        }
+    }
+
+    #[test]
+    fn suggest_from_runs() {
+        let setup = SetupOptions::default();
+        let runs = (1..=10).map(|i| run(Some(i * 30))).collect();
+        let report = BenchmarkReport::new("next-page", runs, &setup);
+        let first_change = report.first_change.unwrap();
+        assert_eq!(Duration::milliseconds(30), first_change.min);
+        assert_eq!(Duration::milliseconds(165), first_change.mean);
+        assert_eq!(Duration::milliseconds(500), report.stable.unwrap().max);
+        assert_eq!(0, report.missed);
+        let suggested = report.suggested;
+        assert_eq!(Duration::milliseconds(300), suggested.post_playback_delay);
+        assert_eq!(setup.syn_gap, suggested.syn_gap);
+        assert!(suggested.optimize);
+
+        let report = BenchmarkReport::new("next-page", vec![run(Some(120)), run(None)], &setup);
+        assert_eq!(1, report.missed);
+        assert_eq!(
+            Duration::milliseconds(150),
+            report.suggested.post_playback_delay
+        );
+        assert_eq!(MIN_SUGGESTED_SYN_GAP, report.suggested.syn_gap);
+        assert!(!report.suggested.optimize);
+    }
+
+    #[test]
+    fn benchmark_runs_are_bounded() {
+        let opts = |runs| BenchmarkOptions {
+            runs,
+            ..Default::default()
+        };
+        assert!(opts(MAX_BENCHMARK_RUNS).check().is_ok());
+        assert!(opts(MAX_BENCHMARK_RUNS + 1).check().is_err());
+        assert!(opts(0).check().is_err());
+        let long = BenchmarkOptions {
+            timeout: Duration::minutes(1),
+            ..Default::default()
+        };
+        assert!(long.check().is_err());
+    }
+}
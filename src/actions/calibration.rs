@@ -0,0 +1,252 @@
+//! Works out how to translate coordinates for arbitrary input by drawing targets on the screen and
+//! reading where the user touches them
+use super::arbitrary::{Coord, CursorCanvas, TouchTransform};
+use super::devices::InputDevices;
+use super::input::{is_x_coord, is_y_coord};
+use super::recording::RecordingControl;
+use super::{ActionMsg, RecordActionOptions};
+use crate::errors::AppError;
+use crate::server::AppState;
+
+use std::fs;
+
+use anyhow::{anyhow, Context, Result};
+use axum::{extract::State, response::IntoResponse, routing::post, Json, Router};
+use chrono::Duration;
+use fbink_rs::image::{DynamicImage, GrayImage, Luma};
+use fbink_rs::CanonicalRotation;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use tracing::{debug, info};
+
+/// Where targets are drawn, as fractions of the screen's width and height. No swap or mirror
+/// leaves every target in the same place
+const TARGETS: [(f64, f64); 3] = [(0.25, 0.25), (0.75, 0.25), (0.25, 0.75)];
+
+/// The width and height of a target in pixels
+const TARGET_SIZE: u32 = 61;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/calibration", post(calibrate_handler))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CalibrationPoint {
+    /// The centre of the target in canonical coordinates
+    pub target: Coord,
+    /// Where the touchscreen reported the touch in native coordinates
+    pub touch: Coord,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Calibration {
+    pub rotation: CanonicalRotation,
+    pub transform: TouchTransform,
+    pub points: Vec<CalibrationPoint>,
+}
+
+impl TouchTransform {
+    /// The transform that brings the targets closest to where they were touched
+    pub fn fit(points: &[CalibrationPoint], screen_width: u32, screen_height: u32) -> Self {
+        let error = |transform: &Self| {
+            points
+                .iter()
+                .map(|p| {
+                    let mut target = p.target;
+                    transform.apply(&mut target, screen_width, screen_height);
+                    (target.x - p.touch.x).powi(2) + (target.y - p.touch.y).powi(2)
+                })
+                .sum::<f64>()
+        };
+        Self::all()
+            .min_by(|a, b| error(a).total_cmp(&error(b)))
+            .expect("There's always a transform")
+    }
+}
+
+/// A black cross on white, with its centre in the middle
+fn target_image() -> DynamicImage {
+    let middle = TARGET_SIZE / 2;
+    let target = GrayImage::from_fn(TARGET_SIZE, TARGET_SIZE, |x, y| {
+        if x.abs_diff(middle) <= 1 || y.abs_diff(middle) <= 1 {
+            Luma([0])
+        } else {
+            Luma([255])
+        }
+    });
+    DynamicImage::ImageLuma8(target)
+}
+
+/// Draw each target in turn and wait for it to be touched, then fit a transform to the touches
+pub fn calibrate(
+    canvas: &mut dyn CursorCanvas,
+    devices: &dyn InputDevices,
+    opts: &RecordActionOptions,
+    screen_width: u32,
+    screen_height: u32,
+) -> Result<(TouchTransform, Vec<CalibrationPoint>)> {
+    let image = target_image();
+    let middle = TARGET_SIZE / 2;
+    canvas.save_background()?;
+    let mut points = Vec::with_capacity(TARGETS.len());
+    for (x, y) in TARGETS {
+        let target = Coord {
+            x: (x * screen_width as f64).round(),
+            y: (y * screen_height as f64).round(),
+        };
+        let rect = canvas.draw(&image, target.x as u32 - middle, target.y as u32 - middle)?;
+        let touch = read_touch(devices, opts);
+        canvas.restore(rect)?;
+        let point = CalibrationPoint {
+            target,
+            touch: touch?,
+        };
+        debug!(
+            "Calibration target {} touched at {}",
+            point.target, point.touch
+        );
+        points.push(point);
+    }
+    let transform = TouchTransform::fit(&points, screen_width, screen_height);
+    Ok((transform, points))
+}
+
+/// Where the first touch read from the touchscreen was
+fn read_touch(devices: &dyn InputDevices, opts: &RecordActionOptions) -> Result<Coord> {
+    let read = devices.read(opts, &RecordingControl::default())?;
+    let events: Vec<_> = read.into_iter().flat_map(|(_, e)| e.events).collect();
+    let x = events.iter().find(|ev| is_x_coord(ev));
+    let y = events.iter().find(|ev| is_y_coord(ev));
+    match (x, y) {
+        (Some(x), Some(y)) => Ok(Coord {
+            x: x.value.into(),
+            y: y.value.into(),
+        }),
+        _ => Err(anyhow!("No touch was detected on the target")),
+    }
+}
+
+async fn calibrate_handler(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    debug!("Received request to calibrate touch input");
+    let info = state.screen.refresh()?;
+    let rotation = info.rotation;
+    let (resp, rx) = oneshot::channel();
+    state.tx.send(ActionMsg::GetInputDevices { resp }).await?;
+    let (devices, locks) = rx.await?;
+    let opts = RecordActionOptions {
+        only_check_touch: true,
+        // So touching the targets doesn't also turn pages or open menus
+        exclusive_grab: true,
+        no_input_timeout: Duration::seconds(30),
+        new_event_timeout: Duration::milliseconds(250),
+        ..Default::default()
+    };
+    // FBInk dumps might not be Send, so the canvas is created on the thread that uses it
+    let screen = state.screen.clone();
+    let (transform, points) = tokio::task::spawn_blocking(move || {
+        // Plays to the touchscreen wait until calibration is finished, like they do for recordings
+        let _guard = locks.lock(devices.paths(&opts)?);
+        let mut canvas = screen.canvas()?;
+        calibrate(
+            canvas.as_mut(),
+            devices.as_ref(),
+            &opts,
+            info.width,
+            info.height,
+        )
+    })
+    .await??;
+    info!("Calibrated touch input in {rotation} rotation: {transform:?}");
+    {
+        let mut config = state.config();
+        config
+            .user
+            .arbitrary_input
+            .calibration
+            .set(rotation, transform);
+        fs::write(&config.user_config_path, toml::to_string(&config.user)?)
+            .context("Failed to write user config file")?;
+    }
+    // Restart arbitrary input so it uses the new calibration
+    if state.arbitrary_input_running().await {
+        state.start_arbitrary_input().await?;
+    }
+    Ok(Json(Calibration {
+        rotation,
+        transform,
+        points,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fakes::{CanvasCall, FakeCanvas, FakeInputDevices};
+    use evdev_rs::enums::EventCode::{EV_ABS, EV_SYN};
+    use evdev_rs::enums::EV_ABS::{ABS_MT_POSITION_X, ABS_MT_POSITION_Y};
+    use evdev_rs::enums::EV_SYN::SYN_REPORT;
+    use evdev_rs::{InputEvent, TimeVal};
+    use pretty_assertions::assert_eq;
+
+    const WIDTH: u32 = 1072;
+    const HEIGHT: u32 = 1448;
+
+    fn touch(coord: Coord) -> Vec<InputEvent> {
+        let time = TimeVal::new(0, 0);
+        [
+            (EV_ABS(ABS_MT_POSITION_X), coord.x as i32),
+            (EV_ABS(ABS_MT_POSITION_Y), coord.y as i32),
+            (EV_SYN(SYN_REPORT), 0),
+        ]
+        .into_iter()
+        .map(|(event_code, value)| InputEvent {
+            time,
+            event_code,
+            value,
+        })
+        .collect()
+    }
+
+    #[test]
+    fn calibration_finds_every_transform() {
+        for expected in TouchTransform::all() {
+            let devices = FakeInputDevices::default();
+            for (x, y) in TARGETS {
+                // Touches are a little off target
+                let mut coord = Coord {
+                    x: (x * WIDTH as f64).round() + 12.0,
+                    y: (y * HEIGHT as f64).round() - 9.0,
+                };
+                expected.apply(&mut coord, WIDTH, HEIGHT);
+                devices.push(touch(coord));
+            }
+            let mut canvas = FakeCanvas::default();
+            let (transform, points) = calibrate(
+                &mut canvas,
+                &devices,
+                &RecordActionOptions::default(),
+                WIDTH,
+                HEIGHT,
+            )
+            .unwrap();
+            assert_eq!(expected, transform);
+            assert_eq!(3, points.len());
+            assert_eq!(CanvasCall::Draw(238, 332), canvas.calls()[1]);
+        }
+    }
+
+    #[test]
+    fn calibration_needs_touches() {
+        let mut canvas = FakeCanvas::default();
+        let result = calibrate(
+            &mut canvas,
+            &FakeInputDevices::default(),
+            &RecordActionOptions::default(),
+            WIDTH,
+            HEIGHT,
+        );
+        assert!(result.is_err());
+        // The target is removed even when it isn't touched
+        assert_eq!(CanvasCall::Restore(238, 332), canvas.calls()[2]);
+    }
+}
@@ -0,0 +1,239 @@
+//! Access to input devices for recording and generating actions, behind a trait so the
+//! ActionManager can be used without hardware
+use super::input::{
+    get_input_devices, is_keys_device, is_touch_device, read_input, DeviceEvents,
+    DeviceFingerprint, InputDevice,
+};
+use super::inspect::{read_events, DeviceInfo, MonitorEvent};
+use super::recording::RecordingControl;
+use super::synthetic::TouchCapabilities;
+use super::RecordActionOptions;
+
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+
+use anyhow::{anyhow, Result};
+use evdev_rs::enums::EventCode::{self, EV_ABS};
+use evdev_rs::enums::EV_ABS::ABS_MT_SLOT;
+use evdev_rs::DeviceWrapper;
+use tokio::sync::mpsc;
+
+/// The device an action's events were read from or will be written to
+#[derive(Clone, Debug)]
+pub struct DeviceDetails {
+    pub path: PathBuf,
+    /// The path and name of the device, for display
+    pub name: String,
+    pub fingerprint: Option<DeviceFingerprint>,
+}
+
+impl From<&InputDevice> for DeviceDetails {
+    fn from(d: &InputDevice) -> Self {
+        Self {
+            path: d.path.clone(),
+            name: d.to_string(),
+            fingerprint: Some(DeviceFingerprint::from(d)),
+        }
+    }
+}
+
+impl Display for DeviceDetails {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+pub trait InputDevices: Send + Sync {
+    /// Describe every input device
+    fn list(&self, use_by_path: bool) -> Result<Vec<DeviceInfo>>;
+    /// Send the events from a device until the duration has passed or the receiver is dropped
+    fn monitor(
+        &self,
+        path: &Path,
+        duration: chrono::Duration,
+        tx: mpsc::Sender<MonitorEvent>,
+    ) -> Result<()>;
+    /// The paths of the devices a recording with the options would read from
+    fn paths(&self, opts: &RecordActionOptions) -> Result<Vec<PathBuf>>;
+    /// Read events from the devices selected by the options until their timeouts are reached or
+    /// the recording is aborted, returning the devices that had input
+    fn read(
+        &self,
+        opts: &RecordActionOptions,
+        control: &RecordingControl,
+    ) -> Result<Vec<(DeviceDetails, DeviceEvents)>>;
+    /// Find the current path of a recorded device. Recordings without a fingerprint use their
+    /// last known path
+    fn resolve(&self, fingerprint: Option<&DeviceFingerprint>, last_path: &Path)
+        -> Result<PathBuf>;
+    /// Find a touchscreen that supports multi-touch slots
+    fn multi_touch(&self, use_by_path: bool) -> Result<(DeviceDetails, TouchCapabilities)>;
+    /// Find a device that supports the key, preferring devices with physical buttons in case the
+    /// touchscreen also claims to support it
+    fn key_device(&self, code: EventCode, use_by_path: bool) -> Result<DeviceDetails>;
+}
+
+/// The real input devices in /dev/input
+pub struct EvdevDevices;
+
+impl EvdevDevices {
+    fn selected(opts: &RecordActionOptions) -> Result<Vec<InputDevice>> {
+        let devices = get_input_devices(opts.use_by_path)?.into_iter();
+        Ok(if opts.only_check_keys {
+            devices.filter(is_keys_device).collect()
+        } else if opts.only_check_touch {
+            devices.filter(is_touch_device).collect()
+        } else {
+            devices.collect()
+        })
+    }
+}
+
+impl InputDevices for EvdevDevices {
+    fn list(&self, use_by_path: bool) -> Result<Vec<DeviceInfo>> {
+        let devices = get_input_devices(use_by_path)?;
+        Ok(devices.iter().map(DeviceInfo::from).collect())
+    }
+
+    fn monitor(
+        &self,
+        path: &Path,
+        duration: chrono::Duration,
+        tx: mpsc::Sender<MonitorEvent>,
+    ) -> Result<()> {
+        read_events(InputDevice::open(path)?, tx, duration);
+        Ok(())
+    }
+
+    fn paths(&self, opts: &RecordActionOptions) -> Result<Vec<PathBuf>> {
+        Ok(Self::selected(opts)?.into_iter().map(|d| d.path).collect())
+    }
+
+    fn read(
+        &self,
+        opts: &RecordActionOptions,
+        control: &RecordingControl,
+    ) -> Result<Vec<(DeviceDetails, DeviceEvents)>> {
+        let devices_with_events = read_input(
+            Self::selected(opts)?.into_iter(),
+            opts.no_input_timeout,
+            opts.new_event_timeout,
+            opts.exclusive_grab,
+            control,
+        )?;
+        Ok(devices_with_events
+            .into_iter()
+            .map(|(d, e)| (DeviceDetails::from(&d), e))
+            .collect())
+    }
+
+    fn resolve(
+        &self,
+        fingerprint: Option<&DeviceFingerprint>,
+        last_path: &Path,
+    ) -> Result<PathBuf> {
+        match fingerprint {
+            Some(fingerprint) => fingerprint.resolve(last_path),
+            None => Ok(last_path.to_path_buf()),
+        }
+    }
+
+    fn multi_touch(&self, use_by_path: bool) -> Result<(DeviceDetails, TouchCapabilities)> {
+        let device = get_input_devices(use_by_path)?
+            .into_iter()
+            .find(|d| is_touch_device(d) && d.evdev.has(EV_ABS(ABS_MT_SLOT)))
+            .ok_or_else(|| anyhow!("No touchscreen that supports multi-touch slots was found"))?;
+        let touch = TouchCapabilities::new(&device)?;
+        Ok((DeviceDetails::from(&device), touch))
+    }
+
+    fn key_device(&self, code: EventCode, use_by_path: bool) -> Result<DeviceDetails> {
+        let mut devices: Vec<_> = get_input_devices(use_by_path)?
+            .into_iter()
+            .filter(|d| d.evdev.has(code))
+            .collect();
+        devices.sort_by_key(|d| !is_keys_device(d));
+        devices
+            .first()
+            .map(DeviceDetails::from)
+            .ok_or_else(|| anyhow!("No input device supports {code}"))
+    }
+}
+
+/// The devices that are being recorded from or played to, so a recording doesn't capture a play
+/// and a play doesn't interrupt a recording. Clones share the same locks
+#[derive(Clone, Default)]
+pub struct DeviceLocks(Arc<(Mutex<HashSet<PathBuf>>, Condvar)>);
+
+impl DeviceLocks {
+    /// Wait until none of the devices are in use, then hold them until the guard is dropped
+    pub fn lock(&self, paths: impl IntoIterator<Item = PathBuf>) -> DeviceGuard {
+        // Symlinks like /dev/input/by-path lock the device they point to
+        let paths: Vec<_> = paths
+            .into_iter()
+            .map(|p| fs::canonicalize(&p).unwrap_or(p))
+            .collect();
+        let (held, released) = &*self.0;
+        let mut held = released
+            .wait_while(held.lock().unwrap(), |held| {
+                paths.iter().any(|p| held.contains(p))
+            })
+            .unwrap();
+        held.extend(paths.iter().cloned());
+        DeviceGuard {
+            locks: self.clone(),
+            paths,
+        }
+    }
+}
+
+pub struct DeviceGuard {
+    locks: DeviceLocks,
+    paths: Vec<PathBuf>,
+}
+
+impl Drop for DeviceGuard {
+    fn drop(&mut self) {
+        let (held, released) = &*self.locks.0;
+        let mut held = held.lock().unwrap();
+        for path in &self.paths {
+            held.remove(path);
+        }
+        released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn device_locks_exclude_same_device() {
+        let locks = DeviceLocks::default();
+        let guard = locks.lock([PathBuf::from("/dev/input/event1")]);
+
+        // Other devices can still be used
+        drop(locks.lock([PathBuf::from("/dev/input/event0")]));
+
+        let (tx, rx) = mpsc::channel();
+        let waiting = locks.clone();
+        let handle = thread::spawn(move || {
+            let _guard = waiting.lock([
+                PathBuf::from("/dev/input/event0"),
+                PathBuf::from("/dev/input/event1"),
+            ]);
+            tx.send(()).unwrap();
+        });
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(guard);
+        assert!(rx.recv_timeout(Duration::from_secs(1)).is_ok());
+        handle.join().unwrap();
+    }
+}
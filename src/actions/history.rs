@@ -0,0 +1,312 @@
+//! A bounded log of every play, kept on disk so slow or failing actions can be tracked down
+use super::{ActionMsg, PlayResult};
+use crate::errors::AppError;
+use crate::server::AppState;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use axum::{extract::State, response::IntoResponse, routing::get, Json, Router};
+use chrono::{DateTime, Duration, Utc};
+use fbink_rs::CanonicalRotation;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DurationMilliSeconds};
+use tokio::sync::oneshot;
+use tracing::{debug, error, warn};
+
+/// How many plays to keep when the config doesn't say
+pub const DEFAULT_HISTORY_LIMIT: usize = 1000;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/actions/history", get(get_history))
+        .route("/actions/history/stats", get(get_stats))
+}
+
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlayRecord {
+    /// When the play was requested
+    pub time: DateTime<Utc>,
+    pub path_segment: String,
+    pub rotation: Option<CanonicalRotation>,
+    /// The address of the client that requested the play, if it came over the network
+    pub client: Option<IpAddr>,
+    /// The name of the device the action was played on. Plays logged by older versions have none
+    #[serde(default)]
+    pub device: Option<String>,
+    /// How long the plays took, not counting any wait for earlier plays
+    #[serde_as(as = "DurationMilliSeconds<i64>")]
+    pub time_taken: Duration,
+    pub played: u32,
+    /// Whether the screen changed after the last play, if the action is verified
+    #[serde(default)]
+    pub effect: Option<bool>,
+    pub error: Option<String>,
+}
+
+impl PlayRecord {
+    pub fn new(
+        path_segment: &str,
+        client: Option<IpAddr>,
+        requested: DateTime<Utc>,
+        rotation: Option<CanonicalRotation>,
+        result: &Result<PlayResult>,
+    ) -> Self {
+        Self {
+            time: requested,
+            path_segment: path_segment.to_string(),
+            rotation,
+            client,
+            device: result.as_ref().ok().and_then(|r| r.device.clone()),
+            time_taken: result.as_ref().map_or(Duration::zero(), |r| r.time_taken),
+            played: result.as_ref().map_or(0, |r| r.played),
+            effect: result.as_ref().ok().and_then(|r| r.effect),
+            error: result.as_ref().err().map(|e| format!("{e:#}")),
+        }
+    }
+}
+
+/// The most recent plays, oldest first. Clones share the same history
+#[derive(Clone, Debug)]
+pub struct PlayHistory(Arc<Mutex<HistoryFile>>);
+
+#[derive(Debug)]
+struct HistoryFile {
+    /// Where plays are appended as JSON lines. The history is only kept in memory without one
+    path: Option<PathBuf>,
+    limit: usize,
+    entries: VecDeque<PlayRecord>,
+    /// How many lines the file has, so it can be rewritten before it grows too far past the limit
+    lines: usize,
+}
+
+impl Default for PlayHistory {
+    fn default() -> Self {
+        Self::new(None, Vec::new(), DEFAULT_HISTORY_LIMIT)
+    }
+}
+
+impl PlayHistory {
+    fn new(path: Option<PathBuf>, entries: Vec<PlayRecord>, limit: usize) -> Self {
+        let lines = entries.len();
+        let mut entries = VecDeque::from(entries);
+        while entries.len() > limit {
+            entries.pop_front();
+        }
+        Self(Arc::new(Mutex::new(HistoryFile {
+            path,
+            limit,
+            entries,
+            lines,
+        })))
+    }
+
+    pub fn load(path: PathBuf, limit: usize) -> Result<Self> {
+        let mut entries = Vec::new();
+        if path.exists() {
+            debug!("Loading play history from {}", path.display());
+            let file = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read play history from {}", path.display()))?;
+            for line in file.lines() {
+                // A line might be cut short if the device powered off while it was written
+                match serde_json::from_str(line) {
+                    Ok(record) => entries.push(record),
+                    Err(e) => warn!("Skipped invalid line in play history. {e}"),
+                }
+            }
+        }
+        Ok(Self::new(Some(path), entries, limit))
+    }
+
+    pub fn add(&self, record: PlayRecord) {
+        let mut history = self.0.lock().unwrap();
+        if let Err(e) = history.add(record) {
+            error!("Failed to save play history. {e:#}");
+        }
+    }
+
+    pub fn entries(&self) -> Vec<PlayRecord> {
+        self.0.lock().unwrap().entries.iter().cloned().collect()
+    }
+}
+
+impl HistoryFile {
+    fn add(&mut self, record: PlayRecord) -> Result<()> {
+        let line = serde_json::to_string(&record).context("Failed to serialize play")?;
+        self.entries.push_back(record);
+        while self.entries.len() > self.limit {
+            self.entries.pop_front();
+        }
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        // Appending keeps each play to a small write. The file is only rewritten once it's
+        // holding twice as many plays as are kept
+        if self.lines < self.limit * 2 {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            writeln!(file, "{line}").context("Failed to append to play history")?;
+            self.lines += 1;
+            return Ok(());
+        }
+        let mut lines = String::new();
+        for record in &self.entries {
+            lines.push_str(&serde_json::to_string(record).context("Failed to serialize play")?);
+            lines.push('\n');
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, lines)
+            .with_context(|| format!("Failed to write play history to {}", tmp.display()))?;
+        fs::rename(&tmp, path).context("Failed to rename temporary play history file")?;
+        self.lines = self.entries.len();
+        Ok(())
+    }
+}
+
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ActionStats {
+    pub path_segment: String,
+    pub device: Option<String>,
+    pub plays: usize,
+    pub failures: usize,
+    /// The fraction of plays that failed, from 0 to 1
+    pub failure_rate: f64,
+    /// The median time taken by successful plays
+    #[serde_as(as = "Option<DurationMilliSeconds<i64>>")]
+    pub p50: Option<Duration>,
+    #[serde_as(as = "Option<DurationMilliSeconds<i64>>")]
+    pub p95: Option<Duration>,
+}
+
+/// How often each action in the history failed on each device and how long it took when it didn't
+pub fn play_stats(entries: &[PlayRecord]) -> Vec<ActionStats> {
+    let mut by_action: BTreeMap<(&str, Option<&str>), Vec<&PlayRecord>> = BTreeMap::new();
+    for record in entries {
+        by_action
+            .entry((&record.path_segment, record.device.as_deref()))
+            .or_default()
+            .push(record);
+    }
+    by_action
+        .into_iter()
+        .map(|((path_segment, device), records)| {
+            let failures = records.iter().filter(|r| r.error.is_some()).count();
+            let mut times: Vec<_> = records
+                .iter()
+                .filter(|r| r.error.is_none())
+                .map(|r| r.time_taken)
+                .collect();
+            times.sort();
+            ActionStats {
+                path_segment: path_segment.to_string(),
+                device: device.map(str::to_string),
+                plays: records.len(),
+                failures,
+                failure_rate: failures as f64 / records.len() as f64,
+                p50: percentile(&times, 0.5),
+                p95: percentile(&times, 0.95),
+            }
+        })
+        .collect()
+}
+
+/// The nearest-rank percentile of sorted times
+pub(super) fn percentile(sorted: &[Duration], p: f64) -> Option<Duration> {
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.max(1) - 1).copied()
+}
+
+/// Get the plays in the history from the ActionManager
+pub async fn history(state: &AppState) -> Result<Vec<PlayRecord>> {
+    let (tx, rx) = oneshot::channel();
+    state
+        .tx
+        .send(ActionMsg::GetPlayHistory { resp: tx })
+        .await?;
+    Ok(rx.await?)
+}
+
+async fn get_history(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(history(&state).await?))
+}
+
+async fn get_stats(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(play_stats(&history(&state).await?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn record(path_segment: &str, ms: i64, failed: bool) -> PlayRecord {
+        PlayRecord {
+            time: Utc::now(),
+            path_segment: path_segment.to_string(),
+            rotation: None,
+            client: None,
+            device: Some("cyttsp5_mt".to_string()),
+            time_taken: Duration::milliseconds(ms),
+            played: if failed { 0 } else { 1 },
+            effect: None,
+            error: failed.then(|| "No recording".to_string()),
+        }
+    }
+
+    #[test]
+    fn stats_per_action() {
+        let mut entries: Vec<_> = (1..=20).map(|ms| record("next-page", ms, false)).collect();
+        entries.push(record("next-page", 0, true));
+        entries.push(record("prev-page", 0, true));
+        let stats = play_stats(&entries);
+        assert_eq!(2, stats.len());
+        assert_eq!(21, stats[0].plays);
+        assert_eq!(1, stats[0].failures);
+        assert_eq!(Some(Duration::milliseconds(10)), stats[0].p50);
+        assert_eq!(Some(Duration::milliseconds(19)), stats[0].p95);
+        assert_eq!(1.0, stats[1].failure_rate);
+        assert_eq!(None, stats[1].p50);
+    }
+
+    #[test]
+    fn stats_per_device() {
+        let mut other = record("next-page", 30, false);
+        other.device = Some("Elan Touchscreen".to_string());
+        let entries = vec![record("next-page", 10, false), other];
+        let stats = play_stats(&entries);
+        assert_eq!(2, stats.len());
+        assert_eq!(Some("Elan Touchscreen"), stats[0].device.as_deref());
+        assert_eq!(Some(Duration::milliseconds(30)), stats[0].p50);
+        assert_eq!(Some("cyttsp5_mt"), stats[1].device.as_deref());
+        assert_eq!(Some(Duration::milliseconds(10)), stats[1].p50);
+    }
+
+    #[test]
+    fn history_is_bounded_on_disk() {
+        let dir = std::env::temp_dir().join(format!("wifiremote-history-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("play-history.jsonl");
+        let history = PlayHistory::load(path.clone(), 3).unwrap();
+        for ms in 0..10 {
+            history.add(record("next-page", ms, false));
+        }
+        let loaded = PlayHistory::load(path.clone(), 3).unwrap().entries();
+        let times: Vec<_> = loaded
+            .iter()
+            .map(|r| r.time_taken.num_milliseconds())
+            .collect();
+        assert_eq!(vec![7, 8, 9], times);
+        assert!(fs::read_to_string(&path).unwrap().lines().count() <= 6);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,219 @@
+//! Backends for injecting the events of an action into the input subsystem
+use super::input::{open_for_writing, InputDevice};
+use super::ActionEvent;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Context, Result};
+use evdev_rs::{InputEvent, UInputDevice};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InjectionBackend {
+    /// Write events directly to the recorded device's /dev/input/eventX node
+    #[default]
+    DeviceNode,
+    /// Write events to a uinput virtual device that mirrors the recorded device's capabilities
+    /// and ABS ranges. The virtual device only exists while the server is running, so the reader
+    /// might not notice events played from the CLI without it.
+    Uinput,
+}
+
+/// Somewhere to write the events of an action
+pub trait EventSink: Send {
+    fn write_event(&mut self, event: &ActionEvent) -> Result<()>;
+}
+
+/// Writes the raw bytes of each event to an input device's node
+pub struct DeviceNodeSink {
+    path: PathBuf,
+    file: File,
+}
+
+impl DeviceNodeSink {
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: open_for_writing(path)?,
+        })
+    }
+}
+
+impl EventSink for DeviceNodeSink {
+    fn write_event(&mut self, event: &ActionEvent) -> Result<()> {
+        self.file
+            .write_all(&event.buf)
+            .with_context(|| format!("Failed to write event to {}", self.path.display()))
+    }
+}
+
+/// Writes events to a uinput virtual device. Clones share the same virtual device
+#[derive(Clone)]
+pub struct UinputSink {
+    source: PathBuf,
+    device: Arc<Mutex<UInputDevice>>,
+}
+
+impl UinputSink {
+    /// Create a virtual device with the same capabilities as the device at `source`
+    pub fn create(source: &Path) -> Result<Self> {
+        let source_device = InputDevice::open(source)?;
+        let device = UInputDevice::create_from_device(&source_device.evdev)
+            .with_context(|| format!("Failed to create uinput device mirroring {source_device}"))?;
+        debug!(
+            "Created uinput device {} mirroring {source_device}",
+            device.devnode().unwrap_or("(no devnode)")
+        );
+        Ok(Self {
+            source: source.to_path_buf(),
+            device: Arc::new(Mutex::new(device)),
+        })
+    }
+}
+
+impl EventSink for UinputSink {
+    fn write_event(&mut self, event: &ActionEvent) -> Result<()> {
+        let event = event.input_event()?;
+        let device = self
+            .device
+            .lock()
+            .map_err(|_| anyhow!("uinput device mutex was poisoned"))?;
+        device.write_event(&event).with_context(|| {
+            format!(
+                "Failed to write event to uinput device mirroring {}",
+                self.source.display()
+            )
+        })
+    }
+}
+
+/// Keeps the events written to it, so playback can be checked without hardware. Clones share the
+/// same events
+#[derive(Clone, Debug, Default)]
+pub struct MemorySink {
+    pub events: Arc<Mutex<Vec<InputEvent>>>,
+}
+
+impl MemorySink {
+    /// Remove and return the events written so far
+    pub fn take(&self) -> Vec<InputEvent> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+}
+
+impl EventSink for MemorySink {
+    fn write_event(&mut self, event: &ActionEvent) -> Result<()> {
+        let event = event.input_event()?;
+        self.events
+            .lock()
+            .map_err(|_| anyhow!("MemorySink mutex was poisoned"))?
+            .push(event);
+        Ok(())
+    }
+}
+
+/// Opens a sink for the device an action was recorded with, keeping uinput devices alive between
+/// actions so the reader has a chance to notice them. Clones share the same uinput devices, so
+/// there's only ever one mirroring each device
+#[derive(Clone)]
+pub struct Injector {
+    backend: InjectionBackend,
+    uinput: Arc<Mutex<HashMap<PathBuf, UinputSink>>>,
+    memory: Option<MemorySink>,
+}
+
+impl Injector {
+    pub fn new(backend: InjectionBackend) -> Self {
+        Self {
+            backend,
+            uinput: Arc::default(),
+            memory: None,
+        }
+    }
+
+    /// Send every action's events to `sink` instead of a device
+    pub fn memory(sink: MemorySink) -> Self {
+        Self {
+            backend: InjectionBackend::default(),
+            uinput: Arc::default(),
+            memory: Some(sink),
+        }
+    }
+
+    pub fn sink(&self, dev_path: &Path) -> Result<Box<dyn EventSink>> {
+        if let Some(memory) = &self.memory {
+            return Ok(Box::new(memory.clone()));
+        }
+        match self.backend {
+            InjectionBackend::DeviceNode => Ok(Box::new(DeviceNodeSink::open(dev_path)?)),
+            InjectionBackend::Uinput => {
+                let mut uinput = self
+                    .uinput
+                    .lock()
+                    .map_err(|_| anyhow!("uinput devices mutex was poisoned"))?;
+                if let Some(sink) = uinput.get(dev_path) {
+                    return Ok(Box::new(sink.clone()));
+                }
+                let sink = UinputSink::create(dev_path)?;
+                uinput.insert(dev_path.to_path_buf(), sink.clone());
+                Ok(Box::new(sink))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Injector, MemorySink};
+    use crate::actions::PlaybackTiming;
+    use crate::fakes::fake_recording;
+    use evdev_rs::enums::EventCode::{EV_ABS, EV_SYN};
+    use evdev_rs::enums::EV_ABS::{ABS_X, ABS_Y};
+    use evdev_rs::enums::EV_SYN::SYN_REPORT;
+    use evdev_rs::{InputEvent, TimeVal};
+    use pretty_assertions::assert_eq;
+    use std::path::Path;
+
+    #[test]
+    fn play_into_memory_sink() {
+        let time = TimeVal {
+            tv_sec: 1705963814,
+            tv_usec: 769621,
+        };
+        let event = |event_code, value| InputEvent {
+            time,
+            event_code,
+            value,
+        };
+        let events = vec![
+            event(EV_ABS(ABS_X), 522),
+            event(EV_ABS(ABS_Y), 51),
+            event(EV_SYN(SYN_REPORT), 0),
+        ];
+        let recording = fake_recording(&events);
+        let memory = MemorySink::default();
+        let injector = Injector::memory(memory.clone());
+        let mut sink = injector.sink(Path::new(&recording.dev_path)).unwrap();
+        recording
+            .play("test", sink.as_mut(), false, &PlaybackTiming::default())
+            .unwrap();
+
+        let written: Vec<_> = memory
+            .take()
+            .iter()
+            .map(|ev| (ev.event_code, ev.value))
+            .collect();
+        let expected = vec![
+            (EV_ABS(ABS_X), 522),
+            (EV_ABS(ABS_Y), 51),
+            (EV_SYN(SYN_REPORT), 0),
+        ];
+        assert_eq!(expected, written);
+    }
+}
@@ -1,20 +1,32 @@
 use super::parse_timeval;
+use super::recording::{RecordingControl, RecordingProgress};
 
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::fs::{self, File};
-use std::path::PathBuf;
-use std::sync::mpsc::{channel, Sender};
-use std::thread::{self, sleep};
+use std::path::{Path, PathBuf};
+use std::time::{Duration as StdDuration, Instant};
 
 use anyhow::{anyhow, Context, Result};
-use chrono::{Duration, Utc};
-use evdev_rs::enums::EventCode::{EV_ABS, EV_KEY, EV_SYN};
+use chrono::Duration;
+use evdev_rs::enums::EventCode::{self, EV_ABS, EV_KEY, EV_SYN};
+use evdev_rs::enums::EventType;
 use evdev_rs::enums::EV_KEY::BTN_TOUCH;
 use evdev_rs::enums::{EV_ABS::*, EV_SYN::*};
-use evdev_rs::{Device, DeviceWrapper, InputEvent, ReadFlag, ReadStatus};
+use evdev_rs::util::{event_type_get_max, int_to_event_code};
+use evdev_rs::{Device, DeviceWrapper, GrabMode, InputEvent, ReadFlag, ReadStatus};
+use nix::errno::Errno;
 use nix::libc::suseconds_t;
+use nix::poll::{poll, PollFd, PollFlags};
+use serde::{Deserialize, Serialize};
 use tracing::{debug, warn};
 
+/// The first BTN_ code, and the first KEY_ code after the BTN_ codes
+const BTN_MISC: u32 = 0x100;
+const KEY_OK: u32 = 0x160;
+/// How often to check if a recording has been aborted while waiting for input
+const ABORT_CHECK_INTERVAL: StdDuration = StdDuration::from_millis(100);
+
 pub fn get_input_devices(use_by_path: bool) -> Result<Vec<InputDevice>> {
     // /dev/input/eventX paths aren't guaranteed to be stable, but they don't seem to change in
     // practise. /dev/input/by-path doesn't exist on old kernels, and on at least the Aura H20
@@ -43,109 +55,361 @@ pub fn get_input_devices(use_by_path: bool) -> Result<Vec<InputDevice>> {
             continue;
         }
 
-        let f = File::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
-        let d = Device::new_from_file(f)
-            .with_context(|| format!("Failed to initialize evdev device {}", path.display()))?;
-        let device = InputDevice {
-            path,
-            name: d.name().map(|s| s.to_string()),
-            evdev: d,
-        };
-        devices.push(device);
+        devices.push(InputDevice::open(&path)?);
     }
     Ok(devices)
 }
 
-fn read_events(
+/// The event types checked when listing the codes a device supports
+pub const EVENT_TYPES: &[EventType] = &[
+    EventType::EV_SYN,
+    EventType::EV_KEY,
+    EventType::EV_REL,
+    EventType::EV_ABS,
+    EventType::EV_MSC,
+    EventType::EV_SW,
+    EventType::EV_LED,
+    EventType::EV_SND,
+    EventType::EV_REP,
+    EventType::EV_FF,
+];
+
+/// All the event codes a device supports
+pub fn supported_codes(d: &InputDevice) -> Vec<EventCode> {
+    let mut codes = Vec::new();
+    for ev_type in EVENT_TYPES {
+        if !d.evdev.has(*ev_type) {
+            continue;
+        }
+        let max = event_type_get_max(ev_type).unwrap_or_default();
+        for code in 0..=max {
+            let code = int_to_event_code(*ev_type as u32, code);
+            if !matches!(code, EventCode::EV_UNK { .. }) && d.evdev.has(code) {
+                codes.push(code);
+            }
+        }
+    }
+    codes
+}
+
+/// Identifies an input device independently of its /dev/input/eventX path, which isn't
+/// guaranteed to stay the same between boots
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceFingerprint {
+    pub name: Option<String>,
+    pub phys: Option<String>,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub bustype: u16,
+    /// The names of the event codes the device supports
+    pub capabilities: Vec<String>,
+}
+
+impl From<&InputDevice> for DeviceFingerprint {
+    fn from(d: &InputDevice) -> Self {
+        Self {
+            name: d.name.clone(),
+            phys: d.evdev.phys().map(|s| s.to_string()),
+            vendor_id: d.evdev.vendor_id(),
+            product_id: d.evdev.product_id(),
+            bustype: d.evdev.bustype(),
+            capabilities: supported_codes(d).iter().map(|c| c.to_string()).collect(),
+        }
+    }
+}
+
+impl DeviceFingerprint {
+    /// Whether `other` is the same device. The codes a device supports can change with kernel
+    /// and driver updates, so they aren't compared
+    fn identifies(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.phys == other.phys
+            && self.vendor_id == other.vendor_id
+            && self.product_id == other.product_id
+            && self.bustype == other.bustype
+    }
+
+    /// Pick the device this fingerprint identifies from `candidates`. If several match, those
+    /// supporting the same codes are preferred, then the one at `last_path`
+    fn choose(
+        &self,
+        candidates: impl IntoIterator<Item = (PathBuf, DeviceFingerprint)>,
+        last_path: &Path,
+    ) -> Option<(PathBuf, DeviceFingerprint)> {
+        candidates
+            .into_iter()
+            .filter(|(_, fingerprint)| self.identifies(fingerprint))
+            .max_by_key(|(path, fingerprint)| {
+                (
+                    fingerprint.capabilities == self.capabilities,
+                    path == last_path,
+                )
+            })
+    }
+
+    /// Find the current path of the device, preferring `last_path` if there are multiple
+    /// identical devices
+    pub fn resolve(&self, last_path: &Path) -> Result<PathBuf> {
+        if let Ok(device) = InputDevice::open(last_path) {
+            if DeviceFingerprint::from(&device) == *self {
+                return Ok(device.path);
+            }
+        }
+        let candidates = get_input_devices(false)?.into_iter().map(|d| {
+            let fingerprint = DeviceFingerprint::from(&d);
+            (d.path, fingerprint)
+        });
+        let (path, fingerprint) = self.choose(candidates, last_path).ok_or_else(|| {
+            anyhow!(
+                "The input device {} ({}) couldn't be found. Its path was {} when recorded",
+                self.name.as_deref().unwrap_or("unnamed"),
+                self.phys.as_deref().unwrap_or("unknown location"),
+                last_path.display()
+            )
+        })?;
+        if fingerprint.capabilities != self.capabilities {
+            warn!(
+                "{} supports different event codes than when it was recorded",
+                path.display()
+            );
+        }
+        if path != last_path {
+            warn!(
+                "{} has moved from {}. Using its new path",
+                path.display(),
+                last_path.display()
+            );
+        }
+        Ok(path)
+    }
+}
+
+/// Open an input device so events can be written to it
+pub fn open_for_writing(path: &Path) -> Result<File> {
+    File::options()
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {} to write events", path.display()))
+}
+
+/// A device being read from by [read_input]
+struct DeviceReader {
     device: InputDevice,
-    tx: Sender<ReadEventsMsg>,
-    poll_wait: Duration,
+    events: DeviceEvents,
+    state: ReadState,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct ReadState {
+    last_event_time: Option<Instant>,
+    done: bool,
+}
+
+/// Decides when to stop reading each device
+struct ReadTimeouts {
+    start: Instant,
+    no_input: StdDuration,
+    new_event: StdDuration,
+}
+
+impl ReadTimeouts {
+    /// When to stop reading a device if no more events arrive
+    fn deadline(&self, state: &ReadState) -> Instant {
+        match state.last_event_time {
+            Some(t) => t + self.new_event,
+            None => self.start + self.no_input,
+        }
+    }
+
+    /// Mark the device as done if its deadline has passed, returning whether it just finished
+    fn expire(&self, state: &mut ReadState, now: Instant) -> bool {
+        if state.done || now < self.deadline(state) {
+            return false;
+        }
+        state.done = true;
+        true
+    }
+
+    /// The earliest deadline of the devices still being read, or None once they're all done
+    fn next_deadline<'a>(&self, states: impl Iterator<Item = &'a ReadState>) -> Option<Instant> {
+        states.filter(|s| !s.done).map(|s| self.deadline(s)).min()
+    }
+}
+
+/// Read events from all the devices in a single poll loop until each has either had no input for
+/// `no_input_timeout`, or no new events for `new_event_timeout` since its last event. Stops early
+/// if the recording is aborted.
+pub fn read_input(
+    devices: impl Iterator<Item = InputDevice>,
+    no_input_timeout: Duration,
+    new_event_timeout: Duration,
+    exclusive_grab: bool,
+    control: &RecordingControl,
+) -> Result<Vec<(InputDevice, DeviceEvents)>> {
+    let mut readers: Vec<_> = devices
+        .map(|device| DeviceReader {
+            device,
+            events: DeviceEvents::default(),
+            state: ReadState::default(),
+        })
+        .collect();
+    // Grabbing the devices stops the input from also being delivered to Nickel, so e.g. recording
+    // a page turn doesn't actually turn the page
+    if exclusive_grab {
+        for r in readers.iter_mut() {
+            debug!("Grabbing {}", r.device);
+            if let Err(e) = r.device.evdev.grab(GrabMode::Grab) {
+                warn!("Failed to grab {}. Input won't be exclusive. {e}", r.device);
+            }
+        }
+    }
+    let result = read_until_timeout(&mut readers, no_input_timeout, new_event_timeout, control);
+    if exclusive_grab {
+        for r in readers.iter_mut() {
+            debug!("Releasing grab on {}", r.device);
+            if let Err(e) = r.device.evdev.grab(GrabMode::Ungrab) {
+                warn!("Failed to release grab on {}. {e}", r.device);
+            }
+        }
+    }
+    result?;
+    Ok(readers
+        .into_iter()
+        .filter(|r| !r.events.events.is_empty())
+        .map(|r| (r.device, r.events))
+        .collect())
+}
+
+fn read_until_timeout(
+    readers: &mut [DeviceReader],
     no_input_timeout: Duration,
     new_event_timeout: Duration,
-) {
-    let mut events = Vec::new();
-    let mut last_event_time = None;
-    let start_time = Utc::now();
-    debug!("Reading events from {device}");
+    control: &RecordingControl,
+) -> Result<()> {
+    let timeouts = ReadTimeouts {
+        start: Instant::now(),
+        no_input: no_input_timeout.to_std().unwrap_or_default(),
+        new_event: new_event_timeout.to_std().unwrap_or_default(),
+    };
+    for r in readers.iter() {
+        debug!("Reading events from {}", r.device);
+    }
     loop {
-        if !device.evdev.has_event_pending() {
-            sleep(poll_wait.to_std().unwrap());
-            if let Some(last_time) = last_event_time {
-                let time_since_last = Utc::now() - last_time;
-                if time_since_last > new_event_timeout {
-                    debug!(
-                        "{}ms since last event on {device}. Stopping",
-                        new_event_timeout.num_milliseconds(),
-                    );
-                    break;
-                }
-            } else if Utc::now() - start_time > no_input_timeout {
+        if control.is_aborted() {
+            debug!("Recording aborted. Stopping");
+            return Ok(());
+        }
+        let now = Instant::now();
+        for r in readers.iter_mut() {
+            if !timeouts.expire(&mut r.state, now) {
+                continue;
+            }
+            if r.state.last_event_time.is_some() {
+                debug!(
+                    "{}ms since last event on {}. Stopping",
+                    timeouts.new_event.as_millis(),
+                    r.device
+                );
+            } else {
                 debug!(
-                    "No input detected on {device} after {}ms. Stopping",
-                    no_input_timeout.num_milliseconds(),
+                    "No input detected on {} after {}ms. Stopping",
+                    r.device,
+                    timeouts.no_input.as_millis()
                 );
-                break;
             }
-            continue;
         }
-        let (status, event) = device
-            .evdev
-            .next_event(ReadFlag::NORMAL | ReadFlag::BLOCKING)
-            .unwrap();
-        match status {
-            ReadStatus::Success => (),
-            ReadStatus::Sync => {
-                // Not sure if it's worth trying to handle this if it occurs. Never happened in
-                // testing and unsure of the best approach, so for now just return an error.
-                // https://docs.rs/evdev-rs/0.6.1/evdev_rs/struct.Device.html#method.next_event
-                if tx.send(ReadEventsMsg::Error).is_err() {
-                    warn!("Failed to send ReadEventsMsg::Error to receiver");
-                    return;
+        let Some(deadline) = timeouts.next_deadline(readers.iter().map(|r| &r.state)) else {
+            return Ok(());
+        };
+        let active: Vec<_> = readers.iter_mut().filter(|r| !r.state.done).collect();
+        let devices: Vec<_> = active.iter().map(|r| &r.device).collect();
+        let timeout = deadline
+            .saturating_duration_since(now)
+            .min(ABORT_CHECK_INTERVAL);
+        let ready = wait_for_events(&devices, timeout)?;
+        let had_input = ready.iter().any(|&r| r);
+        for (r, ready) in active.into_iter().zip(ready) {
+            if !ready {
+                continue;
+            }
+            let mut read = 0;
+            while r.device.evdev.has_event_pending() {
+                let (status, event) = r
+                    .device
+                    .evdev
+                    .next_event(ReadFlag::NORMAL | ReadFlag::BLOCKING)
+                    .with_context(|| format!("Failed to read event from {}", r.device))?;
+                r.events.push(status, event);
+                if status == ReadStatus::Sync {
+                    warn!("Events were dropped on {}. Resyncing", r.device);
+                    resync(&r.device, &mut r.events)?;
                 }
+                r.state.last_event_time = Some(Instant::now());
+                read += 1;
+            }
+            // The fd was readable but there were no events, so the device must have errored or
+            // been removed. Stop reading it rather than spin
+            if read == 0 {
+                warn!("{} stopped responding. Stopping", r.device);
+                r.state.done = true;
             }
         }
-        events.push(event);
-        last_event_time = Some(Utc::now());
-    }
-    if events.is_empty() {
-        if tx.send(ReadEventsMsg::NoEvents).is_err() {
-            warn!("Failed to send ReadEventsMsg::NoEvents to receiver");
+        if had_input {
+            report_input(readers, control);
         }
-    } else if tx.send(ReadEventsMsg::Events((device, events))).is_err() {
-        warn!("Failed to send ReadEventsMsg::Events to receiver");
     }
 }
 
-enum ReadEventsMsg {
-    Events((InputDevice, Vec<InputEvent>)),
-    NoEvents,
-    Error,
+fn report_input(readers: &[DeviceReader], control: &RecordingControl) {
+    let with_input: Vec<_> = readers
+        .iter()
+        .filter(|r| !r.events.events.is_empty())
+        .collect();
+    control.report(RecordingProgress::InputDetected {
+        devices: with_input.iter().map(|r| r.device.to_string()).collect(),
+        events: with_input.iter().map(|r| r.events.events.len()).sum(),
+    });
 }
 
-pub fn read_input(
-    devices: impl Iterator<Item = InputDevice>,
-    poll_wait: Duration,
-    no_input_timeout: Duration,
-    new_event_timeout: Duration,
-) -> Result<Vec<(InputDevice, Vec<InputEvent>)>> {
-    let mut devices_with_events = Vec::new();
-    let (tx, rx) = channel();
-    let mut len = 0;
-    for d in devices {
-        len += 1;
-        let dtx = tx.clone();
-        thread::spawn(move || read_events(d, dtx, poll_wait, no_input_timeout, new_event_timeout));
+/// Read the events libevdev generates to bring the device state up to date after SYN_DROPPED
+/// https://www.freedesktop.org/software/libevdev/doc/latest/syn_dropped.html
+fn resync(device: &InputDevice, events: &mut DeviceEvents) -> Result<()> {
+    loop {
+        match device.evdev.next_event(ReadFlag::SYNC) {
+            Ok((status, event)) => events.push(status, event),
+            // No more events to sync
+            Err(e) if e.raw_os_error() == Some(Errno::EAGAIN as i32) => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to resync {device}")),
+        }
     }
-    let err = anyhow!("An error occured while reading input. Please try again.");
-    for _ in 0..len {
-        match rx.recv() {
-            Ok(ReadEventsMsg::Events(events)) => devices_with_events.push(events),
-            Ok(ReadEventsMsg::NoEvents) => continue,
-            Ok(ReadEventsMsg::Error) => return Err(err),
-            Err(_) => return Err(err),
+}
+
+/// Block until at least one of the devices has events to read or the timeout elapses. Returns
+/// whether each device is readable.
+pub fn wait_for_events(devices: &[&InputDevice], timeout: StdDuration) -> Result<Vec<bool>> {
+    let mut fds: Vec<_> = devices
+        .iter()
+        .map(|d| PollFd::new(d.evdev.file(), PollFlags::POLLIN))
+        .collect();
+    // Round up so we don't wake up just before the deadline and spin
+    let timeout = timeout.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32;
+    loop {
+        match poll(&mut fds, timeout) {
+            Ok(_) => break,
+            // Retrying with the full timeout is fine as the deadline is checked by the caller
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(e).context("Failed to poll input devices"),
         }
     }
-    Ok(devices_with_events)
+    Ok(fds
+        .iter()
+        .map(|fd| {
+            fd.revents().is_some_and(|r| {
+                r.intersects(PollFlags::POLLIN | PollFlags::POLLERR | PollFlags::POLLHUP)
+            })
+        })
+        .collect())
 }
 
 #[derive(Debug)]
@@ -155,6 +419,19 @@ pub struct InputDevice {
     pub evdev: Device,
 }
 
+impl InputDevice {
+    pub fn open(path: &Path) -> Result<Self> {
+        let f = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let d = Device::new_from_file(f)
+            .with_context(|| format!("Failed to initialize evdev device {}", path.display()))?;
+        Ok(InputDevice {
+            path: path.to_path_buf(),
+            name: d.name().map(|s| s.to_string()),
+            evdev: d,
+        })
+    }
+}
+
 impl Display for InputDevice {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.path.display())?;
@@ -165,6 +442,77 @@ impl Display for InputDevice {
     }
 }
 
+/// The events read from a device
+#[derive(Debug, Default, PartialEq)]
+pub struct DeviceEvents {
+    pub events: Vec<InputEvent>,
+    /// Whether the kernel's buffer overflowed and events had to be resynced
+    pub resynced: bool,
+}
+
+impl DeviceEvents {
+    pub fn push(&mut self, status: ReadStatus, event: InputEvent) {
+        if status == ReadStatus::Sync && event.is_code(&EV_SYN(SYN_DROPPED)) {
+            // Events since the last SYN_REPORT are incomplete so they must be discarded. libevdev
+            // then provides events describing the changes in state we missed
+            let last_report = self
+                .events
+                .iter()
+                .rposition(|ev| ev.is_code(&EV_SYN(SYN_REPORT)));
+            self.events.truncate(last_report.map_or(0, |i| i + 1));
+            self.resynced = true;
+        } else {
+            self.events.push(event);
+        }
+    }
+
+    /// Check that resynced events still make up a complete action, i.e. it ends with a full
+    /// report and every key and touch that was pressed is released.
+    pub fn check_resync(&self) -> Result<()> {
+        if !self.resynced {
+            return Ok(());
+        }
+        let err = anyhow!(
+            "Input was lost because events were produced faster than they could be read and the \
+             action couldn't be recovered. Please try again."
+        );
+        if !self
+            .events
+            .last()
+            .is_some_and(|ev| ev.is_code(&EV_SYN(SYN_REPORT)))
+        {
+            return Err(err);
+        }
+        // Type A multi-touch devices (e.g. the Aura H2O v1) don't use tracking IDs to signal the
+        // end of a touch, so we can only check keys for them
+        let is_type_b = !self
+            .events
+            .iter()
+            .any(|ev| ev.is_code(&EV_SYN(SYN_MT_REPORT)));
+        let mut keys = HashMap::new();
+        let mut slots = HashMap::new();
+        let mut slot = 0;
+        for ev in &self.events {
+            match ev.event_code {
+                EV_KEY(key) => {
+                    keys.insert(key, ev.value);
+                }
+                EV_ABS(ABS_MT_SLOT) => slot = ev.value,
+                EV_ABS(ABS_MT_TRACKING_ID) if is_type_b => {
+                    slots.insert(slot, ev.value);
+                }
+                _ => (),
+            }
+        }
+        let key_held = keys.values().any(|v| *v != 0);
+        let touch_held = slots.values().any(|id| *id != -1);
+        if key_held || touch_held {
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
 pub fn is_touch_device(d: &InputDevice) -> bool {
     debug!("Checking if {d} is a touch device");
     let has_btn_touch = d.evdev.has(EV_KEY(BTN_TOUCH));
@@ -174,6 +522,24 @@ pub fn is_touch_device(d: &InputDevice) -> bool {
     has_btn_touch || has_mt_pos_x
 }
 
+/// Whether the device has physical buttons, like page turn or power buttons. On Kobos these are
+/// usually on a separate gpio-keys device rather than the touchscreen
+pub fn is_keys_device(d: &InputDevice) -> bool {
+    if is_touch_device(d) || !d.evdev.has(EventType::EV_KEY) {
+        return false;
+    }
+    let max = event_type_get_max(&EventType::EV_KEY).unwrap_or_default();
+    let has_key = (1..=max)
+        // Skip the BTN_ codes used by mice, joysticks etc.
+        .filter(|code| !(BTN_MISC..KEY_OK).contains(code))
+        .any(|code| {
+            d.evdev
+                .has(int_to_event_code(EventType::EV_KEY as u32, code))
+        });
+    debug!("{d} has_key: {has_key}");
+    has_key
+}
+
 fn events_duration(events: &[InputEvent]) -> Duration {
     let Some(first) = events.first() else {
         return Duration::zero();
@@ -190,12 +556,26 @@ pub fn optimize_events(
     max_duration: Duration,
 ) -> bool {
     if events.is_empty()
-        // Skip actions more complex than a single tap or swipe
+        // Skip actions more complex than a single tap, swipe or gesture
         || events.iter().filter(|ev| ev.is_code(&EV_KEY(BTN_TOUCH))).count() > 2
-        // Skip multi-touch gestures
-        || events.iter().filter(|ev| ev.is_code(&EV_ABS(ABS_MT_SLOT))).count() > 1
         // The Aura H20 doesn't produce BTN_TOUCH events, so as a fallback skip long sequences
         || events_duration(events) > max_duration
+        // Button presses have no coordinates and need to be held for as long as they were
+        || !events.iter().any(|ev| is_x_coord(ev) || is_y_coord(ev))
+    {
+        debug!("Skipped optimizing events");
+        return false;
+    }
+
+    if is_multi_touch(events) {
+        return optimize_multi_touch(events, syn_gap);
+    }
+    // A single touch that used more than one slot (e.g. a slot that was reused) isn't supported
+    if events
+        .iter()
+        .filter(|ev| ev.is_code(&EV_ABS(ABS_MT_SLOT)))
+        .count()
+        > 1
     {
         debug!("Skipped optimizing events");
         return false;
@@ -258,8 +638,12 @@ pub fn optimize_events(
 
     events.clear();
     events.extend(first.into_iter().chain(penultimate.into_iter().chain(last)));
+    compress_batches(events, syn_gap);
+    true
+}
 
-    // Reduce the gap between each batch of events to the smallest possible amount
+/// Reduce the gap between each batch of events to the smallest possible amount
+fn compress_batches(events: &mut [InputEvent], syn_gap: Duration) {
     let mut new_time = events.first().unwrap().time;
     for ev in events {
         ev.time = new_time;
@@ -267,6 +651,229 @@ pub fn optimize_events(
             new_time.tv_usec += syn_gap.num_microseconds().unwrap() as suseconds_t;
         }
     }
+}
+
+/// Whether the events are a gesture that used more than one slot of the type B multi-touch
+/// protocol, like a pinch or two-finger swipe
+fn is_multi_touch(events: &[InputEvent]) -> bool {
+    if events.iter().any(|ev| ev.is_code(&EV_SYN(SYN_MT_REPORT))) {
+        return false;
+    }
+    let mut slots = events
+        .iter()
+        .filter(|ev| ev.is_code(&EV_ABS(ABS_MT_SLOT)))
+        .map(|ev| ev.value);
+    let Some(first) = slots.next() else {
+        return false;
+    };
+    slots.any(|slot| slot != first)
+}
+
+fn is_mt_code(ev: &InputEvent) -> bool {
+    matches!(
+        ev.event_code,
+        EV_ABS(
+            ABS_MT_SLOT
+                | ABS_MT_TOUCH_MAJOR
+                | ABS_MT_TOUCH_MINOR
+                | ABS_MT_WIDTH_MAJOR
+                | ABS_MT_WIDTH_MINOR
+                | ABS_MT_ORIENTATION
+                | ABS_MT_POSITION_X
+                | ABS_MT_POSITION_Y
+                | ABS_MT_TOOL_TYPE
+                | ABS_MT_BLOB_ID
+                | ABS_MT_TRACKING_ID
+                | ABS_MT_PRESSURE
+                | ABS_MT_DISTANCE
+                | ABS_MT_TOOL_X
+                | ABS_MT_TOOL_Y
+        )
+    )
+}
+
+/// The events of a single contact in a multi-touch gesture
+#[derive(Debug)]
+struct SlotTouch {
+    slot: i32,
+    /// The index of the batch the contact started in
+    start: usize,
+    /// The index of the batch the contact was released in
+    end: Option<usize>,
+    /// The slot's events from the batch the contact started in
+    first: Vec<InputEvent>,
+    last_x: Option<InputEvent>,
+    last_y: Option<InputEvent>,
+    release: Option<InputEvent>,
+}
+
+/// Reduce a type B multi-touch gesture to a start, penultimate and end batch, with each batch
+/// containing the events of every slot. Like single touches, only the start and end coordinates
+/// of each contact seem to matter.
+fn optimize_multi_touch(events: &mut Vec<InputEvent>, syn_gap: Duration) -> bool {
+    let Some(syn_report) = events.last().filter(|ev| ev.is_code(&EV_SYN(SYN_REPORT))) else {
+        debug!("Skipped optimizing multi-touch events without a final SYN_REPORT");
+        return false;
+    };
+    let syn_report = syn_report.clone();
+
+    // Split the events into the contact in each slot and the other events (BTN_TOUCH etc.) along
+    // with the index of the batch they were in. The current slot carries over between batches
+    let mut touches: Vec<SlotTouch> = Vec::new();
+    let mut others: Vec<(usize, &InputEvent)> = Vec::new();
+    let mut batch = 0;
+    let mut slot = 0;
+    for ev in events.iter() {
+        if ev.is_code(&EV_SYN(SYN_REPORT)) {
+            batch += 1;
+            continue;
+        }
+        if !is_mt_code(ev) {
+            others.push((batch, ev));
+            continue;
+        }
+        if ev.is_code(&EV_ABS(ABS_MT_SLOT)) {
+            slot = ev.value;
+            continue;
+        }
+        let existing = touches.iter_mut().find(|t| t.slot == slot);
+        if ev.is_code(&EV_ABS(ABS_MT_TRACKING_ID)) && ev.value != -1 {
+            if existing.is_some() {
+                debug!("Skipped optimizing multi-touch events that reuse slot {slot}");
+                return false;
+            }
+            touches.push(SlotTouch {
+                slot,
+                start: batch,
+                end: None,
+                first: vec![ev.clone()],
+                last_x: None,
+                last_y: None,
+                release: None,
+            });
+            continue;
+        }
+        let Some(touch) = existing.filter(|t| t.end.is_none()) else {
+            debug!("Skipped optimizing multi-touch events with an inactive slot {slot}");
+            return false;
+        };
+        if ev.is_code(&EV_ABS(ABS_MT_TRACKING_ID)) {
+            touch.end = Some(batch);
+            touch.release = Some(ev.clone());
+            continue;
+        }
+        if touch.start == batch {
+            touch.first.push(ev.clone());
+        }
+        if is_x_coord(ev) {
+            touch.last_x = Some(ev.clone());
+        } else if is_y_coord(ev) {
+            touch.last_y = Some(ev.clone());
+        }
+    }
+
+    // Every contact must be released, and they must all be down at the same time, otherwise
+    // starting them all together would change what the gesture does
+    let Some(ends) = touches.iter().map(|t| t.end).collect::<Option<Vec<_>>>() else {
+        debug!("Skipped optimizing multi-touch events with a contact that wasn't released");
+        return false;
+    };
+    let all_down = touches.iter().map(|t| t.start).max().unwrap_or_default();
+    let first_up = ends.into_iter().min().unwrap_or_default();
+    if all_down >= first_up {
+        debug!("Skipped optimizing multi-touch events with contacts that don't overlap");
+        return false;
+    }
+
+    debug!("Optimizing {} multi-touch contacts", touches.len());
+    // The latest value of each non multi-touch event code as of the given batch
+    let state_at = |batch: usize| {
+        let mut state: Vec<InputEvent> = Vec::new();
+        for (_, ev) in others.iter().filter(|(b, _)| *b <= batch) {
+            match state.iter_mut().find(|s| s.event_code == ev.event_code) {
+                Some(existing) => existing.value = ev.value,
+                None => state.push((*ev).clone()),
+            }
+        }
+        state
+    };
+    let slot_event = |slot: i32| {
+        let mut ev = syn_report.clone();
+        ev.event_code = EV_ABS(ABS_MT_SLOT);
+        ev.value = slot;
+        ev
+    };
+
+    let mut first = Vec::new();
+    let mut penultimate = Vec::new();
+    let mut last = Vec::new();
+    for touch in &touches {
+        first.push(slot_event(touch.slot));
+        first.extend(touch.first.iter().cloned());
+
+        // They also don't like the last X or Y coordinate being identical to the first
+        penultimate.push(slot_event(touch.slot));
+        for (last_coord, is_coord) in [
+            (&touch.last_x, is_x_coord as fn(&InputEvent) -> bool),
+            (&touch.last_y, is_y_coord),
+        ] {
+            let Some(mut last_coord) = last_coord.clone() else {
+                continue;
+            };
+            if let Some(first_coord) = touch.first.iter().find(|ev| is_coord(ev)) {
+                if first_coord.value == last_coord.value {
+                    last_coord.value += 1;
+                }
+            }
+            penultimate.push(last_coord);
+        }
+
+        last.push(slot_event(touch.slot));
+        last.extend(touch.release.clone());
+    }
+
+    // Keys that were released before every contact was down don't need to be pressed
+    let start_state: Vec<InputEvent> = state_at(all_down)
+        .into_iter()
+        .filter(|ev| !(matches!(ev.event_code, EV_KEY(_)) && ev.value == 0))
+        .collect();
+    // Keep single-touch coordinates in sync with the first contact
+    let mut coords: Vec<InputEvent> = state_at(first_up - 1)
+        .into_iter()
+        .filter(|ev| is_x_coord(ev) || is_y_coord(ev))
+        .collect();
+    for coord in coords.iter_mut() {
+        if start_state
+            .iter()
+            .any(|ev| ev.event_code == coord.event_code && ev.value == coord.value)
+        {
+            coord.value += 1;
+        }
+    }
+    let end_state: Vec<InputEvent> = state_at(batch)
+        .into_iter()
+        .filter(|ev| !is_x_coord(ev) && !is_y_coord(ev))
+        .filter(|ev| {
+            match start_state.iter().find(|s| s.event_code == ev.event_code) {
+                Some(s) => s.value != ev.value,
+                // Don't release keys that were never pressed
+                None => !(matches!(ev.event_code, EV_KEY(_)) && ev.value == 0),
+            }
+        })
+        .collect();
+
+    first.extend(start_state);
+    first.push(syn_report.clone());
+    penultimate.extend(coords);
+    penultimate.push(syn_report.clone());
+    last.extend(end_state);
+    last.push(syn_report);
+
+    let time = events.first().unwrap().time;
+    events.clear();
+    events.extend(first.into_iter().chain(penultimate.into_iter().chain(last)));
+    events.first_mut().unwrap().time = time;
+    compress_batches(events, syn_gap);
     true
 }
 
@@ -294,13 +901,16 @@ mod tests {
     use chrono::Duration;
     use evdev_rs::enums::EventCode::{self, EV_ABS, EV_KEY, EV_SYN};
     use evdev_rs::enums::EV_ABS::*;
-    use evdev_rs::enums::EV_KEY::BTN_TOUCH;
-    use evdev_rs::enums::EV_SYN::{SYN_MT_REPORT, SYN_REPORT};
+    use evdev_rs::enums::EV_KEY::{BTN_TOOL_DOUBLETAP, BTN_TOOL_FINGER, BTN_TOUCH};
+    use evdev_rs::enums::EV_SYN::{SYN_DROPPED, SYN_MT_REPORT, SYN_REPORT};
     use evdev_rs::InputEvent;
+    use evdev_rs::ReadStatus::{self, Success, Sync};
     use evdev_rs::TimeVal;
 
-    use super::optimize_events;
+    use super::{optimize_events, DeviceEvents, DeviceFingerprint, ReadState, ReadTimeouts};
     use pretty_assertions::assert_eq;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration as StdDuration, Instant};
 
     #[test]
     fn optimize_events_second_x_missing() {
@@ -449,4 +1059,364 @@ mod tests {
         optimize_events(&mut events, syn_gap, max_duration);
         assert_eq!(expected, events);
     }
+
+    #[test]
+    // test that a pinch on a type B multi-touch device is reduced to three batches with every slot
+    fn optimize_events_pinch() {
+        let mut events = vec![
+            input_event(0, 100, EV_ABS(ABS_MT_SLOT), 0),
+            input_event(0, 110, EV_ABS(ABS_MT_TRACKING_ID), 20),
+            input_event(0, 120, EV_ABS(ABS_MT_POSITION_X), 300),
+            input_event(0, 130, EV_ABS(ABS_MT_POSITION_Y), 500),
+            input_event(0, 140, EV_KEY(BTN_TOUCH), 1),
+            input_event(0, 150, EV_KEY(BTN_TOOL_FINGER), 1),
+            input_event(0, 160, EV_SYN(SYN_REPORT), 0),
+            input_event(0, 10100, EV_ABS(ABS_MT_SLOT), 1),
+            input_event(0, 10110, EV_ABS(ABS_MT_TRACKING_ID), 21),
+            input_event(0, 10120, EV_ABS(ABS_MT_POSITION_X), 700),
+            input_event(0, 10130, EV_ABS(ABS_MT_POSITION_Y), 500),
+            input_event(0, 10140, EV_KEY(BTN_TOOL_FINGER), 0),
+            input_event(0, 10150, EV_KEY(BTN_TOOL_DOUBLETAP), 1),
+            input_event(0, 10160, EV_SYN(SYN_REPORT), 0),
+            input_event(0, 20100, EV_ABS(ABS_MT_SLOT), 0),
+            input_event(0, 20110, EV_ABS(ABS_MT_POSITION_X), 280),
+            input_event(0, 20120, EV_ABS(ABS_MT_SLOT), 1),
+            input_event(0, 20130, EV_ABS(ABS_MT_POSITION_X), 720),
+            input_event(0, 20140, EV_SYN(SYN_REPORT), 0),
+            input_event(0, 30100, EV_ABS(ABS_MT_SLOT), 0),
+            input_event(0, 30110, EV_ABS(ABS_MT_POSITION_X), 250),
+            input_event(0, 30120, EV_ABS(ABS_MT_POSITION_Y), 501),
+            input_event(0, 30130, EV_ABS(ABS_MT_SLOT), 1),
+            input_event(0, 30140, EV_ABS(ABS_MT_POSITION_X), 750),
+            input_event(0, 30150, EV_SYN(SYN_REPORT), 0),
+            input_event(0, 40100, EV_ABS(ABS_MT_SLOT), 0),
+            input_event(0, 40110, EV_ABS(ABS_MT_TRACKING_ID), -1),
+            input_event(0, 40120, EV_ABS(ABS_MT_SLOT), 1),
+            input_event(0, 40130, EV_ABS(ABS_MT_TRACKING_ID), -1),
+            input_event(0, 40140, EV_KEY(BTN_TOUCH), 0),
+            input_event(0, 40150, EV_KEY(BTN_TOOL_DOUBLETAP), 0),
+            input_event(0, 40160, EV_SYN(SYN_REPORT), 0),
+        ];
+        let expected = vec![
+            input_event(0, 100, EV_ABS(ABS_MT_SLOT), 0),
+            input_event(0, 100, EV_ABS(ABS_MT_TRACKING_ID), 20),
+            input_event(0, 100, EV_ABS(ABS_MT_POSITION_X), 300),
+            input_event(0, 100, EV_ABS(ABS_MT_POSITION_Y), 500),
+            input_event(0, 100, EV_ABS(ABS_MT_SLOT), 1),
+            input_event(0, 100, EV_ABS(ABS_MT_TRACKING_ID), 21),
+            input_event(0, 100, EV_ABS(ABS_MT_POSITION_X), 700),
+            input_event(0, 100, EV_ABS(ABS_MT_POSITION_Y), 500),
+            input_event(0, 100, EV_KEY(BTN_TOUCH), 1),
+            input_event(0, 100, EV_KEY(BTN_TOOL_DOUBLETAP), 1),
+            input_event(0, 100, EV_SYN(SYN_REPORT), 0),
+            input_event(0, 101, EV_ABS(ABS_MT_SLOT), 0),
+            input_event(0, 101, EV_ABS(ABS_MT_POSITION_X), 250),
+            input_event(0, 101, EV_ABS(ABS_MT_POSITION_Y), 501),
+            input_event(0, 101, EV_ABS(ABS_MT_SLOT), 1),
+            input_event(0, 101, EV_ABS(ABS_MT_POSITION_X), 750),
+            input_event(0, 101, EV_ABS(ABS_MT_POSITION_Y), 501),
+            input_event(0, 101, EV_SYN(SYN_REPORT), 0),
+            input_event(0, 102, EV_ABS(ABS_MT_SLOT), 0),
+            input_event(0, 102, EV_ABS(ABS_MT_TRACKING_ID), -1),
+            input_event(0, 102, EV_ABS(ABS_MT_SLOT), 1),
+            input_event(0, 102, EV_ABS(ABS_MT_TRACKING_ID), -1),
+            input_event(0, 102, EV_KEY(BTN_TOUCH), 0),
+            input_event(0, 102, EV_KEY(BTN_TOOL_DOUBLETAP), 0),
+            input_event(0, 102, EV_SYN(SYN_REPORT), 0),
+        ];
+        let syn_gap = Duration::microseconds(1);
+        let max_duration = Duration::milliseconds(500);
+        assert!(optimize_events(&mut events, syn_gap, max_duration));
+        assert_eq!(expected, events);
+    }
+
+    #[test]
+    // test a two-finger swipe where both fingers land together and the first only moves along Y
+    fn optimize_events_two_finger_swipe() {
+        let mut events = vec![
+            input_event(0, 100, EV_ABS(ABS_MT_SLOT), 0),
+            input_event(0, 110, EV_ABS(ABS_MT_TRACKING_ID), 5),
+            input_event(0, 120, EV_ABS(ABS_MT_TOUCH_MAJOR), 2),
+            input_event(0, 130, EV_ABS(ABS_MT_POSITION_X), 400),
+            input_event(0, 140, EV_ABS(ABS_MT_POSITION_Y), 900),
+            input_event(0, 150, EV_ABS(ABS_MT_SLOT), 1),
+            input_event(0, 160, EV_ABS(ABS_MT_TRACKING_ID), 6),
+            input_event(0, 170, EV_ABS(ABS_MT_TOUCH_MAJOR), 2),
+            input_event(0, 180, EV_ABS(ABS_MT_POSITION_X), 600),
+            input_event(0, 190, EV_ABS(ABS_MT_POSITION_Y), 900),
+            input_event(0, 200, EV_KEY(BTN_TOUCH), 1),
+            input_event(0, 210, EV_SYN(SYN_REPORT), 0),
+            input_event(0, 10100, EV_ABS(ABS_MT_SLOT), 0),
+            input_event(0, 10110, EV_ABS(ABS_MT_POSITION_Y), 700),
+            input_event(0, 10120, EV_ABS(ABS_MT_SLOT), 1),
+            input_event(0, 10130, EV_ABS(ABS_MT_POSITION_X), 605),
+            input_event(0, 10140, EV_ABS(ABS_MT_POSITION_Y), 702),
+            input_event(0, 10150, EV_SYN(SYN_REPORT), 0),
+            input_event(0, 20100, EV_ABS(ABS_MT_SLOT), 0),
+            input_event(0, 20110, EV_ABS(ABS_MT_TRACKING_ID), -1),
+            input_event(0, 20120, EV_SYN(SYN_REPORT), 0),
+            input_event(0, 30100, EV_ABS(ABS_MT_SLOT), 1),
+            input_event(0, 30110, EV_ABS(ABS_MT_TRACKING_ID), -1),
+            input_event(0, 30120, EV_KEY(BTN_TOUCH), 0),
+            input_event(0, 30130, EV_SYN(SYN_REPORT), 0),
+        ];
+        let expected = vec![
+            input_event(0, 100, EV_ABS(ABS_MT_SLOT), 0),
+            input_event(0, 100, EV_ABS(ABS_MT_TRACKING_ID), 5),
+            input_event(0, 100, EV_ABS(ABS_MT_TOUCH_MAJOR), 2),
+            input_event(0, 100, EV_ABS(ABS_MT_POSITION_X), 400),
+            input_event(0, 100, EV_ABS(ABS_MT_POSITION_Y), 900),
+            input_event(0, 100, EV_ABS(ABS_MT_SLOT), 1),
+            input_event(0, 100, EV_ABS(ABS_MT_TRACKING_ID), 6),
+            input_event(0, 100, EV_ABS(ABS_MT_TOUCH_MAJOR), 2),
+            input_event(0, 100, EV_ABS(ABS_MT_POSITION_X), 600),
+            input_event(0, 100, EV_ABS(ABS_MT_POSITION_Y), 900),
+            input_event(0, 100, EV_KEY(BTN_TOUCH), 1),
+            input_event(0, 100, EV_SYN(SYN_REPORT), 0),
+            input_event(0, 101, EV_ABS(ABS_MT_SLOT), 0),
+            input_event(0, 101, EV_ABS(ABS_MT_POSITION_X), 401),
+            input_event(0, 101, EV_ABS(ABS_MT_POSITION_Y), 700),
+            input_event(0, 101, EV_ABS(ABS_MT_SLOT), 1),
+            input_event(0, 101, EV_ABS(ABS_MT_POSITION_X), 605),
+            input_event(0, 101, EV_ABS(ABS_MT_POSITION_Y), 702),
+            input_event(0, 101, EV_SYN(SYN_REPORT), 0),
+            input_event(0, 102, EV_ABS(ABS_MT_SLOT), 0),
+            input_event(0, 102, EV_ABS(ABS_MT_TRACKING_ID), -1),
+            input_event(0, 102, EV_ABS(ABS_MT_SLOT), 1),
+            input_event(0, 102, EV_ABS(ABS_MT_TRACKING_ID), -1),
+            input_event(0, 102, EV_KEY(BTN_TOUCH), 0),
+            input_event(0, 102, EV_SYN(SYN_REPORT), 0),
+        ];
+        let syn_gap = Duration::microseconds(1);
+        let max_duration = Duration::milliseconds(500);
+        assert!(optimize_events(&mut events, syn_gap, max_duration));
+        assert_eq!(expected, events);
+    }
+
+    #[test]
+    // test that two taps with different fingers aren't merged into a single gesture
+    fn optimize_events_multi_touch_not_overlapping() {
+        let mut events = vec![
+            input_event(0, 100, EV_ABS(ABS_MT_SLOT), 0),
+            input_event(0, 110, EV_ABS(ABS_MT_TRACKING_ID), 5),
+            input_event(0, 120, EV_ABS(ABS_MT_POSITION_X), 400),
+            input_event(0, 130, EV_ABS(ABS_MT_POSITION_Y), 900),
+            input_event(0, 140, EV_KEY(BTN_TOUCH), 1),
+            input_event(0, 150, EV_SYN(SYN_REPORT), 0),
+            input_event(0, 10100, EV_ABS(ABS_MT_TRACKING_ID), -1),
+            input_event(0, 10110, EV_KEY(BTN_TOUCH), 0),
+            input_event(0, 10120, EV_SYN(SYN_REPORT), 0),
+            input_event(0, 20100, EV_ABS(ABS_MT_SLOT), 1),
+            input_event(0, 20110, EV_ABS(ABS_MT_TRACKING_ID), 6),
+            input_event(0, 20120, EV_ABS(ABS_MT_POSITION_X), 600),
+            input_event(0, 20130, EV_ABS(ABS_MT_POSITION_Y), 900),
+            input_event(0, 20140, EV_SYN(SYN_REPORT), 0),
+            input_event(0, 30100, EV_ABS(ABS_MT_TRACKING_ID), -1),
+            input_event(0, 30110, EV_SYN(SYN_REPORT), 0),
+        ];
+        let expected = events.clone();
+        let syn_gap = Duration::microseconds(1);
+        let max_duration = Duration::milliseconds(500);
+        assert!(!optimize_events(&mut events, syn_gap, max_duration));
+        assert_eq!(expected, events);
+    }
+
+    fn read_events(read: Vec<(ReadStatus, InputEvent)>) -> DeviceEvents {
+        let mut events = DeviceEvents::default();
+        for (status, event) in read {
+            events.push(status, event);
+        }
+        events
+    }
+
+    #[test]
+    fn resync_discards_incomplete_report() {
+        let events = read_events(vec![
+            (Success, input_event(0, 100, EV_ABS(ABS_X), 522)),
+            (Success, input_event(0, 110, EV_ABS(ABS_Y), 51)),
+            (Success, input_event(0, 120, EV_KEY(BTN_TOUCH), 1)),
+            (Success, input_event(0, 130, EV_SYN(SYN_REPORT), 0)),
+            (Success, input_event(0, 200, EV_ABS(ABS_X), 530)),
+            (Sync, input_event(0, 300, EV_SYN(SYN_DROPPED), 0)),
+            (Sync, input_event(0, 300, EV_ABS(ABS_X), 600)),
+            (Sync, input_event(0, 300, EV_ABS(ABS_Y), 80)),
+            (Sync, input_event(0, 300, EV_KEY(BTN_TOUCH), 0)),
+            (Sync, input_event(0, 300, EV_SYN(SYN_REPORT), 0)),
+        ]);
+        let expected = DeviceEvents {
+            events: vec![
+                input_event(0, 100, EV_ABS(ABS_X), 522),
+                input_event(0, 110, EV_ABS(ABS_Y), 51),
+                input_event(0, 120, EV_KEY(BTN_TOUCH), 1),
+                input_event(0, 130, EV_SYN(SYN_REPORT), 0),
+                input_event(0, 300, EV_ABS(ABS_X), 600),
+                input_event(0, 300, EV_ABS(ABS_Y), 80),
+                input_event(0, 300, EV_KEY(BTN_TOUCH), 0),
+                input_event(0, 300, EV_SYN(SYN_REPORT), 0),
+            ],
+            resynced: true,
+        };
+        assert_eq!(expected, events);
+        assert!(events.check_resync().is_ok());
+    }
+
+    #[test]
+    fn resync_touch_still_held() {
+        // The release was lost and the timeout was reached before another one was read
+        let events = read_events(vec![
+            (Success, input_event(0, 100, EV_ABS(ABS_X), 522)),
+            (Success, input_event(0, 110, EV_ABS(ABS_Y), 51)),
+            (Success, input_event(0, 120, EV_KEY(BTN_TOUCH), 1)),
+            (Success, input_event(0, 130, EV_SYN(SYN_REPORT), 0)),
+            (Sync, input_event(0, 300, EV_SYN(SYN_DROPPED), 0)),
+            (Sync, input_event(0, 300, EV_ABS(ABS_X), 600)),
+            (Sync, input_event(0, 300, EV_SYN(SYN_REPORT), 0)),
+        ]);
+        assert!(events.resynced);
+        assert!(events.check_resync().is_err());
+    }
+
+    #[test]
+    fn resync_multi_touch_slots() {
+        let mut read = vec![
+            (Success, input_event(0, 100, EV_ABS(ABS_MT_SLOT), 0)),
+            (Success, input_event(0, 100, EV_ABS(ABS_MT_TRACKING_ID), 10)),
+            (Success, input_event(0, 100, EV_ABS(ABS_MT_POSITION_X), 300)),
+            (Success, input_event(0, 100, EV_ABS(ABS_MT_POSITION_Y), 400)),
+            (Success, input_event(0, 100, EV_ABS(ABS_MT_SLOT), 1)),
+            (Success, input_event(0, 100, EV_ABS(ABS_MT_TRACKING_ID), 11)),
+            (Success, input_event(0, 100, EV_ABS(ABS_MT_POSITION_X), 500)),
+            (Success, input_event(0, 100, EV_ABS(ABS_MT_POSITION_Y), 600)),
+            (Success, input_event(0, 100, EV_SYN(SYN_REPORT), 0)),
+            (Sync, input_event(0, 200, EV_SYN(SYN_DROPPED), 0)),
+            (Sync, input_event(0, 200, EV_ABS(ABS_MT_SLOT), 0)),
+            (Sync, input_event(0, 200, EV_ABS(ABS_MT_TRACKING_ID), -1)),
+            (Sync, input_event(0, 200, EV_SYN(SYN_REPORT), 0)),
+        ];
+        // The second finger is still down
+        let events = read_events(read.clone());
+        assert!(events.check_resync().is_err());
+
+        read.extend([
+            (Success, input_event(0, 300, EV_ABS(ABS_MT_SLOT), 1)),
+            (Success, input_event(0, 300, EV_ABS(ABS_MT_TRACKING_ID), -1)),
+            (Success, input_event(0, 300, EV_SYN(SYN_REPORT), 0)),
+        ]);
+        let events = read_events(read);
+        assert!(events.check_resync().is_ok());
+    }
+
+    #[test]
+    fn resync_without_complete_report() {
+        let events = read_events(vec![
+            (Success, input_event(0, 100, EV_ABS(ABS_X), 522)),
+            (Sync, input_event(0, 200, EV_SYN(SYN_DROPPED), 0)),
+        ]);
+        assert_eq!(Vec::<InputEvent>::new(), events.events);
+        assert!(events.check_resync().is_err());
+    }
+
+    #[test]
+    fn read_timeouts() {
+        let start = Instant::now();
+        let ms = StdDuration::from_millis;
+        let timeouts = ReadTimeouts {
+            start,
+            no_input: ms(5000),
+            new_event: ms(250),
+        };
+        let mut idle = ReadState::default();
+        let mut touched = ReadState {
+            last_event_time: Some(start + ms(1000)),
+            done: false,
+        };
+
+        // A device with input stops sooner than one waiting for its first event
+        assert_eq!(
+            Some(start + ms(1250)),
+            timeouts.next_deadline([idle, touched].iter())
+        );
+        assert!(!timeouts.expire(&mut touched, start + ms(1249)));
+        assert!(timeouts.expire(&mut touched, start + ms(1250)));
+        assert!(touched.done);
+        // It only finishes once
+        assert!(!timeouts.expire(&mut touched, start + ms(6000)));
+
+        // The other device is still read until its own timeout
+        assert_eq!(
+            Some(start + ms(5000)),
+            timeouts.next_deadline([idle, touched].iter())
+        );
+        assert!(!timeouts.expire(&mut idle, start + ms(4999)));
+        assert!(timeouts.expire(&mut idle, start + ms(5000)));
+        assert_eq!(None, timeouts.next_deadline([idle, touched].iter()));
+    }
+
+    fn fingerprint(name: &str, capabilities: &[&str]) -> DeviceFingerprint {
+        DeviceFingerprint {
+            name: Some(name.to_string()),
+            phys: Some("input/ts".to_string()),
+            vendor_id: 0,
+            product_id: 0,
+            bustype: 24,
+            capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn choose_fingerprinted_device() {
+        let recorded = fingerprint("cyttsp5_mt", &["ABS_MT_POSITION_X", "ABS_MT_POSITION_Y"]);
+        let last_path = Path::new("/dev/input/event1");
+        let candidate =
+            |path: &str, fingerprint: DeviceFingerprint| (PathBuf::from(path), fingerprint);
+        let chosen = |candidates: Vec<(PathBuf, DeviceFingerprint)>| {
+            recorded
+                .choose(candidates, last_path)
+                .map(|(path, _)| path.display().to_string())
+        };
+
+        // A device that moved is still found
+        assert_eq!(
+            Some("/dev/input/event2".to_string()),
+            chosen(vec![
+                candidate(
+                    "/dev/input/event0",
+                    fingerprint("gpio-keys", &["KEY_POWER"])
+                ),
+                candidate("/dev/input/event2", recorded.clone()),
+            ])
+        );
+        // A driver update that changed the supported codes doesn't hide the device
+        assert_eq!(
+            Some("/dev/input/event1".to_string()),
+            chosen(vec![candidate(
+                "/dev/input/event1",
+                fingerprint("cyttsp5_mt", &["ABS_MT_POSITION_X"])
+            )])
+        );
+        // But the codes still decide between otherwise identical devices, before the path
+        assert_eq!(
+            Some("/dev/input/event3".to_string()),
+            chosen(vec![
+                candidate(
+                    "/dev/input/event1",
+                    fingerprint("cyttsp5_mt", &["ABS_MT_POSITION_X"])
+                ),
+                candidate("/dev/input/event3", recorded.clone()),
+            ])
+        );
+        // The last path decides between identical devices
+        assert_eq!(
+            Some("/dev/input/event1".to_string()),
+            chosen(vec![
+                candidate("/dev/input/event0", recorded.clone()),
+                candidate("/dev/input/event1", recorded.clone()),
+                candidate("/dev/input/event2", recorded.clone()),
+            ])
+        );
+        // A different device with the same codes isn't a match
+        let mut other = recorded.clone();
+        other.phys = Some("input/pen".to_string());
+        assert_eq!(None, chosen(vec![candidate("/dev/input/event1", other)]));
+    }
 }
@@ -0,0 +1,312 @@
+//! Endpoints to inspect input devices and monitor the events they emit, for debugging recordings
+//! on models that behave differently
+use super::devices::{DeviceLocks, InputDevices};
+use super::input::{is_touch_device, supported_codes, wait_for_events, InputDevice};
+use super::{parse_timeval, ActionMsg};
+use crate::errors::AppError;
+use crate::server::AppState;
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+use anyhow::anyhow;
+use axum::{
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use chrono::{DateTime, Duration, Utc};
+use evdev_rs::util::{event_code_to_int, int_to_event_type};
+use evdev_rs::{DeviceWrapper, InputEvent, ReadFlag, ReadStatus};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, error, warn};
+
+/// The longest a client can monitor a device for in seconds
+const MAX_MONITOR_DURATION: u64 = 60;
+/// How often to check if a monitor client has disconnected when no events are received
+const MONITOR_CHECK_INTERVAL: Duration = Duration::milliseconds(500);
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/input/devices", get(list_devices))
+        .route("/input/monitor", get(monitor_handler))
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub path: PathBuf,
+    pub name: Option<String>,
+    pub phys: Option<String>,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub bustype: u16,
+    pub is_touch: bool,
+    /// The supported event codes grouped by event type
+    pub codes: BTreeMap<String, Vec<String>>,
+    pub abs_ranges: BTreeMap<String, AbsRange>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AbsRange {
+    pub minimum: i32,
+    pub maximum: i32,
+    pub fuzz: i32,
+    pub flat: i32,
+    pub resolution: i32,
+    pub value: i32,
+}
+
+impl Display for DeviceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(name) = &self.name {
+            write!(f, " ({})", name)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&InputDevice> for DeviceInfo {
+    fn from(d: &InputDevice) -> Self {
+        let mut codes: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let mut abs_ranges = BTreeMap::new();
+        for code in supported_codes(d) {
+            if let Some(info) = d.evdev.abs_info(&code) {
+                let range = AbsRange {
+                    minimum: info.minimum,
+                    maximum: info.maximum,
+                    fuzz: info.fuzz,
+                    flat: info.flat,
+                    resolution: info.resolution,
+                    value: info.value,
+                };
+                abs_ranges.insert(code.to_string(), range);
+            }
+            let ev_type = int_to_event_type(event_code_to_int(&code).0)
+                .map_or("UNKNOWN".into(), |t| t.to_string());
+            codes.entry(ev_type).or_default().push(code.to_string());
+        }
+        Self {
+            path: d.path.clone(),
+            name: d.name.clone(),
+            phys: d.evdev.phys().map(|s| s.to_string()),
+            vendor_id: d.evdev.vendor_id(),
+            product_id: d.evdev.product_id(),
+            bustype: d.evdev.bustype(),
+            is_touch: is_touch_device(d),
+            codes,
+            abs_ranges,
+        }
+    }
+}
+
+/// The devices shared with the ActionManager, and the locks that keep recordings and plays from
+/// using them at the same time
+async fn input_devices(state: &AppState) -> anyhow::Result<(Arc<dyn InputDevices>, DeviceLocks)> {
+    let (resp, rx) = oneshot::channel();
+    state.tx.send(ActionMsg::GetInputDevices { resp }).await?;
+    Ok(rx.await?)
+}
+
+/// Listing opens every device, which would hold up other requests
+async fn list(state: &AppState, devices: Arc<dyn InputDevices>) -> anyhow::Result<Vec<DeviceInfo>> {
+    let use_by_path = state.config().user.setup.use_by_path;
+    tokio::task::spawn_blocking(move || devices.list(use_by_path)).await?
+}
+
+async fn list_devices(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let (devices, _) = input_devices(&state).await?;
+    Ok(Json(list(&state, devices).await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct MonitorQuery {
+    /// The path of the device to monitor, as listed by /input/devices
+    path: PathBuf,
+    /// How long to monitor the device for in seconds
+    #[serde(default = "default_monitor_duration")]
+    duration: u64,
+}
+
+fn default_monitor_duration() -> u64 {
+    10
+}
+
+/// A decoded event sent to the client as JSON
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MonitorEvent {
+    pub time: DateTime<Utc>,
+    pub event_type: String,
+    pub code: String,
+    pub value: i32,
+    /// Whether the kernel's buffer overflowed and the event was read while resyncing
+    pub resync: bool,
+}
+
+impl MonitorEvent {
+    pub fn new(event: &InputEvent, status: ReadStatus) -> Self {
+        Self {
+            time: parse_timeval(event.time),
+            event_type: event
+                .event_type()
+                .map_or("UNKNOWN".into(), |t| t.to_string()),
+            code: event.event_code.to_string(),
+            value: event.value,
+            resync: status == ReadStatus::Sync,
+        }
+    }
+}
+
+async fn monitor_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    Query(query): Query<MonitorQuery>,
+) -> Result<Response, AppError> {
+    debug!("Received request to monitor {}", query.path.display());
+    let (devices, locks) = input_devices(&state).await?;
+    // Only allow input devices to be opened, rather than any file the client asks for
+    if !list(&state, devices.clone())
+        .await?
+        .iter()
+        .any(|d| d.path == query.path)
+    {
+        return Err(anyhow!("{} isn't an input device", query.path.display()).into());
+    }
+    let duration = Duration::seconds(query.duration.min(MAX_MONITOR_DURATION) as i64);
+    Ok(ws.on_upgrade(move |socket| monitor(socket, devices, locks, query.path, duration)))
+}
+
+async fn monitor(
+    mut socket: WebSocket,
+    devices: Arc<dyn InputDevices>,
+    locks: DeviceLocks,
+    path: PathBuf,
+    duration: Duration,
+) {
+    let (tx, mut rx) = mpsc::channel(256);
+    thread::spawn(move || {
+        // Wait for any recording that has the device grabbed, and hold plays to it until the
+        // monitor finishes
+        let _guard = locks.lock([path.clone()]);
+        if let Err(e) = devices.monitor(&path, duration, tx) {
+            error!("Failed to monitor {}. {e:#}", path.display());
+        }
+    });
+    loop {
+        tokio::select! {
+            event = rx.recv() => {
+                let Some(event) = event else {
+                    // The reader finished
+                    break;
+                };
+                let Ok(json) = serde_json::to_string(&event) else {
+                    error!("Failed to serialize {event:?}");
+                    continue;
+                };
+                if socket.send(Message::Text(json)).await.is_err() {
+                    debug!("Monitor client disconnected");
+                    return;
+                }
+            }
+            msg = socket.recv() => {
+                if ends_monitor(&msg) {
+                    debug!("Monitor client disconnected");
+                    return;
+                }
+            }
+        }
+    }
+    if let Err(e) = socket.send(Message::Close(None)).await {
+        warn!("Failed to close monitor WebSocket. {e}");
+    }
+}
+
+/// Whether a message from the client means it's gone. Anything else, like keepalives sent by
+/// proxies, is ignored
+fn ends_monitor(msg: &Option<Result<Message, axum::Error>>) -> bool {
+    matches!(msg, None | Some(Err(_)) | Some(Ok(Message::Close(_))))
+}
+
+pub(super) fn read_events(device: InputDevice, tx: mpsc::Sender<MonitorEvent>, duration: Duration) {
+    debug!(
+        "Monitoring events from {device} for {}s",
+        duration.num_seconds()
+    );
+    let stop_at = Utc::now() + duration;
+    while Utc::now() < stop_at && !tx.is_closed() {
+        if !device.evdev.has_event_pending() {
+            // Wake up periodically to check if the client has disconnected
+            let timeout = (stop_at - Utc::now()).clamp(Duration::zero(), MONITOR_CHECK_INTERVAL);
+            match wait_for_events(&[&device], timeout.to_std().unwrap_or_default()) {
+                Ok(ready) if ready[0] && !device.evdev.has_event_pending() => {
+                    warn!("{device} stopped responding");
+                    break;
+                }
+                Ok(_) => continue,
+                Err(e) => {
+                    error!("{e}");
+                    break;
+                }
+            }
+        }
+        match device
+            .evdev
+            .next_event(ReadFlag::NORMAL | ReadFlag::BLOCKING)
+        {
+            Ok((status, event)) => {
+                if tx.blocking_send(MonitorEvent::new(&event, status)).is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                error!("Failed to read event from {device}. {e}");
+                break;
+            }
+        }
+    }
+    debug!("Stopped monitoring events from {device}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evdev_rs::enums::EventCode::EV_ABS;
+    use evdev_rs::enums::EV_ABS::ABS_MT_POSITION_X;
+    use evdev_rs::TimeVal;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn decode_monitor_event() {
+        let event = InputEvent {
+            time: TimeVal::new(1705963814, 69621),
+            event_code: EV_ABS(ABS_MT_POSITION_X),
+            value: 522,
+        };
+        let decoded = MonitorEvent::new(&event, ReadStatus::Sync);
+        assert_eq!("EV_ABS", decoded.event_type);
+        assert_eq!("ABS_MT_POSITION_X", decoded.code);
+        assert_eq!(522, decoded.value);
+        assert!(decoded.resync);
+        assert_eq!(
+            "2024-01-22T22:50:14.069621Z",
+            decoded
+                .time
+                .to_rfc3339_opts(chrono::SecondsFormat::Micros, true)
+        );
+    }
+
+    #[test]
+    fn only_close_ends_monitor() {
+        assert!(ends_monitor(&None));
+        assert!(ends_monitor(&Some(Ok(Message::Close(None)))));
+        assert!(!ends_monitor(&Some(Ok(Message::Ping(vec![1])))));
+        assert!(!ends_monitor(&Some(Ok(Message::Pong(vec![1])))));
+        assert!(!ends_monitor(&Some(Ok(Message::Text("keepalive".into())))));
+    }
+}
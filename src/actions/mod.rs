@@ -2,20 +2,32 @@ pub use self::action::*;
 use crate::{errors::AppError, server::AppState};
 use anyhow::Result;
 use axum::{
-    extract::{Path as AxumPath, State},
+    extract::{ConnectInfo, Path as AxumPath, Query, State},
     response::IntoResponse,
     routing::{delete, get, post, put},
     Json, Router,
 };
 use chrono::{Duration, Utc};
+use player::PlayJob;
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DurationMilliSeconds};
+use std::net::SocketAddr;
+use synthetic::SyntheticActionOptions;
 use tokio::sync::oneshot;
 use tracing::debug;
 
 mod action;
 pub mod arbitrary;
-mod input;
+pub mod benchmark;
+pub mod calibration;
+pub mod devices;
+pub mod history;
+pub mod inject;
+pub mod input;
+pub mod inspect;
+pub mod player;
+pub mod recording;
+pub mod synthetic;
 
 pub fn routes() -> Router<AppState> {
     Router::new()
@@ -24,19 +36,37 @@ pub fn routes() -> Router<AppState> {
         // and anything that users integrated with the original version
         .route("/actions", get(get_actions))
         .route("/actions", post(record_action))
+        .route("/actions/cancel", post(cancel_plays))
+        .route("/actions/export", get(export_actions))
+        .route("/actions/generate", post(generate_action))
+        .route("/actions/import", post(import_actions))
         .route("/actions/:path_segment", get(play_action_handler))
         .route("/actions/:path_segment", delete(delete_action))
         .route("/actions/:path_segment", put(update_action))
+        .route("/actions/:path_segment/rename", post(rename_action))
         .route("/left", get(prev_page))
         .route("/right", get(next_page))
+        .merge(recording::routes())
+        .merge(history::routes())
+        .merge(benchmark::routes())
+        .merge(calibration::routes())
 }
 
-async fn next_page(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
-    play_action("next-page".into(), &state).await
+/// The address of the client, which isn't known when the router is used without a server
+type Client = Option<ConnectInfo<SocketAddr>>;
+
+async fn next_page(
+    State(state): State<AppState>,
+    client: Client,
+) -> Result<impl IntoResponse, AppError> {
+    play_action("next-page".into(), PlayQuery::default(), client, &state).await
 }
 
-async fn prev_page(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
-    play_action("prev-page".into(), &state).await
+async fn prev_page(
+    State(state): State<AppState>,
+    client: Client,
+) -> Result<impl IntoResponse, AppError> {
+    play_action("prev-page".into(), PlayQuery::default(), client, &state).await
 }
 
 async fn get_actions(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
@@ -46,39 +76,100 @@ async fn get_actions(State(state): State<AppState>) -> Result<impl IntoResponse,
     Ok(Json(actions))
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct PlayQuery {
+    /// Overrides the speed of the action for this request only
+    speed: Option<f64>,
+    /// How many times to play the action, e.g. to skip several pages
+    count: u32,
+    /// Whether the play was triggered by a timer rather than the reader, so page turns are left
+    /// out of the measured reading pace
+    automatic: bool,
+}
+
+impl Default for PlayQuery {
+    fn default() -> Self {
+        Self {
+            speed: None,
+            count: 1,
+            automatic: false,
+        }
+    }
+}
+
 async fn play_action_handler(
     State(state): State<AppState>,
     AxumPath(path_segment): AxumPath<String>,
+    Query(query): Query<PlayQuery>,
+    client: Client,
 ) -> Result<impl IntoResponse, AppError> {
-    play_action(path_segment, &state).await
+    play_action(path_segment, query, client, &state).await
 }
 
 async fn play_action(
     path_segment: String,
+    query: PlayQuery,
+    client: Client,
     state: &AppState,
 ) -> Result<impl IntoResponse, AppError> {
     let start = Utc::now();
     debug!("Received request to play action {path_segment}");
     let (tx, rx) = oneshot::channel();
-    let msg = ActionMsg::Play {
+    let msg = ActionMsg::Play(PlayJob {
         path_segment: path_segment.clone(),
+        speed: query.speed,
+        count: query.count,
+        generation: state.plays.generation(),
+        client: client.map(|ConnectInfo(addr)| addr.ip()),
+        requested: start,
         resp: tx,
-    };
+    });
     state.tx.send(msg).await?;
-    rx.await??;
-    debug!("Successfully played action");
+    let result = rx.await??;
+    debug!("Successfully played action {} times", result.played);
+    let forward = match path_segment.as_str() {
+        "next-page" if result.played > 0 => Some(true),
+        "prev-page" if result.played > 0 => Some(false),
+        _ => None,
+    };
+    if let Some(forward) = forward {
+        // Saving the session writes to a file, which shouldn't hold up the runtime's only thread
+        let reading = state.reading.clone();
+        tokio::task::spawn_blocking(move || {
+            reading.page_turned(forward, result.played, query.automatic)
+        })
+        .await?;
+    }
     Ok(Json(PlayActionResponse {
         path_segment,
         time_taken: Utc::now() - start,
+        played: result.played,
+        drift: result.drift,
+        effect: result.effect,
     }))
 }
 
+/// Drop plays that are waiting for the player and stop repeating the current one
+async fn cancel_plays(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    debug!("Received request to cancel pending plays");
+    state.plays.cancel();
+    Ok(())
+}
+
 #[serde_with::serde_as]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct PlayActionResponse {
     path_segment: String,
     #[serde_as(as = "DurationMilliSeconds<i64>")]
     time_taken: Duration,
+    /// How many times the action was played, which is less than requested if it was cancelled
+    played: u32,
+    /// How far the last play strayed from the recorded timing
+    drift: PlaybackDrift,
+    /// Whether the screen changed after the last play. Only included for verified actions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    effect: Option<bool>,
 }
 
 async fn delete_action(
@@ -120,9 +211,77 @@ async fn record_action(
     Json(opts): Json<RecordActionOptions>,
 ) -> Result<impl IntoResponse, AppError> {
     debug!("Received request to record action: {:#?}", &opts);
+    let job = recording::prepare(&state, opts).await?;
+    let response = recording::run(&state, job, recording::RecordingControl::default()).await?;
+    debug!("Successfully recorded action");
+    Ok(Json(response))
+}
+
+async fn generate_action(
+    State(state): State<AppState>,
+    Json(opts): Json<SyntheticActionOptions>,
+) -> Result<impl IntoResponse, AppError> {
+    debug!("Received request to generate action: {:#?}", &opts);
+    let input = state.config().user.arbitrary_input.clone();
     let (tx, rx) = oneshot::channel();
-    state.tx.send(ActionMsg::Record { opts, resp: tx }).await?;
+    state
+        .tx
+        .send(ActionMsg::Generate {
+            opts,
+            input,
+            resp: tx,
+        })
+        .await?;
     let response = rx.await??;
-    debug!("Successfully recorded action");
+    debug!("Successfully generated action");
     Ok(Json(response))
 }
+
+async fn rename_action(
+    State(state): State<AppState>,
+    AxumPath(path_segment): AxumPath<String>,
+    Json(opts): Json<RenameActionOptions>,
+) -> Result<impl IntoResponse, AppError> {
+    debug!("Received request to rename action {path_segment}");
+    let (tx, rx) = oneshot::channel();
+    let msg = ActionMsg::Rename {
+        path_segment,
+        opts,
+        resp: tx,
+    };
+    state.tx.send(msg).await?;
+    let new_path_segment = rx.await??;
+    debug!("Successfully renamed action");
+    Ok(Json(new_path_segment))
+}
+
+async fn export_actions(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    debug!("Received request to export actions");
+    let (tx, rx) = oneshot::channel();
+    state.tx.send(ActionMsg::Export { resp: tx }).await?;
+    Ok(Json(rx.await?))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ImportQuery {
+    overwrite: bool,
+}
+
+async fn import_actions(
+    State(state): State<AppState>,
+    Query(query): Query<ImportQuery>,
+    Json(export): Json<ActionsExport>,
+) -> Result<impl IntoResponse, AppError> {
+    debug!("Received request to import actions");
+    let (tx, rx) = oneshot::channel();
+    let msg = ActionMsg::Import {
+        export: Box::new(export),
+        overwrite: query.overwrite,
+        resp: tx,
+    };
+    state.tx.send(msg).await?;
+    let imported = rx.await??;
+    debug!("Successfully imported {} actions", imported.len());
+    Ok(Json(imported))
+}
@@ -0,0 +1,440 @@
+//! Plays actions on their own thread so the ActionManager can keep answering requests
+use super::benchmark::{BenchmarkJob, BenchmarkOptions, BenchmarkRun};
+use super::devices::{DeviceLocks, InputDevices};
+use super::history::{PlayHistory, PlayRecord};
+use super::inject::Injector;
+use super::{get_recording, PlayCanceller, PlayResult, PlaybackDrift, SharedActions};
+use crate::fbink::{RotationSource, ScreenSource};
+use crate::util::sleep;
+
+use std::net::IpAddr;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use tokio::sync::oneshot;
+use tracing::{debug, warn};
+
+/// How long to wait for the screen to change after a verified play when the config doesn't say
+pub const DEFAULT_EFFECT_TIMEOUT: Duration = Duration::milliseconds(1500);
+
+/// The most times an action can be played by one request, or by plays coalesced into one batch
+pub const MAX_PLAY_COUNT: u32 = 100;
+
+/// How often the screen is captured while waiting for it to change
+const EFFECT_CHECK_INTERVAL: Duration = Duration::milliseconds(100);
+/// How often the screen is captured while benchmarking. Much finer than the 50ms the suggested
+/// delay is rounded to
+const BENCHMARK_CHECK_INTERVAL: Duration = Duration::milliseconds(10);
+/// The fraction of a capture that has to change for a play to count as having had an effect, so
+/// the cursor or the clock changing isn't mistaken for one
+const MIN_EFFECT_FRACTION: f64 = 0.01;
+
+pub struct Player {
+    actions: SharedActions,
+    pub(super) rotation: Arc<dyn RotationSource>,
+    pub(super) devices: Arc<dyn InputDevices>,
+    locks: DeviceLocks,
+    pub(super) injector: Injector,
+    pub(super) busy_wait: bool,
+    pub(super) coalesce_plays: bool,
+    canceller: PlayCanceller,
+    pub(super) history: PlayHistory,
+    pub(super) screen: Arc<dyn ScreenSource>,
+    pub(super) effect_timeout: Duration,
+    play_wait_until: DateTime<Utc>,
+}
+
+/// Work handed over by the ActionManager
+pub enum PlayerJob {
+    Play(PlayJob),
+    Benchmark(BenchmarkJob),
+}
+
+impl PlayerJob {
+    /// Answer the job with an error as there's no player to handle it
+    pub fn player_stopped(self) {
+        let error = anyhow!("The player has stopped");
+        // The requester might have given up waiting, so there's no one to tell if sending fails
+        match self {
+            Self::Play(job) => {
+                let _ = job.resp.send(Err(error));
+            }
+            Self::Benchmark(job) => {
+                let _ = job.resp.send(Err(error));
+            }
+        }
+    }
+}
+
+/// A request to play an action, handed over by the ActionManager
+pub struct PlayJob {
+    pub path_segment: String,
+    pub speed: Option<f64>,
+    pub count: u32,
+    /// From [PlayCanceller::generation] when the play was requested
+    pub generation: u64,
+    /// For the history
+    pub client: Option<IpAddr>,
+    pub requested: DateTime<Utc>,
+    pub resp: oneshot::Sender<Result<PlayResult>>,
+}
+
+impl Player {
+    pub fn new(
+        actions: SharedActions,
+        rotation: Arc<dyn RotationSource>,
+        devices: Arc<dyn InputDevices>,
+        locks: DeviceLocks,
+        injector: Injector,
+        history: PlayHistory,
+        screen: Arc<dyn ScreenSource>,
+    ) -> Self {
+        Self {
+            actions,
+            rotation,
+            devices,
+            locks,
+            injector,
+            busy_wait: false,
+            coalesce_plays: false,
+            canceller: PlayCanceller::default(),
+            history,
+            screen,
+            effect_timeout: DEFAULT_EFFECT_TIMEOUT,
+            play_wait_until: Utc::now(),
+        }
+    }
+
+    pub fn canceller(&self) -> PlayCanceller {
+        self.canceller.clone()
+    }
+
+    /// Play an action, optionally overriding its speed
+    pub fn play(&mut self, path_segment: &str, speed: Option<f64>) -> Result<PlaybackDrift> {
+        let mut result = PlayResult::default();
+        self.play_verified(path_segment, speed, &mut result)?;
+        if result.effect == Some(false) {
+            warn!("Playing {path_segment} didn't change the screen");
+        }
+        Ok(result.drift)
+    }
+
+    /// Play an action `count` times, stopping early if plays from `generation` are cancelled
+    pub fn play_repeated(
+        &mut self,
+        path_segment: &str,
+        speed: Option<f64>,
+        count: u32,
+        generation: u64,
+    ) -> Result<PlayResult> {
+        if count > MAX_PLAY_COUNT {
+            return Err(anyhow!(
+                "An action can't be played more than {MAX_PLAY_COUNT} times at once"
+            ));
+        }
+        let mut result = PlayResult::default();
+        for _ in 0..count {
+            self.wait_for_previous_play();
+            if self.canceller.is_cancelled(generation) {
+                debug!(
+                    "Cancelled {path_segment} after {} of {count} plays",
+                    result.played
+                );
+                break;
+            }
+            let started = Utc::now();
+            self.play_verified(path_segment, speed, &mut result)?;
+            result.time_taken += Utc::now() - started;
+            result.played += 1;
+        }
+        Ok(result)
+    }
+
+    fn wait_for_previous_play(&self) {
+        // Don't play consecutive actions immediately so the device has time to act on the input.
+        // Allows a user to spam page turns and have them all register
+        if Utc::now() < self.play_wait_until {
+            sleep(self.play_wait_until - Utc::now());
+        }
+    }
+
+    /// Play an action after waiting for the previous play. If the action is verified, the screen is
+    /// compared before and after, and the action played once more if it didn't change. The drift,
+    /// device and effect of the play are written to `result`
+    fn play_verified(
+        &mut self,
+        path_segment: &str,
+        speed: Option<f64>,
+        result: &mut PlayResult,
+    ) -> Result<()> {
+        self.wait_for_previous_play();
+        let before = self.capture_before(path_segment);
+        (result.drift, result.device) = self.play_now(path_segment, speed)?;
+        let Some(before) = before else {
+            result.effect = None;
+            return Ok(());
+        };
+        result.effect = self.check_effect(path_segment, &before);
+        if result.effect != Some(false) {
+            return Ok(());
+        }
+        self.wait_for_previous_play();
+        // The screen might have changed after the timeout, in which case playing again would
+        // repeat the action
+        let Some(latest) = self.capture_before(path_segment) else {
+            result.effect = None;
+            return Ok(());
+        };
+        if screen_differs(&before, &latest) {
+            debug!("{path_segment} changed the screen after the effect timeout");
+            result.effect = Some(true);
+            return Ok(());
+        }
+        debug!("{path_segment} didn't change the screen. Playing it again");
+        (result.drift, result.device) = self.play_now(path_segment, speed)?;
+        result.effect = self.check_effect(path_segment, &latest);
+        Ok(())
+    }
+
+    /// Capture the screen if the action should be verified
+    fn capture_before(&self, path_segment: &str) -> Option<Vec<u8>> {
+        let actions = self.actions.read().unwrap().clone();
+        if !actions.actions.get(path_segment)?.verify_effect {
+            return None;
+        }
+        self.screen
+            .capture()
+            .map_err(|e| warn!("Unable to verify {path_segment} had an effect. {e:#}"))
+            .ok()
+    }
+
+    fn check_effect(&self, path_segment: &str, before: &[u8]) -> Option<bool> {
+        self.screen_changed(before)
+            .map_err(|e| warn!("Unable to verify {path_segment} had an effect. {e:#}"))
+            .ok()
+    }
+
+    /// Wait up to the effect timeout for the screen to differ from `before`
+    fn screen_changed(&self, before: &[u8]) -> Result<bool> {
+        let deadline = Utc::now() + self.effect_timeout;
+        loop {
+            if screen_differs(before, &self.screen.capture()?) {
+                return Ok(true);
+            }
+            if Utc::now() >= deadline {
+                return Ok(false);
+            }
+            sleep(EFFECT_CHECK_INTERVAL);
+        }
+    }
+
+    /// Play an action repeatedly, timing how long the screen takes to change and then settle.
+    /// Stops early with the runs so far if plays from `generation` are cancelled
+    pub fn benchmark(
+        &mut self,
+        path_segment: &str,
+        opts: &BenchmarkOptions,
+        generation: u64,
+    ) -> Result<Vec<BenchmarkRun>> {
+        opts.check()?;
+        let mut runs = Vec::with_capacity(opts.runs as usize);
+        for _ in 0..opts.runs {
+            self.wait_for_previous_play();
+            if self.canceller.is_cancelled(generation) {
+                break;
+            }
+            let before = self.screen.capture()?;
+            self.play_now(path_segment, None)?;
+            let Some(run) = self.time_screen_changes(before, opts, generation)? else {
+                break;
+            };
+            debug!("Benchmarked {path_segment}: {run:?}");
+            runs.push(run);
+        }
+        if runs.len() < opts.runs as usize {
+            debug!(
+                "Cancelled benchmark of {path_segment} after {} of {} runs",
+                runs.len(),
+                opts.runs
+            );
+        }
+        Ok(runs)
+    }
+
+    /// None if the benchmark was cancelled before the run finished
+    fn time_screen_changes(
+        &self,
+        mut last: Vec<u8>,
+        opts: &BenchmarkOptions,
+        generation: u64,
+    ) -> Result<Option<BenchmarkRun>> {
+        let played = Instant::now();
+        let mut run = BenchmarkRun::default();
+        let mut last_change = None;
+        loop {
+            if self.canceller.is_cancelled(generation) {
+                return Ok(None);
+            }
+            let elapsed = Duration::from_std(played.elapsed())?;
+            if elapsed > opts.timeout {
+                return Ok(Some(run));
+            }
+            let frame = self.screen.capture()?;
+            if screen_differs(&last, &frame) {
+                run.first_change.get_or_insert(elapsed);
+                last_change = Some(elapsed);
+                last = frame;
+            } else if let Some(changed) = last_change {
+                if elapsed - changed >= opts.settle_time {
+                    run.stable = Some(changed);
+                    return Ok(Some(run));
+                }
+            }
+            sleep(BENCHMARK_CHECK_INTERVAL);
+        }
+    }
+
+    /// Play an action without waiting, returning its drift and the name of the device it was
+    /// written to
+    fn play_now(
+        &mut self,
+        path_segment: &str,
+        speed: Option<f64>,
+    ) -> Result<(PlaybackDrift, Option<String>)> {
+        let rotation = self.rotation.current_rotation()?;
+        // Take the latest actions without holding the lock, so changes aren't blocked by the play
+        let actions = self.actions.read().unwrap().clone();
+        let recording = get_recording(&actions.recordings, path_segment, rotation)?;
+        let Some(opts) = actions.actions.get(path_segment) else {
+            return Err(anyhow!("No action exists for {path_segment}"));
+        };
+        let mut timing = opts.timing();
+        if let Some(speed) = speed {
+            timing.speed = speed;
+        }
+        timing.check()?;
+        let dev_path = self
+            .devices
+            .resolve(recording.fingerprint.as_ref(), &recording.dev_path)?;
+        let _guard = self.locks.lock([dev_path.clone()]);
+        let mut sink = self.injector.sink(&dev_path)?;
+        let drift = recording.play(path_segment, sink.as_mut(), self.busy_wait, &timing)?;
+        self.play_wait_until = Utc::now() + opts.post_playback_delay;
+        Ok((drift, Some(recording.dev_name.clone())))
+    }
+
+    /// Play actions in the order they're requested until the ActionManager stops
+    pub fn manage(&mut self, rx: Receiver<PlayerJob>) {
+        // A job taken from the channel while coalescing plays that still needs handling
+        let mut queued = None;
+        loop {
+            let job = match queued.take().or_else(|| rx.recv().ok()) {
+                Some(PlayerJob::Play(job)) => job,
+                Some(PlayerJob::Benchmark(job)) => {
+                    let result = self.benchmark(&job.path_segment, &job.opts, job.generation);
+                    if job.resp.send(result).is_err() {
+                        warn!("Unable to send Benchmark result. Receiver dropped")
+                    }
+                    continue;
+                }
+                None => break,
+            };
+            let mut batch = vec![job];
+            if self.coalesce_plays {
+                queued = coalesce_plays(&rx, &mut batch);
+            }
+            if batch.len() > 1 {
+                debug!(
+                    "Coalesced {} plays of {}",
+                    batch.len(),
+                    batch[0].path_segment
+                );
+            }
+            let count = batch.iter().map(|j| j.count).sum();
+            let first = &batch[0];
+            let path_segment = first.path_segment.clone();
+            let result = self.play_repeated(&path_segment, first.speed, count, first.generation);
+            let rotation = self.rotation.current_rotation().ok();
+            let mut unassigned = result.as_ref().map_or(0, |r| r.played);
+            for job in batch {
+                // Each job is given its own share of the plays, so the batch isn't counted once
+                // for every job in it
+                let result = match &result {
+                    Ok(r) => {
+                        let played = unassigned.min(job.count);
+                        unassigned -= played;
+                        Ok(PlayResult {
+                            played,
+                            ..r.clone()
+                        })
+                    }
+                    Err(e) => Err(anyhow!("{e:#}")),
+                };
+                // Logged before responding so the play is in the history once the client knows
+                // it's finished
+                self.history.add(PlayRecord::new(
+                    &path_segment,
+                    job.client,
+                    job.requested,
+                    rotation,
+                    &result,
+                ));
+                if job.resp.send(result).is_err() {
+                    warn!("Unable to send Play result. Receiver dropped")
+                }
+            }
+        }
+    }
+}
+
+/// Whether enough of a capture changed to count as an effect. Captures of different sizes are
+/// from different rotations, so always differ
+fn screen_differs(before: &[u8], after: &[u8]) -> bool {
+    if before.len() != after.len() {
+        return true;
+    }
+    let changed = before.iter().zip(after).filter(|(b, a)| b != a).count();
+    changed as f64 > before.len() as f64 * MIN_EFFECT_FRACTION
+}
+
+/// Add plays of the same action from the front of the channel to the batch, up to
+/// [MAX_PLAY_COUNT] plays in total. Returns the first other job so it can be handled next
+fn coalesce_plays(rx: &Receiver<PlayerJob>, batch: &mut Vec<PlayJob>) -> Option<PlayerJob> {
+    let mut count = batch[0].count;
+    loop {
+        match rx.try_recv() {
+            Ok(PlayerJob::Play(job))
+                if job.path_segment == batch[0].path_segment
+                    && job.speed == batch[0].speed
+                    && job.generation == batch[0].generation
+                    && count.saturating_add(job.count) <= MAX_PLAY_COUNT =>
+            {
+                count += job.count;
+                batch.push(job);
+            }
+            Ok(job) => return Some(job),
+            Err(_) => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_changes_have_no_effect() {
+        let before = vec![0; 1000];
+        let mut cursor = before.clone();
+        cursor[..10].fill(255);
+        assert!(!screen_differs(&before, &cursor));
+        let mut page = before.clone();
+        page[..500].fill(255);
+        assert!(screen_differs(&before, &page));
+        assert!(screen_differs(&before, &before[..900]));
+        assert!(!screen_differs(&[], &[]));
+    }
+}
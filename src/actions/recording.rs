@@ -0,0 +1,274 @@
+//! Recordings as jobs with an id, so the browser can follow their progress over a WebSocket and
+//! abort them instead of waiting for the timeouts
+use super::{ActionMsg, RecordActionOptions, RecordActionResponse, RecordJob};
+use crate::errors::AppError;
+use crate::server::AppState;
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    extract::{Path as AxumPath, State},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{oneshot, watch};
+use tracing::{debug, warn};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/actions/recordings", post(start_recording))
+        .route(
+            "/actions/recordings/:id",
+            get(progress_handler).delete(abort_recording),
+        )
+}
+
+/// What a recording is doing, sent to clients whenever it changes
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum RecordingProgress {
+    WaitingForInput,
+    /// The devices that have had input so far and how many events they've sent altogether
+    InputDetected {
+        devices: Vec<String>,
+        events: usize,
+    },
+    Finished {
+        action: RecordActionResponse,
+    },
+    Failed {
+        error: String,
+    },
+    Aborted,
+}
+
+impl RecordingProgress {
+    pub fn is_done(&self) -> bool {
+        matches!(
+            self,
+            Self::Finished { .. } | Self::Failed { .. } | Self::Aborted
+        )
+    }
+}
+
+/// Reports the progress of a recording and lets it be aborted. Clones share the same state
+#[derive(Clone, Debug)]
+pub struct RecordingControl {
+    aborted: Arc<AtomicBool>,
+    progress: Arc<watch::Sender<RecordingProgress>>,
+}
+
+impl Default for RecordingControl {
+    fn default() -> Self {
+        Self {
+            aborted: Arc::default(),
+            progress: Arc::new(watch::channel(RecordingProgress::WaitingForInput).0),
+        }
+    }
+}
+
+impl RecordingControl {
+    /// Stop reading input as soon as possible. The recording won't be saved
+    pub fn abort(&self) {
+        self.aborted.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::SeqCst)
+    }
+
+    pub fn report(&self, progress: RecordingProgress) {
+        self.progress.send_replace(progress);
+    }
+
+    pub fn progress(&self) -> RecordingProgress {
+        self.progress.borrow().clone()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<RecordingProgress> {
+        self.progress.subscribe()
+    }
+}
+
+/// Recordings that are in progress, and the most recent of those that have finished so its result
+/// can still be fetched. Clones share the same jobs
+#[derive(Clone, Debug, Default)]
+pub struct RecordingJobs {
+    next_id: Arc<AtomicU64>,
+    jobs: Arc<Mutex<BTreeMap<u64, RecordingControl>>>,
+}
+
+impl RecordingJobs {
+    /// Start tracking a new recording, forgetting those that have finished except the most recent
+    pub fn add(&self) -> (u64, RecordingControl) {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let control = RecordingControl::default();
+        let mut jobs = self.jobs.lock().unwrap();
+        let last_done = jobs
+            .iter()
+            .rev()
+            .find(|(_, c)| c.progress().is_done())
+            .map(|(id, _)| *id);
+        jobs.retain(|id, c| !c.progress().is_done() || Some(*id) == last_done);
+        jobs.insert(id, control.clone());
+        (id, control)
+    }
+
+    pub fn get(&self, id: u64) -> Result<RecordingControl> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| anyhow!("No recording with id {id}"))
+    }
+}
+
+/// Check the options with the ActionManager and get a recording ready to run
+pub async fn prepare(state: &AppState, opts: RecordActionOptions) -> Result<RecordJob> {
+    let (tx, rx) = oneshot::channel();
+    state.tx.send(ActionMsg::Record { opts, resp: tx }).await?;
+    rx.await?
+}
+
+/// Read the input for a recording then save it, reporting the outcome to the control
+pub async fn run(
+    state: &AppState,
+    job: RecordJob,
+    control: RecordingControl,
+) -> Result<RecordActionResponse> {
+    let result = save(state, job, control.clone()).await;
+    match &result {
+        Ok(action) => control.report(RecordingProgress::Finished {
+            action: action.clone(),
+        }),
+        Err(_) if control.is_aborted() => control.report(RecordingProgress::Aborted),
+        Err(e) => control.report(RecordingProgress::Failed {
+            error: format!("{e:#}"),
+        }),
+    }
+    result
+}
+
+async fn save(
+    state: &AppState,
+    job: RecordJob,
+    control: RecordingControl,
+) -> Result<RecordActionResponse> {
+    // Reading input blocks until the timeouts are reached, so keep it off the async runtime
+    let job_control = control.clone();
+    let recorded = tokio::task::spawn_blocking(move || job.run(&job_control)).await??;
+    // The recording can be aborted after its input was read, and an aborted one shouldn't be saved
+    if control.is_aborted() {
+        return Err(anyhow!("The recording was aborted"));
+    }
+    let (tx, rx) = oneshot::channel();
+    let msg = ActionMsg::AddRecorded {
+        recorded: Box::new(recorded),
+        resp: tx,
+    };
+    state.tx.send(msg).await?;
+    rx.await?
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StartRecordingResponse {
+    id: u64,
+}
+
+/// Start recording in the background, returning the id to follow it with
+async fn start_recording(
+    State(state): State<AppState>,
+    Json(opts): Json<RecordActionOptions>,
+) -> Result<impl IntoResponse, AppError> {
+    debug!("Received request to start recording action: {:#?}", &opts);
+    let job = prepare(&state, opts).await?;
+    let (id, control) = state.recordings.add();
+    tokio::spawn(async move {
+        if let Err(e) = run(&state, job, control).await {
+            debug!("Recording {id} didn't finish. {e:#}");
+        }
+    });
+    Ok(Json(StartRecordingResponse { id }))
+}
+
+async fn abort_recording(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    debug!("Received request to abort recording {id}");
+    state.recordings.get(id)?.abort();
+    Ok(())
+}
+
+async fn progress_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<u64>,
+) -> Result<Response, AppError> {
+    let control = state.recordings.get(id)?;
+    Ok(ws.on_upgrade(move |socket| send_progress(socket, control)))
+}
+
+async fn send_progress(mut socket: WebSocket, control: RecordingControl) {
+    let mut rx = control.subscribe();
+    loop {
+        let progress = rx.borrow_and_update().clone();
+        let Ok(json) = serde_json::to_string(&progress) else {
+            warn!("Failed to serialize {progress:?}");
+            break;
+        };
+        if socket.send(Message::Text(json)).await.is_err() {
+            debug!("Recording progress client disconnected");
+            return;
+        }
+        if progress.is_done() {
+            break;
+        }
+        tokio::select! {
+            changed = rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+            }
+            msg = socket.recv() => {
+                if !matches!(msg, Some(Ok(Message::Ping(_) | Message::Pong(_)))) {
+                    debug!("Recording progress client disconnected");
+                    return;
+                }
+            }
+        }
+    }
+    if let Err(e) = socket.send(Message::Close(None)).await {
+        warn!("Failed to close recording progress WebSocket. {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RecordingJobs, RecordingProgress};
+
+    #[test]
+    fn keep_last_finished_recording() {
+        let jobs = RecordingJobs::default();
+        let (first, control) = jobs.add();
+        control.report(RecordingProgress::Aborted);
+        let (second, control) = jobs.add();
+        control.report(RecordingProgress::Failed {
+            error: "No input".into(),
+        });
+        let (running, _) = jobs.add();
+        let (next, _) = jobs.add();
+
+        assert!(jobs.get(first).is_err());
+        assert!(jobs.get(second).unwrap().progress().is_done());
+        assert!(jobs.get(running).is_ok());
+        assert!(jobs.get(next).is_ok());
+    }
+}
@@ -0,0 +1,569 @@
+//! Generates actions that can't be recorded reliably by hand, like pinch-zoom gestures, or that
+//! are easier to create without recording, like button presses
+use super::arbitrary::TouchTransform;
+use super::devices::{DeviceDetails, InputDevices};
+use super::input::InputDevice;
+
+use anyhow::{anyhow, Result};
+use chrono::Duration;
+use evdev_rs::enums::EventCode::{self, EV_ABS, EV_KEY, EV_SYN};
+use evdev_rs::enums::EventType;
+use evdev_rs::enums::EV_ABS::*;
+use evdev_rs::enums::EV_KEY::{BTN_TOOL_DOUBLETAP, BTN_TOUCH};
+use evdev_rs::enums::EV_SYN::SYN_REPORT;
+use evdev_rs::util::{event_type_get_max, int_to_event_code};
+use evdev_rs::{DeviceWrapper, InputEvent, TimeVal};
+use nix::libc::{suseconds_t, time_t};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DurationMilliSeconds};
+use strum::Display;
+use tracing::debug;
+
+/// The most batches of movement a gesture can have
+const MAX_GESTURE_STEPS: u32 = 100;
+/// The longest a generated gesture or key press can take
+const MAX_SYNTHETIC_DURATION: Duration = Duration::seconds(10);
+
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyntheticActionOptions {
+    pub name: String,
+    pub sort_value: Option<String>,
+    pub path_segment: Option<String>,
+    pub keyboard_shortcut: Option<keyboard_types::Code>,
+    pub voice_trigger: Option<String>,
+    #[serde_as(as = "DurationMilliSeconds<i64>")]
+    pub post_playback_delay: Duration,
+    pub use_by_path: bool,
+    pub action: SyntheticAction,
+}
+
+impl Default for SyntheticActionOptions {
+    fn default() -> Self {
+        Self {
+            name: "Default".into(),
+            sort_value: None,
+            path_segment: None,
+            keyboard_shortcut: None,
+            voice_trigger: None,
+            post_playback_delay: Duration::milliseconds(300),
+            use_by_path: false,
+            action: SyntheticAction::Gesture(GestureOptions::default()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SyntheticAction {
+    Gesture(GestureOptions),
+    KeyPress(KeyPressOptions),
+}
+
+impl SyntheticAction {
+    /// Find the device to write the action to and generate its events. Gestures are translated
+    /// to the touchscreen's axes with `transform`
+    pub fn generate(
+        &self,
+        devices: &dyn InputDevices,
+        use_by_path: bool,
+        transform: TouchTransform,
+    ) -> Result<(DeviceDetails, Vec<InputEvent>)> {
+        match self {
+            SyntheticAction::Gesture(opts) => {
+                opts.check()?;
+                let (device, touch) = devices.multi_touch(use_by_path)?;
+                debug!("Generating {} on {device} with {touch:?}", opts.kind);
+                let events = opts.events(&touch, transform);
+                Ok((device, events))
+            }
+            SyntheticAction::KeyPress(opts) => {
+                check_duration(opts.duration)?;
+                let code = opts.event_code()?;
+                let device = devices.key_device(code, use_by_path)?;
+                debug!("Generating {code} press on {device}");
+                let events = opts.events(code);
+                Ok((device, events))
+            }
+        }
+    }
+}
+
+/// Press and release a key, e.g. `KEY_F23` for 50ms
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyPressOptions {
+    /// The name of the key's event code, as listed by /input/devices
+    pub key: String,
+    /// How long to hold the key for
+    #[serde_as(as = "DurationMilliSeconds<i64>")]
+    pub duration: Duration,
+}
+
+impl Default for KeyPressOptions {
+    fn default() -> Self {
+        Self {
+            key: "KEY_F23".into(),
+            duration: Duration::milliseconds(50),
+        }
+    }
+}
+
+impl KeyPressOptions {
+    pub fn event_code(&self) -> Result<EventCode> {
+        let max = event_type_get_max(&EventType::EV_KEY).unwrap_or_default();
+        (0..=max)
+            .map(|code| int_to_event_code(EventType::EV_KEY as u32, code))
+            .find(|code| code.to_string() == self.key)
+            .ok_or_else(|| anyhow!("{} isn't a key", self.key))
+    }
+
+    pub fn events(&self, code: EventCode) -> Vec<InputEvent> {
+        let release = timeval(self.duration);
+        let start = timeval(Duration::zero());
+        let event = |time, event_code, value| InputEvent {
+            time,
+            event_code,
+            value,
+        };
+        vec![
+            event(start, code, 1),
+            event(start, EV_SYN(SYN_REPORT), 0),
+            event(release, code, 0),
+            event(release, EV_SYN(SYN_REPORT), 0),
+        ]
+    }
+}
+
+fn check_duration(duration: Duration) -> Result<()> {
+    if duration < Duration::zero() || duration > MAX_SYNTHETIC_DURATION {
+        return Err(anyhow!(
+            "The duration must be between 0 and {}ms",
+            MAX_SYNTHETIC_DURATION.num_milliseconds()
+        ));
+    }
+    Ok(())
+}
+
+fn timeval(time: Duration) -> TimeVal {
+    TimeVal {
+        tv_sec: time.num_seconds() as time_t,
+        tv_usec: (time.num_microseconds().unwrap() % 1_000_000) as suseconds_t,
+    }
+}
+
+#[derive(Clone, Copy, Debug, Display, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum GestureKind {
+    /// Two fingers moving towards each other horizontally, e.g. to zoom out
+    Pinch,
+    /// Two fingers moving away from each other horizontally, e.g. to zoom in
+    Spread,
+    SwipeUp,
+    SwipeDown,
+    SwipeLeft,
+    SwipeRight,
+}
+
+/// A two-finger gesture. Positions and distances are fractions of the screen's width and height
+/// as it's currently rotated, so e.g. a swipe left always moves towards the left of the screen.
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GestureOptions {
+    pub kind: GestureKind,
+    pub centre_x: f64,
+    pub centre_y: f64,
+    /// How far apart the fingers are at the widest point of a pinch or spread, or how far they
+    /// travel during a swipe
+    pub distance: f64,
+    #[serde_as(as = "DurationMilliSeconds<i64>")]
+    pub duration: Duration,
+    /// The number of batches of movement between touching and releasing, from 1 to
+    /// [MAX_GESTURE_STEPS]
+    pub steps: u32,
+}
+
+impl Default for GestureOptions {
+    fn default() -> Self {
+        Self {
+            kind: GestureKind::Spread,
+            centre_x: 0.5,
+            centre_y: 0.5,
+            distance: 0.5,
+            duration: Duration::milliseconds(300),
+            steps: 10,
+        }
+    }
+}
+
+/// How far apart the fingers are at the narrowest point of a pinch or spread, as a fraction of
+/// `distance`
+const PINCH_MIN_RATIO: f64 = 0.2;
+/// How far apart the fingers are during a swipe, as a fraction of the touchscreen's range
+const SWIPE_FINGER_GAP: f64 = 0.1;
+
+/// The parts of a touchscreen's capabilities needed to generate a gesture
+#[derive(Clone, Debug, PartialEq)]
+pub struct TouchCapabilities {
+    pub x: (i32, i32),
+    pub y: (i32, i32),
+    pub btn_touch: bool,
+    pub btn_tool_doubletap: bool,
+    /// The value to use for ABS_MT_PRESSURE and ABS_MT_TOUCH_MAJOR if they're supported
+    pub pressure: Option<i32>,
+    pub touch_major: Option<i32>,
+}
+
+impl TouchCapabilities {
+    pub fn new(device: &InputDevice) -> Result<Self> {
+        let slots = device.evdev.abs_info(&EV_ABS(ABS_MT_SLOT));
+        if !matches!(slots, Some(s) if s.maximum >= 1) {
+            return Err(anyhow!("{device} doesn't support more than one touch"));
+        }
+        let range = |code: &EventCode| {
+            device
+                .evdev
+                .abs_info(code)
+                .map(|i| (i.minimum, i.maximum))
+                .ok_or_else(|| anyhow!("{device} doesn't support {code}"))
+        };
+        let middle = |code: &EventCode| {
+            device
+                .evdev
+                .abs_info(code)
+                .map(|i| i.minimum + (i.maximum - i.minimum) / 2)
+        };
+        Ok(Self {
+            x: range(&EV_ABS(ABS_MT_POSITION_X))?,
+            y: range(&EV_ABS(ABS_MT_POSITION_Y))?,
+            btn_touch: device.evdev.has(EV_KEY(BTN_TOUCH)),
+            btn_tool_doubletap: device.evdev.has(EV_KEY(BTN_TOOL_DOUBLETAP)),
+            pressure: middle(&EV_ABS(ABS_MT_PRESSURE)),
+            touch_major: middle(&EV_ABS(ABS_MT_TOUCH_MAJOR)),
+        })
+    }
+
+    fn x(&self, fraction: f64) -> i32 {
+        scale(self.x, fraction)
+    }
+
+    fn y(&self, fraction: f64) -> i32 {
+        scale(self.y, fraction)
+    }
+}
+
+fn scale((min, max): (i32, i32), fraction: f64) -> i32 {
+    min + ((max - min) as f64 * fraction.clamp(0.0, 1.0)).round() as i32
+}
+
+impl GestureOptions {
+    /// Reject gestures that wouldn't move, would leave the screen, have too many steps or would
+    /// take too long
+    pub fn check(&self) -> Result<()> {
+        if !(1..=MAX_GESTURE_STEPS).contains(&self.steps) {
+            return Err(anyhow!(
+                "A gesture needs between 1 and {MAX_GESTURE_STEPS} steps"
+            ));
+        }
+        for (name, centre) in [("centre_x", self.centre_x), ("centre_y", self.centre_y)] {
+            if !(0.0..=1.0).contains(&centre) {
+                return Err(anyhow!(
+                    "{name} must be a fraction of the screen from 0 to 1"
+                ));
+            }
+        }
+        if !(self.distance > 0.0 && self.distance <= 1.0) {
+            return Err(anyhow!(
+                "The distance must be a fraction of the screen above 0 and up to 1"
+            ));
+        }
+        check_duration(self.duration)
+    }
+
+    /// The start and end positions of each finger as fractions of the screen
+    fn positions(&self) -> [((f64, f64), (f64, f64)); 2] {
+        let (cx, cy) = (self.centre_x, self.centre_y);
+        let wide = self.distance / 2.0;
+        let narrow = wide * PINCH_MIN_RATIO;
+        let travel = self.distance / 2.0;
+        let gap = SWIPE_FINGER_GAP / 2.0;
+        match self.kind {
+            GestureKind::Pinch => [
+                ((cx - wide, cy), (cx - narrow, cy)),
+                ((cx + wide, cy), (cx + narrow, cy)),
+            ],
+            GestureKind::Spread => [
+                ((cx - narrow, cy), (cx - wide, cy)),
+                ((cx + narrow, cy), (cx + wide, cy)),
+            ],
+            GestureKind::SwipeUp => [
+                ((cx - gap, cy + travel), (cx - gap, cy - travel)),
+                ((cx + gap, cy + travel), (cx + gap, cy - travel)),
+            ],
+            GestureKind::SwipeDown => [
+                ((cx - gap, cy - travel), (cx - gap, cy + travel)),
+                ((cx + gap, cy - travel), (cx + gap, cy + travel)),
+            ],
+            GestureKind::SwipeLeft => [
+                ((cx + travel, cy - gap), (cx - travel, cy - gap)),
+                ((cx + travel, cy + gap), (cx - travel, cy + gap)),
+            ],
+            GestureKind::SwipeRight => [
+                ((cx - travel, cy - gap), (cx + travel, cy - gap)),
+                ((cx - travel, cy + gap), (cx + travel, cy + gap)),
+            ],
+        }
+    }
+
+    /// Generate the events for the gesture using the type B multi-touch protocol, with a batch
+    /// for both fingers touching, `steps` batches of movement, and a batch for the release.
+    /// Positions on the screen are translated to the touchscreen's axes with `transform`
+    pub fn events(&self, touch: &TouchCapabilities, transform: TouchTransform) -> Vec<InputEvent> {
+        let positions = self.positions().map(|(start, end)| {
+            (
+                transform.apply_fraction(start),
+                transform.apply_fraction(end),
+            )
+        });
+        let batches = self.steps as i64 + 2;
+        let gap = self.duration / (batches as i32 - 1).max(1);
+        let mut events = Vec::new();
+        let mut push = |batch: i64, code: EventCode, value: i32| {
+            events.push(InputEvent {
+                time: timeval(gap * batch as i32),
+                event_code: code,
+                value,
+            });
+        };
+
+        for (slot, ((x, y), _)) in positions.iter().enumerate() {
+            push(0, EV_ABS(ABS_MT_SLOT), slot as i32);
+            push(0, EV_ABS(ABS_MT_TRACKING_ID), slot as i32 + 1);
+            if let Some(touch_major) = touch.touch_major {
+                push(0, EV_ABS(ABS_MT_TOUCH_MAJOR), touch_major);
+            }
+            if let Some(pressure) = touch.pressure {
+                push(0, EV_ABS(ABS_MT_PRESSURE), pressure);
+            }
+            push(0, EV_ABS(ABS_MT_POSITION_X), touch.x(*x));
+            push(0, EV_ABS(ABS_MT_POSITION_Y), touch.y(*y));
+        }
+        if touch.btn_touch {
+            push(0, EV_KEY(BTN_TOUCH), 1);
+        }
+        if touch.btn_tool_doubletap {
+            push(0, EV_KEY(BTN_TOOL_DOUBLETAP), 1);
+        }
+        push(0, EV_SYN(SYN_REPORT), 0);
+
+        for step in 1..=self.steps as i64 {
+            let progress = step as f64 / self.steps as f64;
+            for (slot, ((x1, y1), (x2, y2))) in positions.iter().enumerate() {
+                push(step, EV_ABS(ABS_MT_SLOT), slot as i32);
+                push(
+                    step,
+                    EV_ABS(ABS_MT_POSITION_X),
+                    touch.x(x1 + (x2 - x1) * progress),
+                );
+                push(
+                    step,
+                    EV_ABS(ABS_MT_POSITION_Y),
+                    touch.y(y1 + (y2 - y1) * progress),
+                );
+            }
+            push(step, EV_SYN(SYN_REPORT), 0);
+        }
+
+        let last = batches - 1;
+        for slot in 0..positions.len() {
+            push(last, EV_ABS(ABS_MT_SLOT), slot as i32);
+            push(last, EV_ABS(ABS_MT_TRACKING_ID), -1);
+        }
+        if touch.btn_touch {
+            push(last, EV_KEY(BTN_TOUCH), 0);
+        }
+        if touch.btn_tool_doubletap {
+            push(last, EV_KEY(BTN_TOOL_DOUBLETAP), 0);
+        }
+        push(last, EV_SYN(SYN_REPORT), 0);
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GestureKind, GestureOptions, KeyPressOptions, TouchCapabilities};
+    use crate::actions::arbitrary::TouchTransform;
+    use chrono::Duration;
+    use evdev_rs::enums::EventCode::{self, EV_ABS, EV_KEY, EV_SYN};
+    use evdev_rs::enums::EV_ABS::*;
+    use evdev_rs::enums::EV_KEY::{BTN_TOUCH, KEY_F23};
+    use evdev_rs::enums::EV_SYN::SYN_REPORT;
+    use evdev_rs::InputEvent;
+    use pretty_assertions::assert_eq;
+
+    fn touch() -> TouchCapabilities {
+        TouchCapabilities {
+            x: (0, 1000),
+            y: (0, 2000),
+            btn_touch: true,
+            btn_tool_doubletap: false,
+            pressure: None,
+            touch_major: None,
+        }
+    }
+
+    fn values(events: &[InputEvent]) -> Vec<(EventCode, i32)> {
+        events.iter().map(|ev| (ev.event_code, ev.value)).collect()
+    }
+
+    #[test]
+    fn pinch_events() {
+        let opts = GestureOptions {
+            kind: GestureKind::Pinch,
+            distance: 0.5,
+            duration: Duration::milliseconds(100),
+            steps: 1,
+            ..Default::default()
+        };
+        let events = opts.events(&touch(), TouchTransform::default());
+        let expected = vec![
+            (EV_ABS(ABS_MT_SLOT), 0),
+            (EV_ABS(ABS_MT_TRACKING_ID), 1),
+            (EV_ABS(ABS_MT_POSITION_X), 250),
+            (EV_ABS(ABS_MT_POSITION_Y), 1000),
+            (EV_ABS(ABS_MT_SLOT), 1),
+            (EV_ABS(ABS_MT_TRACKING_ID), 2),
+            (EV_ABS(ABS_MT_POSITION_X), 750),
+            (EV_ABS(ABS_MT_POSITION_Y), 1000),
+            (EV_KEY(BTN_TOUCH), 1),
+            (EV_SYN(SYN_REPORT), 0),
+            (EV_ABS(ABS_MT_SLOT), 0),
+            (EV_ABS(ABS_MT_POSITION_X), 450),
+            (EV_ABS(ABS_MT_POSITION_Y), 1000),
+            (EV_ABS(ABS_MT_SLOT), 1),
+            (EV_ABS(ABS_MT_POSITION_X), 550),
+            (EV_ABS(ABS_MT_POSITION_Y), 1000),
+            (EV_SYN(SYN_REPORT), 0),
+            (EV_ABS(ABS_MT_SLOT), 0),
+            (EV_ABS(ABS_MT_TRACKING_ID), -1),
+            (EV_ABS(ABS_MT_SLOT), 1),
+            (EV_ABS(ABS_MT_TRACKING_ID), -1),
+            (EV_KEY(BTN_TOUCH), 0),
+            (EV_SYN(SYN_REPORT), 0),
+        ];
+        assert_eq!(expected, values(&events));
+        assert_eq!(100_000, events.last().unwrap().time.tv_usec);
+    }
+
+    #[test]
+    fn swipe_stays_in_range() {
+        let opts = GestureOptions {
+            kind: GestureKind::SwipeUp,
+            centre_y: 0.9,
+            distance: 0.5,
+            steps: 4,
+            ..Default::default()
+        };
+        let events = opts.events(&touch(), TouchTransform::default());
+        let ys: Vec<i32> = events
+            .iter()
+            .filter(|ev| ev.is_code(&EV_ABS(ABS_MT_POSITION_Y)))
+            .map(|ev| ev.value)
+            .collect();
+        assert_eq!(Some(&2000), ys.first());
+        assert_eq!(Some(&1300), ys.last());
+        assert!(ys.windows(2).all(|w| w[0] >= w[1]));
+    }
+
+    #[test]
+    fn swipe_follows_rotation() {
+        let opts = GestureOptions {
+            kind: GestureKind::SwipeLeft,
+            steps: 2,
+            ..Default::default()
+        };
+        // e.g. a touchscreen whose x axis runs down the screen
+        let transform = TouchTransform {
+            swap_axes: true,
+            mirror_x: false,
+            mirror_y: false,
+        };
+        let events = opts.events(&touch(), transform);
+        let positions = |code| {
+            events
+                .iter()
+                .filter(|ev| ev.is_code(&EV_ABS(code)) && ev.time.tv_usec == 0)
+                .map(|ev| ev.value)
+                .collect::<Vec<_>>()
+        };
+        // The fingers start on the right of the screen, which is the end of the y axis, side by
+        // side along the x axis
+        assert_eq!(vec![450, 550], positions(ABS_MT_POSITION_X));
+        assert_eq!(vec![1500, 1500], positions(ABS_MT_POSITION_Y));
+        let ys: Vec<i32> = events
+            .iter()
+            .filter(|ev| ev.is_code(&EV_ABS(ABS_MT_POSITION_Y)))
+            .map(|ev| ev.value)
+            .collect();
+        assert_eq!(Some(&500), ys.last());
+    }
+
+    #[test]
+    fn gestures_are_bounded() {
+        let mut opts = GestureOptions {
+            steps: u32::MAX,
+            ..Default::default()
+        };
+        assert!(opts.check().is_err());
+        opts.steps = 100;
+        assert!(opts.check().is_ok());
+        opts.duration = Duration::days(365);
+        assert!(opts.check().is_err());
+    }
+
+    #[test]
+    fn gestures_move_on_screen() {
+        let invalid: [fn(&mut GestureOptions); 6] = [
+            |o| o.steps = 0,
+            |o| o.centre_x = -0.1,
+            |o| o.centre_y = 1.5,
+            |o| o.centre_x = f64::NAN,
+            |o| o.distance = f64::INFINITY,
+            |o| o.distance = 0.0,
+        ];
+        for (i, change) in invalid.into_iter().enumerate() {
+            let mut opts = GestureOptions::default();
+            change(&mut opts);
+            assert!(opts.check().is_err(), "case {i}");
+        }
+        let opts = GestureOptions {
+            distance: 1.0,
+            steps: 1,
+            ..Default::default()
+        };
+        assert!(opts.check().is_ok());
+    }
+
+    #[test]
+    fn key_press_events() {
+        let opts = KeyPressOptions {
+            key: "KEY_F23".into(),
+            duration: Duration::milliseconds(1050),
+        };
+        let events = opts.events(EV_KEY(KEY_F23));
+        let expected = vec![
+            (EV_KEY(KEY_F23), 1),
+            (EV_SYN(SYN_REPORT), 0),
+            (EV_KEY(KEY_F23), 0),
+            (EV_SYN(SYN_REPORT), 0),
+        ];
+        assert_eq!(expected, values(&events));
+        assert_eq!(1, events[2].time.tv_sec);
+        assert_eq!(50_000, events[2].time.tv_usec);
+    }
+}
@@ -1,4 +1,16 @@
+use crate::actions::benchmark::{BenchmarkOptions, BenchmarkReport};
+use crate::actions::devices::EvdevDevices;
+use crate::actions::history::PlayHistory;
+use crate::actions::inject::Injector;
+use crate::actions::{
+    ActionManager, ActionsExport, RecordActionOptions, RecordActionResponse, RenameActionOptions,
+};
+use crate::client::LocalClient;
 use crate::config::{AppConfig, Config, UserConfig};
+use crate::diagnostics::{CheckStatus, Diagnostics, DiagnosticsReport};
+use crate::fbink::FbInkWrapper;
+use crate::kobo_config::KoboConfigOptions;
+use crate::nickel_menu::NickelMenu;
 use crate::server;
 
 use std::path::{Path, PathBuf};
@@ -82,6 +94,67 @@ pub enum Commands {
         #[arg(long = "fbink")]
         use_fbink: bool,
     },
+    /// Play an action, forwarding the request to the server if it's running
+    Play {
+        /// The URL path segment of the action, e.g. next-page
+        path_segment: String,
+        /// How long in seconds to wait before playing the action
+        #[arg(long, default_value_t = 0)]
+        delay: u64,
+        /// How many times faster than recorded to play the action, overriding its speed
+        #[arg(long)]
+        speed: Option<f64>,
+        /// How many times to play the action
+        #[arg(long, default_value_t = 1)]
+        count: u32,
+    },
+    /// Play an action repeatedly and time how long the screen takes to respond, forwarding the
+    /// request to the server if it's running
+    Benchmark {
+        /// The URL path segment of the action, e.g. next-page
+        path_segment: String,
+        /// How many times to play the action
+        #[arg(long, default_value_t = 10)]
+        runs: u32,
+        /// How long in milliseconds to wait for the screen to change and settle after each play
+        #[arg(long, default_value_t = 3000)]
+        timeout: i64,
+        /// How long in milliseconds the screen has to stay the same to count as settled
+        #[arg(long, default_value_t = 500)]
+        settle_time: i64,
+    },
+    /// Record an action in the current rotation, forwarding the request to the server if it's
+    /// running
+    Record {
+        /// The name to display in the remote control
+        #[arg(long)]
+        name: String,
+        /// The URL path segment. Derived from the name if not provided
+        #[arg(long)]
+        path_segment: Option<String>,
+        /// Used to sort the button in the remote control. Defaults to the name
+        #[arg(long)]
+        sort_value: Option<String>,
+        /// Optimize a single tap or swipe so it replays quicker
+        #[arg(long)]
+        optimize: bool,
+        /// Stop the input from also being delivered to the reader while recording
+        #[arg(long)]
+        grab: bool,
+        /// Only record physical buttons, like page turn or power buttons
+        #[arg(long)]
+        keys: bool,
+        /// How long in seconds to wait before starting to record
+        #[arg(long, default_value_t = 0)]
+        delay: u64,
+    },
+    /// List, delete, rename, export or import actions
+    Actions {
+        #[command(subcommand)]
+        command: ActionsCommand,
+    },
+    /// Check for common problems and suggest how to fix them
+    Doctor,
     /// Create a user config file with the default values
     CreateUserConfig {
         #[arg(long, short, default_value = "user-config.toml")]
@@ -94,6 +167,36 @@ pub enum Commands {
     },
 }
 
+#[derive(Debug, Subcommand)]
+pub enum ActionsCommand {
+    /// List all actions
+    List,
+    /// Delete an action and its recordings
+    Delete { path_segment: String },
+    /// Change the display name and/or path segment of an action
+    Rename {
+        path_segment: String,
+        /// The new display name
+        #[arg(long)]
+        name: Option<String>,
+        /// The new URL path segment
+        #[arg(long = "path-segment")]
+        new_path_segment: Option<String>,
+    },
+    /// Save all actions and their recordings to a JSON file
+    Export {
+        #[arg(default_value = "wifiremote-actions.json")]
+        path: PathBuf,
+    },
+    /// Add actions from a file created with the export command
+    Import {
+        path: PathBuf,
+        /// Replace existing actions that have the same path segment
+        #[arg(long)]
+        overwrite: bool,
+    },
+}
+
 pub fn load_config(args: &Cli) -> Result<Config> {
     let user_config_path = if let Some(path) = env::var_os("WIFIREMOTE_USER_CONFIG") {
         path.into()
@@ -194,6 +297,64 @@ pub fn cli() -> Result<()> {
         Commands::Uninstall { dry_run } => uninstall(&config, *dry_run)?,
         Commands::Serve => server::serve(&config)?,
         Commands::Screenshot { delay, use_fbink } => screenshot(&config, *delay, *use_fbink)?,
+        Commands::Play {
+            path_segment,
+            delay,
+            speed,
+            count,
+        } => {
+            sleep(Duration::from_secs(*delay));
+            play(&config, path_segment, *speed, *count)?;
+        }
+        Commands::Benchmark {
+            path_segment,
+            runs,
+            timeout,
+            settle_time,
+        } => {
+            let opts = BenchmarkOptions {
+                runs: *runs,
+                timeout: chrono::Duration::milliseconds(*timeout),
+                settle_time: chrono::Duration::milliseconds(*settle_time),
+            };
+            print_benchmark(&benchmark(&config, path_segment, opts)?);
+        }
+        Commands::Record {
+            name,
+            path_segment,
+            sort_value,
+            optimize,
+            grab,
+            keys,
+            delay,
+        } => {
+            let defaults = &config.user.custom_action_defaults;
+            let opts = RecordActionOptions {
+                name: name.clone(),
+                path_segment: path_segment.clone(),
+                sort_value: sort_value.clone(),
+                only_check_touch: defaults.only_check_touch,
+                only_check_keys: *keys || defaults.only_check_keys,
+                optimize: *optimize,
+                use_by_path: defaults.use_by_path,
+                optimize_max_duration: defaults.optimize_max_duration,
+                post_playback_delay: defaults.post_playback_delay,
+                syn_gap: defaults.syn_gap,
+                no_input_timeout: defaults.no_input_timeout,
+                new_event_timeout: defaults.new_event_timeout,
+                poll_wait: defaults.poll_wait,
+                exclusive_grab: *grab || defaults.exclusive_grab,
+                ..Default::default()
+            };
+            sleep(Duration::from_secs(*delay));
+            let recorded = record(&config, opts)?;
+            println!(
+                "Recorded {} in {} rotation",
+                recorded.name, recorded.rotation
+            );
+        }
+        Commands::Actions { command } => actions_command(&config, command)?,
+        Commands::Doctor => doctor(&config)?,
         Commands::CreateUserConfig { path } => {
             let config = UserConfig::default();
             info!("Writing user config to {}", path.display());
@@ -208,6 +369,210 @@ pub fn cli() -> Result<()> {
     Ok(())
 }
 
+/// Returns a client for the server if it's running, so that requests that change the actions or
+/// recordings files can be forwarded to it rather than risk concurrent writes.
+fn running_server(config: &Config) -> Result<Option<LocalClient>> {
+    if get_pid()?.is_some() {
+        Ok(Some(LocalClient::new(config.app.port)))
+    } else {
+        Ok(None)
+    }
+}
+
+fn action_manager(config: &Config) -> Result<ActionManager> {
+    let fbink = FbInkWrapper::new(config);
+    let mut manager =
+        ActionManager::from_path(config.action_file(), config.recordings_file(), fbink)?;
+    manager.set_nickel_menu(NickelMenu::new(config));
+    manager.set_injector(Injector::new(config.app.injection_backend));
+    manager.set_busy_wait(config.app.playback_busy_wait);
+    manager.set_effect_timeout(config.app.effect_timeout);
+    manager.set_play_history(PlayHistory::load(
+        config.play_history_file(),
+        config.app.play_history_limit,
+    )?);
+    Ok(manager)
+}
+
+fn play(config: &Config, path_segment: &str, speed: Option<f64>, count: u32) -> Result<()> {
+    if let Some(client) = running_server(config)? {
+        let mut path = format!("/actions/{path_segment}?count={count}");
+        if let Some(speed) = speed {
+            path.push_str(&format!("&speed={speed}"));
+        }
+        client.request("GET", &path, None)?;
+    } else {
+        let mut manager = action_manager(config)?;
+        let generation = manager.play_canceller().generation();
+        manager.play_repeated(path_segment, speed, count, generation)?;
+    }
+    Ok(())
+}
+
+fn benchmark(
+    config: &Config,
+    path_segment: &str,
+    opts: BenchmarkOptions,
+) -> Result<BenchmarkReport> {
+    if let Some(client) = running_server(config)? {
+        client.post(&format!("/actions/{path_segment}/benchmark"), &opts)
+    } else {
+        let runs = action_manager(config)?.benchmark(path_segment, &opts)?;
+        Ok(BenchmarkReport::new(path_segment, runs, &config.user.setup))
+    }
+}
+
+fn print_benchmark(report: &BenchmarkReport) {
+    println!(
+        "Played {} {} times. The screen didn't change {} times",
+        report.path_segment,
+        report.runs.len(),
+        report.missed
+    );
+    for (label, stats) in [
+        ("First change", &report.first_change),
+        ("Stable", &report.stable),
+    ] {
+        if let Some(s) = stats {
+            println!(
+                "{label}: min {}ms, median {}ms, p95 {}ms, max {}ms, mean {}ms",
+                s.min.num_milliseconds(),
+                s.p50.num_milliseconds(),
+                s.p95.num_milliseconds(),
+                s.max.num_milliseconds(),
+                s.mean.num_milliseconds()
+            );
+        }
+    }
+    let suggested = &report.suggested;
+    println!("Suggested setup options:");
+    println!(
+        "  post_playback_delay = {}",
+        suggested.post_playback_delay.num_milliseconds()
+    );
+    println!(
+        "  syn_gap = {}",
+        suggested.syn_gap.num_microseconds().unwrap_or_default()
+    );
+    println!("  optimize = {}", suggested.optimize);
+}
+
+fn record(config: &Config, opts: RecordActionOptions) -> Result<RecordActionResponse> {
+    if let Some(client) = running_server(config)? {
+        client.post("/actions", &opts)
+    } else {
+        action_manager(config)?.record(opts)
+    }
+}
+
+fn actions_command(config: &Config, command: &ActionsCommand) -> Result<()> {
+    let server = running_server(config)?;
+    match command {
+        ActionsCommand::List => {
+            let actions = if let Some(client) = server {
+                client.get("/actions")?
+            } else {
+                action_manager(config)?.list()
+            };
+            for a in actions {
+                println!("{}\t{}", a.path_segment, a.name);
+            }
+        }
+        ActionsCommand::Delete { path_segment } => {
+            if let Some(client) = server {
+                client.delete(&format!("/actions/{path_segment}"))?;
+            } else {
+                action_manager(config)?.delete(path_segment)?;
+            }
+            println!("Deleted {path_segment}");
+        }
+        ActionsCommand::Rename {
+            path_segment,
+            name,
+            new_path_segment,
+        } => {
+            let opts = RenameActionOptions {
+                name: name.clone(),
+                path_segment: new_path_segment.clone(),
+            };
+            let renamed: String = if let Some(client) = server {
+                client.post(&format!("/actions/{path_segment}/rename"), &opts)?
+            } else {
+                action_manager(config)?.rename(path_segment, opts)?
+            };
+            println!("Renamed {path_segment} to {renamed}");
+        }
+        ActionsCommand::Export { path } => {
+            let export: ActionsExport = if let Some(client) = server {
+                client.get("/actions/export")?
+            } else {
+                action_manager(config)?.export()
+            };
+            fs::write(path, serde_json::to_string_pretty(&export)?)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            println!(
+                "Exported {} actions to {}",
+                export.actions.len(),
+                path.display()
+            );
+        }
+        ActionsCommand::Import { path, overwrite } => {
+            let json = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let export: ActionsExport = serde_json::from_str(&json)
+                .with_context(|| format!("Failed to deserialize {}", path.display()))?;
+            let imported: Vec<String> = if let Some(client) = server {
+                client.post(&format!("/actions/import?overwrite={overwrite}"), &export)?
+            } else {
+                action_manager(config)?.import(export, *overwrite)?
+            };
+            println!("Imported {} actions", imported.len());
+        }
+    }
+    Ok(())
+}
+
+fn doctor(config: &Config) -> Result<()> {
+    let report: DiagnosticsReport = if let Some(client) = running_server(config)? {
+        client.get("/diagnostics")?
+    } else {
+        let fbink = FbInkWrapper::new(config);
+        let coverage = ActionManager::from_path(
+            config.action_file(),
+            config.recordings_file(),
+            fbink.clone(),
+        )
+        .map(|m| m.coverage())
+        .unwrap_or_default();
+        Diagnostics {
+            config,
+            fbink: &fbink,
+            devices: &EvdevDevices,
+            kobo_config: &KoboConfigOptions::default().path,
+            server_pid: None,
+            coverage: &coverage,
+        }
+        .run()
+    };
+    println!("Wi-Fi Remote {}", report.version);
+    for check in &report.checks {
+        let status = check.status.to_string().to_uppercase();
+        println!("[{status}] {}: {}", check.name, check.message);
+        if let Some(hint) = &check.hint {
+            println!("       {hint}");
+        }
+    }
+    let failed = report
+        .checks
+        .iter()
+        .filter(|c| c.status == CheckStatus::Fail)
+        .count();
+    if failed > 0 {
+        return Err(anyhow!("{failed} checks failed"));
+    }
+    Ok(())
+}
+
 fn get_pid() -> Result<Option<Pid>> {
     let server_cmd = format!("{} serve", bin_path()?.display());
     let output = Command::new("pgrep")
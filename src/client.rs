@@ -0,0 +1,137 @@
+//! A minimal HTTP client used by the CLI to forward requests to a running server, so that only
+//! one process is ever writing to the actions and recordings files.
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::debug;
+
+pub struct LocalClient {
+    port: u32,
+}
+
+impl LocalClient {
+    pub fn new(port: u32) -> Self {
+        Self { port }
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let body = self.request("GET", path, None)?;
+        serde_json::from_str(&body).context("Failed to deserialize response from server")
+    }
+
+    pub fn post<T: DeserializeOwned>(&self, path: &str, body: &impl Serialize) -> Result<T> {
+        let body = serde_json::to_string(body)?;
+        let body = self.request("POST", path, Some(&body))?;
+        serde_json::from_str(&body).context("Failed to deserialize response from server")
+    }
+
+    pub fn delete(&self, path: &str) -> Result<()> {
+        self.request("DELETE", path, None)?;
+        Ok(())
+    }
+
+    /// Send a request and return the body of the response if it was successful
+    pub fn request(&self, method: &str, path: &str, body: Option<&str>) -> Result<String> {
+        // Paths include arguments from the command line, which mustn't be able to alter the request
+        if path.contains(|c: char| c.is_whitespace() || c.is_control() || c == '#') {
+            return Err(anyhow!("{path:?} isn't a valid request path"));
+        }
+        let addr = format!("127.0.0.1:{}", self.port);
+        debug!("Sending {method} {path} to server at {addr}");
+        let mut stream = TcpStream::connect(&addr)
+            .with_context(|| format!("Failed to connect to server at {addr}"))?;
+        // HTTP/1.0 so the server closes the connection and never uses a chunked response
+        let mut request = format!("{method} {path} HTTP/1.0\r\nHost: {addr}\r\n");
+        if let Some(body) = body {
+            request.push_str("Content-Type: application/json\r\n");
+            request.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+            request.push_str(body);
+        } else {
+            request.push_str("\r\n");
+        }
+        stream
+            .write_all(request.as_bytes())
+            .context("Failed to send request to server")?;
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .context("Failed to read response from server")?;
+        parse_response(&response)
+    }
+}
+
+fn parse_response(response: &str) -> Result<String> {
+    let Some((head, body)) = response.split_once("\r\n\r\n") else {
+        return Err(anyhow!("Received malformed response from server"));
+    };
+    let status = head
+        .lines()
+        .next()
+        .and_then(|l| l.split_whitespace().nth(1))
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| anyhow!("Received response without a status code from server"))?;
+    if (200..300).contains(&status) {
+        Ok(body.to_string())
+    } else {
+        Err(anyhow!("{}", body.trim()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use pretty_assertions::assert_eq;
+
+    /// Accept a single connection, reply with `response` and return the request that was received
+    fn serve_once(response: &'static str) -> (u32, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port().into();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![0; 1024];
+            let n = stream.read(&mut request).unwrap();
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8_lossy(&request[..n]).into_owned()
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn forward_request() {
+        let (port, handle) = serve_once("HTTP/1.1 200 OK\r\n\r\n[\"next-page\"]");
+        let body = serde_json::json!({"overwrite": true});
+        let imported: Vec<String> = LocalClient::new(port)
+            .post("/actions/import", &body)
+            .unwrap();
+        assert_eq!(vec!["next-page"], imported);
+        let request = handle.join().unwrap();
+        assert!(request.starts_with("POST /actions/import HTTP/1.0\r\n"));
+        assert!(request.ends_with("\r\n\r\n{\"overwrite\":true}"));
+
+        let (port, handle) = serve_once("HTTP/1.1 500 Internal Server Error\r\n\r\nNo action\n");
+        let error = LocalClient::new(port)
+            .delete("/actions/missing")
+            .unwrap_err();
+        assert_eq!("No action", error.to_string());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn reject_invalid_paths() {
+        let client = LocalClient::new(0);
+        for path in [
+            "/actions/a b",
+            "/actions/a\r\nX-Injected: 1",
+            "/actions/a#b",
+        ] {
+            assert!(client.request("GET", path, None).is_err(), "{path:?}");
+        }
+        assert!(parse_response("HTTP/1.1 200 OK").is_err());
+        assert!(parse_response("garbage\r\n\r\n").is_err());
+    }
+}
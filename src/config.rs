@@ -1,6 +1,12 @@
 use crate::actions::arbitrary::InputOptions;
+use crate::actions::history::DEFAULT_HISTORY_LIMIT;
+use crate::actions::inject::InjectionBackend;
+use crate::actions::player::DEFAULT_EFFECT_TIMEOUT;
+use crate::actions::ActionMsg;
 use crate::frontend::index::IndexOptions;
 use crate::init::set_sunxi_rota;
+use crate::nickel_menu::{NickelMenu, NickelMenuOptions};
+use crate::screenshot::StreamFormat;
 use crate::{errors::AppError, server::AppState};
 
 use std::fs;
@@ -28,14 +34,29 @@ pub struct Config {
     pub user: UserConfig,
 }
 
+#[serde_with::serde_as]
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct AppConfig {
     pub data_dir: PathBuf,
     pub udev_dir: PathBuf,
     pub user_dir: PathBuf,
+    pub nickel_menu_dir: PathBuf,
     pub port: u32,
     pub allow_remote_restart: bool,
     pub allow_remote_exit: bool,
+    /// How the events of actions are written when they're played
+    pub injection_backend: InjectionBackend,
+    /// Spin instead of sleeping for the last millisecond before each event when playing actions.
+    /// Keeps sub-millisecond gaps accurate at the cost of CPU time
+    pub playback_busy_wait: bool,
+    /// Merge requests to play the same action that queue up while it's playing, e.g. from tapping
+    /// next page repeatedly, into one batch
+    pub coalesce_plays: bool,
+    /// How many plays to keep in the history used for the statistics on the Manage Actions page
+    pub play_history_limit: usize,
+    /// How long to wait for the screen to change after playing an action that's verified
+    #[serde_as(as = "DurationMilliSeconds<i64>")]
+    pub effect_timeout: Duration,
 }
 
 impl Default for AppConfig {
@@ -44,9 +65,15 @@ impl Default for AppConfig {
             data_dir: "/opt/wifiremote/data".into(),
             udev_dir: "/etc/udev/rules.d".into(),
             user_dir: "/mnt/onboard/.adds/wifiremote".into(),
+            nickel_menu_dir: "/mnt/onboard/.adds/nm".into(),
             port: 80,
             allow_remote_restart: true,
             allow_remote_exit: false,
+            injection_backend: InjectionBackend::default(),
+            playback_busy_wait: false,
+            coalesce_plays: false,
+            play_history_limit: DEFAULT_HISTORY_LIMIT,
+            effect_timeout: DEFAULT_EFFECT_TIMEOUT,
         }
     }
 }
@@ -72,6 +99,7 @@ pub struct UserConfig {
     pub custom_action_defaults: CustomActionOptions,
     pub arbitrary_input: InputOptions,
     pub index: IndexOptions,
+    pub nickel_menu: NickelMenuOptions,
 }
 
 impl UserConfig {
@@ -106,6 +134,9 @@ impl Config {
     pub fn recordings_file(&self) -> PathBuf {
         self.app.data_dir.join("recordings.bin")
     }
+    pub fn play_history_file(&self) -> PathBuf {
+        self.app.data_dir.join("play-history.jsonl")
+    }
     pub fn udev_file(&self) -> PathBuf {
         self.app.data_dir.join("udev.rules")
     }
@@ -118,6 +149,9 @@ impl Config {
     pub fn file_list(&self) -> PathBuf {
         self.app.data_dir.join("tracked_files")
     }
+    pub fn nickel_menu_file(&self) -> PathBuf {
+        self.app.nickel_menu_dir.join("wifiremote-actions")
+    }
     pub fn version() -> &'static str {
         clap::crate_version!()
     }
@@ -174,8 +208,10 @@ pub struct SetupOptions {
     pub no_input_timeout: Duration,
     #[serde_as(as = "DurationMilliSeconds<i64>")]
     pub new_event_timeout: Duration,
+    /// Deprecated and ignored. Input is now read as soon as it arrives
     #[serde_as(as = "DurationMilliSeconds<i64>")]
     pub poll_wait: Duration,
+    pub exclusive_grab: bool,
 }
 
 impl Default for SetupOptions {
@@ -189,6 +225,7 @@ impl Default for SetupOptions {
             no_input_timeout: Duration::milliseconds(5000),
             new_event_timeout: Duration::milliseconds(250),
             poll_wait: Duration::milliseconds(10),
+            exclusive_grab: false,
         }
     }
 }
@@ -197,6 +234,7 @@ impl Default for SetupOptions {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CustomActionOptions {
     pub only_check_touch: bool,
+    pub only_check_keys: bool,
     pub optimize: bool,
     pub use_by_path: bool,
     #[serde_as(as = "DurationMilliSeconds<i64>")]
@@ -209,14 +247,17 @@ pub struct CustomActionOptions {
     pub no_input_timeout: Duration,
     #[serde_as(as = "DurationMilliSeconds<i64>")]
     pub new_event_timeout: Duration,
+    /// Deprecated and ignored. Input is now read as soon as it arrives
     #[serde_as(as = "DurationMilliSeconds<i64>")]
     pub poll_wait: Duration,
+    pub exclusive_grab: bool,
 }
 
 impl Default for CustomActionOptions {
     fn default() -> Self {
         Self {
             only_check_touch: true,
+            only_check_keys: false,
             optimize: false,
             use_by_path: false,
             optimize_max_duration: Duration::milliseconds(1000),
@@ -225,6 +266,7 @@ impl Default for CustomActionOptions {
             no_input_timeout: Duration::milliseconds(5000),
             new_event_timeout: Duration::milliseconds(4000),
             poll_wait: Duration::milliseconds(10),
+            exclusive_grab: false,
         }
     }
 }
@@ -235,6 +277,14 @@ pub struct RemoteOptions {
     pub color2: String,
     pub enable_arbitrary_input: bool,
     pub prompt_fullscreen: bool,
+    /// Show what's on the e-reader's screen above the buttons
+    pub show_screen: bool,
+    /// The most frames of the screen to send each second, from 0.1 to 10. Frames are only sent
+    /// when it changes
+    pub screen_max_fps: f64,
+    pub screen_format: StreamFormat,
+    /// Touch the e-reader where its screen is tapped or long pressed in the browser
+    pub tap_on_screen: bool,
 }
 
 impl Default for RemoteOptions {
@@ -244,6 +294,10 @@ impl Default for RemoteOptions {
             color2: "#33b249".into(),
             enable_arbitrary_input: false,
             prompt_fullscreen: false,
+            show_screen: false,
+            screen_max_fps: 2.0,
+            screen_format: StreamFormat::default(),
+            tap_on_screen: false,
         }
     }
 }
@@ -316,15 +370,22 @@ async fn update_user_toml(
         },
     };
     // If validation was successful, write the edited config to file and update the AppState
-    let mut config = state.config();
-    if config.user.fbink.sunxi_force_rota != new_config.fbink.sunxi_force_rota {
-        if let Ok(fbink) = state.fbink.try_inner() {
-            set_sunxi_rota(&new_config.fbink, fbink)
+    let nickel_menu = {
+        let mut config = state.config();
+        if config.user.fbink.sunxi_force_rota != new_config.fbink.sunxi_force_rota {
+            if let Ok(fbink) = state.fbink.try_inner() {
+                set_sunxi_rota(&new_config.fbink, fbink)
+            }
         }
+        fs::write(&config.user_config_path, toml::to_string(&new_config)?)
+            .context("Failed to write user config file")?;
+        let nickel_menu_changed = config.user.nickel_menu != new_config.nickel_menu;
+        config.user = new_config;
+        nickel_menu_changed.then(|| NickelMenu::new(&config))
+    };
+    if let Some(nickel_menu) = nickel_menu {
+        state.tx.send(ActionMsg::SetNickelMenu(nickel_menu)).await?;
     }
-    fs::write(&config.user_config_path, toml::to_string(&new_config)?)
-        .context("Failed to write user config file")?;
-    config.user = new_config;
     Ok(())
 }
 
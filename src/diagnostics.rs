@@ -0,0 +1,591 @@
+//! A checklist of common problems, used by the doctor subcommand and the /diagnostics endpoint
+use crate::actions::devices::InputDevices;
+use crate::actions::{ActionCoverage, ActionMsg};
+use crate::config::{AppConfig, Config, UserConfig};
+use crate::errors::AppError;
+use crate::fbink::FbInkWrapper;
+use crate::kobo_config::{KoboConfigFile, KoboConfigOptions};
+use crate::server::AppState;
+
+use std::fmt::Display;
+use std::fs;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Result;
+use axum::{extract::State, response::IntoResponse, routing::get, Json, Router};
+use fbink_rs::state::SunxiForceRotation;
+use fbink_rs::CanonicalRotation;
+use nix::sys::statvfs::statvfs;
+use serde::{Deserialize, Serialize};
+use strum::Display;
+use tokio::sync::oneshot;
+
+/// Warn when a filesystem we write to has less than this many bytes free
+const LOW_SPACE_WARN: u64 = 10 * 1024 * 1024;
+/// Fail when a filesystem we write to has less than this many bytes free
+const LOW_SPACE_FAIL: u64 = 1024 * 1024;
+/// How long to wait when connecting to the server's port
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/diagnostics", get(diagnostics))
+}
+
+async fn diagnostics(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(report(&state).await?))
+}
+
+/// Run the diagnostics from within the server
+pub async fn report(state: &AppState) -> Result<DiagnosticsReport> {
+    let (tx, rx) = oneshot::channel();
+    state.tx.send(ActionMsg::GetCoverage { resp: tx }).await?;
+    let coverage = rx.await?;
+    let (tx, rx) = oneshot::channel();
+    state
+        .tx
+        .send(ActionMsg::GetInputDevices { resp: tx })
+        .await?;
+    let (devices, _) = rx.await?;
+    let config = state.config().clone();
+    let fbink = state.fbink.clone();
+    // The checks read files and open devices, which would hold up other requests
+    let report = tokio::task::spawn_blocking(move || {
+        Diagnostics {
+            config: &config,
+            fbink: &fbink,
+            devices: devices.as_ref(),
+            kobo_config: &KoboConfigOptions::default().path,
+            server_pid: Some(std::process::id()),
+            coverage: &coverage,
+        }
+        .run()
+    })
+    .await?;
+    Ok(report)
+}
+
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub message: String,
+    /// What the user can do to fix the problem
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: impl Display, message: impl Display) -> Self {
+        Self::new(name, CheckStatus::Pass, message, None::<String>)
+    }
+
+    fn warn(name: impl Display, message: impl Display, hint: impl Display) -> Self {
+        Self::new(name, CheckStatus::Warn, message, Some(hint))
+    }
+
+    fn fail(name: impl Display, message: impl Display, hint: impl Display) -> Self {
+        Self::new(name, CheckStatus::Fail, message, Some(hint))
+    }
+
+    fn new(
+        name: impl Display,
+        status: CheckStatus,
+        message: impl Display,
+        hint: Option<impl Display>,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            message: message.to_string(),
+            hint: hint.map(|h| h.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DiagnosticsReport {
+    pub version: String,
+    pub checks: Vec<Check>,
+}
+
+impl DiagnosticsReport {
+    /// The worst status of all the checks
+    pub fn status(&self) -> CheckStatus {
+        self.checks
+            .iter()
+            .map(|c| c.status)
+            .max()
+            .unwrap_or(CheckStatus::Pass)
+    }
+}
+
+pub struct Diagnostics<'a> {
+    pub config: &'a Config,
+    pub fbink: &'a FbInkWrapper,
+    pub devices: &'a dyn InputDevices,
+    /// Where Nickel's config file is
+    pub kobo_config: &'a Path,
+    /// The PID of the server if the diagnostics are being run from within it
+    pub server_pid: Option<u32>,
+    pub coverage: &'a [ActionCoverage],
+}
+
+impl Diagnostics<'_> {
+    pub fn run(&self) -> DiagnosticsReport {
+        let mut checks = vec![
+            self.udev_link(),
+            self.server(),
+            self.port(),
+            self.user_config(),
+            self.app_config(),
+        ];
+        checks.extend(self.fbink());
+        checks.push(self.input_devices());
+        checks.extend(self.actions());
+        checks.push(self.free_space(&self.config.app.data_dir));
+        checks.push(self.free_space(&self.config.app.user_dir));
+        checks.push(self.force_wifi_on());
+        DiagnosticsReport {
+            version: Config::version().to_string(),
+            checks,
+        }
+    }
+
+    fn udev_link(&self) -> Check {
+        let name = "Run at boot";
+        let link = self.config.udev_link();
+        let l = link.display();
+        let hint = "Run `wifiremote enable` or use the NickelMenu entry to enable it";
+        if fs::symlink_metadata(&link).is_err() {
+            return Check::warn(name, format!("No UDEV rules symlink at {l}"), hint);
+        }
+        match fs::read_link(&link) {
+            Ok(target) if target == self.config.udev_file() && target.exists() => {
+                Check::pass(name, format!("{l} links to {}", target.display()))
+            }
+            Ok(target) if !target.exists() => Check::fail(
+                name,
+                format!("{l} links to {} which doesn't exist", target.display()),
+                "Reinstall Wi-Fi Remote, then run `wifiremote disable` and `wifiremote enable`",
+            ),
+            Ok(target) => Check::warn(
+                name,
+                format!(
+                    "{l} links to {} instead of the expected rules",
+                    target.display()
+                ),
+                "Run `wifiremote disable` and `wifiremote enable` to recreate it",
+            ),
+            Err(_) => Check::warn(
+                name,
+                format!("{l} isn't a symlink"),
+                format!("Delete {l} and run `wifiremote enable`"),
+            ),
+        }
+    }
+
+    fn server(&self) -> Check {
+        let name = "Server";
+        match self.server_pid {
+            Some(pid) => Check::pass(name, format!("Running with PID {pid}")),
+            None => Check::warn(
+                name,
+                "Not running",
+                "Start it with `wifiremote start` or the NickelMenu entry",
+            ),
+        }
+    }
+
+    fn port(&self) -> Check {
+        let name = "Port";
+        let port = self.config.app.port;
+        if self.server_pid.is_some() {
+            let connected = format!("127.0.0.1:{port}")
+                .parse::<SocketAddr>()
+                .map_err(anyhow::Error::from)
+                .and_then(|addr| Ok(TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?));
+            return match connected {
+                Ok(_) => Check::pass(name, format!("Server is listening on port {port}")),
+                Err(e) => Check::fail(
+                    name,
+                    format!("Unable to connect to the server on port {port}. {e}"),
+                    "Restart Wi-Fi Remote. If the problem persists, check that `port` in the app \
+                     config is the one it was started with",
+                ),
+            };
+        }
+        match TcpListener::bind(format!("0.0.0.0:{port}")) {
+            Ok(_) => Check::pass(name, format!("Port {port} is available")),
+            Err(e) => Check::fail(
+                name,
+                format!("Unable to bind to port {port}. {e}"),
+                "Stop any other program using the port or change `port` in the app config",
+            ),
+        }
+    }
+
+    fn user_config(&self) -> Check {
+        config_check(
+            "User config",
+            &self.config.user_config_path,
+            UserConfig::load,
+            "Fix the error on the Edit Config page, or delete the file to restore the defaults",
+        )
+    }
+
+    fn app_config(&self) -> Check {
+        config_check(
+            "App config",
+            &self.config.app_config_path,
+            AppConfig::load,
+            "Fix the error in the file, or delete it to restore the defaults",
+        )
+    }
+
+    fn fbink(&self) -> Vec<Check> {
+        let name = "FBInk";
+        let fbink = match self.fbink {
+            FbInkWrapper::Loaded(fbink) => fbink,
+            FbInkWrapper::Failed(e) => {
+                return vec![Check::fail(
+                    name,
+                    format!("Failed to initialize. {e}"),
+                    "Rotation detection and on-screen messages won't work. Include the log file \
+                     when reporting this problem",
+                )]
+            }
+            _ => {
+                let e = self.fbink.try_inner().unwrap_err();
+                return vec![Check::warn(
+                    name,
+                    e,
+                    "Rotation detection and on-screen messages won't work",
+                )];
+            }
+        };
+        let state = fbink.state();
+        let mut checks = vec![Check::pass(
+            name,
+            format!("Initialized on {} ({})", state.device_name, state.device_id),
+        )];
+        if state.is_sunxi {
+            let name = "Sunxi rotation";
+            let force_rota = self.config.user.fbink.sunxi_force_rota;
+            let fbdamage = if state.sunxi_has_fbdamage {
+                "fbdamage is loaded"
+            } else {
+                "fbdamage isn't loaded"
+            };
+            if force_rota == SunxiForceRotation::Workbuf && !state.sunxi_has_fbdamage {
+                checks.push(Check::warn(
+                    name,
+                    format!("sunxi_force_rota is Workbuf but {fbdamage}, so Gyro is used"),
+                    "Load fbdamage or choose a different sunxi_force_rota in the user config",
+                ));
+            } else {
+                checks.push(Check::pass(
+                    name,
+                    format!("sunxi_force_rota is {force_rota} and {fbdamage}"),
+                ));
+            }
+        }
+        checks
+    }
+
+    fn input_devices(&self) -> Check {
+        let name = "Input devices";
+        let devices = match self.devices.list(self.config.user.setup.use_by_path) {
+            Ok(d) => d,
+            Err(e) => {
+                return Check::fail(
+                    name,
+                    format!("{e:#}"),
+                    "Reboot your e-reader. If the problem persists, include the log file when \
+                     reporting it",
+                )
+            }
+        };
+        let mut touch = Vec::new();
+        let mut other = Vec::new();
+        for d in &devices {
+            if d.is_touch {
+                touch.push(d.to_string());
+            } else {
+                other.push(d.to_string());
+            }
+        }
+        let mut message = format!("Touch: {}", join_or_none(&touch));
+        message.push_str(&format!(". Other: {}", join_or_none(&other)));
+        if touch.is_empty() {
+            let hint = if self.config.user.setup.only_check_touch {
+                "Set only_check_touch to false in the setup options and try recording again"
+            } else {
+                "Check that the touchscreen is working"
+            };
+            Check::warn(name, message, hint)
+        } else {
+            Check::pass(name, message)
+        }
+    }
+
+    fn actions(&self) -> Vec<Check> {
+        if self.coverage.is_empty() {
+            return vec![Check::warn(
+                "Actions",
+                "No actions have been recorded",
+                "Record the page turns on the initial setup page",
+            )];
+        }
+        let current = self
+            .fbink
+            .try_inner()
+            .ok()
+            .and_then(|f| f.current_rotation().ok());
+        self.coverage
+            .iter()
+            .map(|a| action_check(a, current))
+            .collect()
+    }
+
+    fn free_space(&self, path: &Path) -> Check {
+        let p = path.display();
+        let name = format!("Free space ({p})");
+        let stat = match statvfs(path) {
+            Ok(s) => s,
+            Err(e) => {
+                return Check::warn(
+                    name,
+                    format!("Unable to check. {e}"),
+                    format!("Check that {p} exists"),
+                )
+            }
+        };
+        #[allow(clippy::useless_conversion)] // Types differ between 32 & 64 bit targets
+        let free = u64::from(stat.blocks_available()) * u64::from(stat.fragment_size());
+        let message = format!("{:.1} MiB available", free as f64 / 1024.0 / 1024.0);
+        let hint = "Delete some files so actions and settings can be saved";
+        if free < LOW_SPACE_FAIL {
+            Check::fail(name, message, hint)
+        } else if free < LOW_SPACE_WARN {
+            Check::warn(name, message, hint)
+        } else {
+            Check::pass(name, message)
+        }
+    }
+
+    fn force_wifi_on(&self) -> Check {
+        let name = "ForceWifiOn";
+        let opts = KoboConfigOptions {
+            path: self.kobo_config.to_path_buf(),
+            ..Default::default()
+        };
+        let value = match KoboConfigFile::open(opts) {
+            Ok(c) => c
+                .get_values()
+                .into_iter()
+                .find(|s| s.section == "DeveloperSettings" && s.key == "ForceWifiOn")
+                .and_then(|s| s.value),
+            Err(e) => {
+                return Check::warn(
+                    name,
+                    format!("{e:#}"),
+                    "Reboot your e-reader so the file is recreated",
+                )
+            }
+        };
+        let hint = "Enable it on the Developer Settings page, otherwise Wi-Fi may turn off while \
+                    you're reading";
+        match value.as_deref() {
+            Some("true") => Check::pass(name, "ForceWifiOn is true"),
+            Some(v) => Check::warn(name, format!("ForceWifiOn is {v}"), hint),
+            None => Check::warn(name, "ForceWifiOn isn't set", hint),
+        }
+    }
+}
+
+fn config_check<T>(
+    name: &str,
+    path: &Path,
+    load: impl Fn(&Path) -> Result<T>,
+    hint: &str,
+) -> Check {
+    let p = path.display();
+    if !path.exists() {
+        return Check::warn(
+            name,
+            format!("{p} doesn't exist. Using defaults"),
+            "Restart Wi-Fi Remote to create it",
+        );
+    }
+    match load(path) {
+        Ok(_) => Check::pass(name, format!("{p} is valid")),
+        Err(e) => Check::fail(name, format!("{p} is invalid. {e}"), hint),
+    }
+}
+
+fn action_check(action: &ActionCoverage, current: Option<CanonicalRotation>) -> Check {
+    let name = format!("Action: {}", action.name);
+    if action.rotations.is_empty() {
+        return Check::fail(
+            name,
+            "Has no recordings",
+            "Record it again or delete it on the Manage Actions page",
+        );
+    }
+    let rotations: Vec<_> = action.rotations.iter().map(|r| r.to_string()).collect();
+    let message = format!("Recorded in {}", rotations.join(", "));
+    match current {
+        Some(rotation) if !action.rotations.contains(&rotation) => Check::warn(
+            name,
+            format!("{message}, but not the current {rotation} rotation"),
+            format!("Record it while the e-reader is in {rotation} rotation"),
+        ),
+        _ => Check::pass(name, message),
+    }
+}
+
+fn join_or_none(items: &[String]) -> String {
+    if items.is_empty() {
+        "none".into()
+    } else {
+        items.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fakes::FakeInputDevices;
+    use pretty_assertions::assert_eq;
+
+    fn coverage(rotations: &[CanonicalRotation]) -> ActionCoverage {
+        ActionCoverage {
+            name: "Next Page".into(),
+            path_segment: "next-page".into(),
+            rotations: rotations.to_vec(),
+        }
+    }
+
+    #[test]
+    fn action_checks() {
+        let upright = Some(CanonicalRotation::Upright);
+        assert_eq!(
+            CheckStatus::Fail,
+            action_check(&coverage(&[]), upright).status
+        );
+        let check = action_check(&coverage(&[CanonicalRotation::Clockwise]), upright);
+        assert_eq!(CheckStatus::Warn, check.status);
+        assert!(check.hint.unwrap().contains("Upright"));
+        let check = action_check(&coverage(&[CanonicalRotation::Upright]), upright);
+        assert_eq!(CheckStatus::Pass, check.status);
+        // Rotation detection failing shouldn't warn about every action
+        let check = action_check(&coverage(&[CanonicalRotation::Clockwise]), None);
+        assert_eq!(CheckStatus::Pass, check.status);
+    }
+
+    #[test]
+    fn config_checks() {
+        let dir =
+            std::env::temp_dir().join(format!("wifiremote-diagnostics-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("user-config.toml");
+        let check = config_check("User config", &path, UserConfig::load, "Fix it");
+        assert_eq!(CheckStatus::Warn, check.status);
+        fs::write(&path, "setup = 1").unwrap();
+        let check = config_check("User config", &path, UserConfig::load, "Fix it");
+        assert_eq!(CheckStatus::Fail, check.status);
+        assert_eq!(Some("Fix it".into()), check.hint);
+        fs::write(&path, toml::to_string(&UserConfig::default()).unwrap()).unwrap();
+        let check = config_check("User config", &path, UserConfig::load, "Fix it");
+        assert_eq!(CheckStatus::Pass, check.status);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn port_check_connects_to_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut config = Config::default();
+        config.app.port = listener.local_addr().unwrap().port().into();
+        let diagnostics = Diagnostics {
+            config: &config,
+            fbink: &FbInkWrapper::DisabledInConfig,
+            devices: &FakeInputDevices::default(),
+            kobo_config: Path::new("Kobo eReader.conf"),
+            server_pid: Some(1),
+            coverage: &[],
+        };
+        assert_eq!(CheckStatus::Pass, diagnostics.port().status);
+        drop(listener);
+        let check = diagnostics.port();
+        assert_eq!(CheckStatus::Fail, check.status);
+        assert!(check.hint.is_some());
+    }
+
+    fn check(status: CheckStatus) -> Check {
+        Check::new("Check", status, "Message", None::<String>)
+    }
+
+    #[test]
+    fn report_status_is_worst_check() {
+        use CheckStatus::*;
+        let report = |statuses: &[CheckStatus]| DiagnosticsReport {
+            version: "0.0.0".into(),
+            checks: statuses.iter().map(|&s| check(s)).collect(),
+        };
+        assert_eq!(Pass, report(&[]).status());
+        assert_eq!(Pass, report(&[Pass, Pass]).status());
+        assert_eq!(Warn, report(&[Pass, Warn, Pass]).status());
+        assert_eq!(Fail, report(&[Warn, Fail, Pass]).status());
+    }
+
+    #[test]
+    fn checks_with_fakes() {
+        let dir = std::env::temp_dir().join(format!("wifiremote-doctor-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut config = Config::default();
+        config.app.data_dir = dir.clone();
+        config.app.user_dir = dir.clone();
+        config.app.udev_dir = dir.clone();
+        config.app.port = 0;
+        config.user_config_path = dir.join("user-config.toml");
+        config.app_config_path = dir.join("app-config.toml");
+        let kobo_config = dir.join("Kobo eReader.conf");
+        fs::write(&kobo_config, "[DeveloperSettings]\nForceWifiOn=true\n").unwrap();
+        let report = Diagnostics {
+            config: &config,
+            fbink: &FbInkWrapper::DisabledInConfig,
+            devices: &FakeInputDevices::default(),
+            kobo_config: &kobo_config,
+            server_pid: None,
+            coverage: &[coverage(&[CanonicalRotation::Upright])],
+        }
+        .run();
+        let find = |name: &str| report.checks.iter().find(|c| c.name == name).unwrap();
+        assert_eq!(CheckStatus::Warn, find("Run at boot").status);
+        assert_eq!(CheckStatus::Warn, find("Server").status);
+        assert_eq!(CheckStatus::Pass, find("Port").status);
+        assert_eq!(CheckStatus::Warn, find("User config").status);
+        assert_eq!(CheckStatus::Warn, find("FBInk").status);
+        let input = find("Input devices");
+        assert_eq!(CheckStatus::Pass, input.status);
+        assert!(
+            input.message.contains("Fake touchscreen"),
+            "{}",
+            input.message
+        );
+        assert_eq!(CheckStatus::Pass, find("Action: Next Page").status);
+        assert_eq!(CheckStatus::Pass, find("ForceWifiOn").status);
+        assert_eq!(CheckStatus::Warn, report.status());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,316 @@
+//! In-memory stand-ins for the hardware used by the remote, so it can be driven in tests
+use crate::actions::arbitrary::CursorCanvas;
+use crate::actions::devices::{DeviceDetails, InputDevices};
+use crate::actions::inject::MemorySink;
+use crate::actions::input::{DeviceEvents, DeviceFingerprint};
+use crate::actions::inspect::{DeviceInfo, MonitorEvent};
+use crate::actions::recording::{RecordingControl, RecordingProgress};
+use crate::actions::synthetic::TouchCapabilities;
+use crate::actions::{create_action_events, ActionRecording, RecordActionOptions};
+use crate::fbink::{RotationSource, Screen, ScreenInfo, ScreenSource};
+
+use std::collections::VecDeque;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use evdev_rs::enums::EventCode;
+use evdev_rs::{InputEvent, ReadStatus};
+use fbink_rs::image::{DynamicImage, GrayImage, ImageFormat, Luma};
+use fbink_rs::{CanonicalRotation, FbInkRect};
+use tokio::sync::mpsc;
+
+/// Input devices that return queued events instead of reading them. Clones share the same queue
+#[derive(Clone, Debug, Default)]
+pub struct FakeInputDevices {
+    reads: Arc<Mutex<VecDeque<Vec<InputEvent>>>>,
+    /// Whether a read with nothing queued waits to be aborted, like a user who never touches the
+    /// screen with a long timeout
+    hold: Arc<AtomicBool>,
+}
+
+impl FakeInputDevices {
+    /// The touchscreen all events are read from
+    pub fn touchscreen() -> DeviceDetails {
+        DeviceDetails {
+            path: "/dev/input/event1".into(),
+            name: "/dev/input/event1 (Fake touchscreen)".into(),
+            fingerprint: None,
+        }
+    }
+
+    /// The device physical buttons are pressed on
+    pub fn buttons() -> DeviceDetails {
+        DeviceDetails {
+            path: "/dev/input/event0".into(),
+            name: "/dev/input/event0 (Fake buttons)".into(),
+            fingerprint: None,
+        }
+    }
+
+    /// Queue events to be read from the touchscreen by the next recording
+    pub fn push(&self, events: Vec<InputEvent>) {
+        self.reads.lock().unwrap().push_back(events);
+    }
+
+    /// Make reads with nothing queued wait until their recording is aborted
+    pub fn hold_until_aborted(&self) {
+        self.hold.store(true, Ordering::SeqCst);
+    }
+}
+
+impl InputDevices for FakeInputDevices {
+    fn list(&self, _use_by_path: bool) -> Result<Vec<DeviceInfo>> {
+        let info = |details: DeviceDetails, name: &str, is_touch| DeviceInfo {
+            path: details.path,
+            name: Some(name.to_string()),
+            phys: None,
+            vendor_id: 0,
+            product_id: 0,
+            bustype: 0,
+            is_touch,
+            codes: Default::default(),
+            abs_ranges: Default::default(),
+        };
+        Ok(vec![
+            info(Self::buttons(), "Fake buttons", false),
+            info(Self::touchscreen(), "Fake touchscreen", true),
+        ])
+    }
+
+    fn paths(&self, _opts: &RecordActionOptions) -> Result<Vec<PathBuf>> {
+        Ok(vec![Self::touchscreen().path])
+    }
+
+    /// Sends the next queued read's events
+    fn monitor(
+        &self,
+        path: &Path,
+        _duration: chrono::Duration,
+        tx: mpsc::Sender<MonitorEvent>,
+    ) -> Result<()> {
+        if path != Self::touchscreen().path {
+            return Err(anyhow!("{} isn't the fake touchscreen", path.display()));
+        }
+        let events = self.reads.lock().unwrap().pop_front().unwrap_or_default();
+        for event in &events {
+            if tx
+                .blocking_send(MonitorEvent::new(event, ReadStatus::Success))
+                .is_err()
+            {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn read(
+        &self,
+        _opts: &RecordActionOptions,
+        control: &RecordingControl,
+    ) -> Result<Vec<(DeviceDetails, DeviceEvents)>> {
+        let Some(events) = self.reads.lock().unwrap().pop_front() else {
+            while self.hold.load(Ordering::SeqCst) && !control.is_aborted() {
+                thread::sleep(Duration::from_millis(10));
+            }
+            return Ok(Vec::new());
+        };
+        control.report(RecordingProgress::InputDetected {
+            devices: vec![Self::touchscreen().name],
+            events: events.len(),
+        });
+        let events = DeviceEvents {
+            events,
+            resynced: false,
+        };
+        Ok(vec![(Self::touchscreen(), events)])
+    }
+
+    fn resolve(
+        &self,
+        _fingerprint: Option<&DeviceFingerprint>,
+        last_path: &Path,
+    ) -> Result<PathBuf> {
+        Ok(last_path.to_path_buf())
+    }
+
+    fn multi_touch(&self, _use_by_path: bool) -> Result<(DeviceDetails, TouchCapabilities)> {
+        let touch = TouchCapabilities {
+            x: (0, 1071),
+            y: (0, 1447),
+            btn_touch: true,
+            btn_tool_doubletap: false,
+            pressure: None,
+            touch_major: None,
+        };
+        Ok((Self::touchscreen(), touch))
+    }
+
+    fn key_device(&self, _code: EventCode, _use_by_path: bool) -> Result<DeviceDetails> {
+        Ok(Self::buttons())
+    }
+}
+
+/// An upright recording of the events on the fake touchscreen
+pub fn fake_recording(events: &[InputEvent]) -> ActionRecording {
+    ActionRecording {
+        rotation: CanonicalRotation::Upright,
+        events: create_action_events(events),
+        dev_path: "/dev/input/event1".into(),
+        dev_name: "/dev/input/event1 (test)".into(),
+        is_optimized: false,
+        resynced: false,
+        fingerprint: None,
+    }
+}
+
+/// A rotation that only changes when a test changes it. Clones share the same rotation
+#[derive(Clone, Debug)]
+pub struct FakeRotation(pub Arc<Mutex<CanonicalRotation>>);
+
+impl FakeRotation {
+    pub fn new(rotation: CanonicalRotation) -> Self {
+        Self(Arc::new(Mutex::new(rotation)))
+    }
+
+    pub fn set(&self, rotation: CanonicalRotation) {
+        *self.0.lock().unwrap() = rotation;
+    }
+}
+
+impl RotationSource for FakeRotation {
+    fn current_rotation(&self) -> Result<CanonicalRotation> {
+        Ok(*self.0.lock().unwrap())
+    }
+}
+
+/// A screen that's redrawn whenever events are written to the sink, like a reader turning the page,
+/// until it's frozen. It's drawn on with a FakeCanvas. Clones share the same state
+#[derive(Clone, Debug)]
+pub struct FakeScreen {
+    sink: MemorySink,
+    rotation: FakeRotation,
+    frozen: Arc<AtomicBool>,
+    pub canvas: FakeCanvas,
+    /// How many times the screen has been refreshed, which reinitializes FBInk on a reader
+    pub refreshes: Arc<AtomicUsize>,
+}
+
+impl FakeScreen {
+    /// The width and height of the screen when upright, matching the fake touchscreen
+    pub const SIZE: (u32, u32) = (1072, 1448);
+
+    pub fn new(sink: MemorySink, rotation: FakeRotation) -> Self {
+        Self {
+            sink,
+            rotation,
+            frozen: Arc::default(),
+            canvas: FakeCanvas::default(),
+            refreshes: Arc::default(),
+        }
+    }
+
+    /// Stop the screen changing, like a reader that missed a touch
+    pub fn freeze(&self) {
+        self.frozen.store(true, Ordering::SeqCst);
+    }
+}
+
+impl ScreenSource for FakeScreen {
+    fn capture(&self) -> Result<Vec<u8>> {
+        if self.frozen.load(Ordering::SeqCst) {
+            return Ok(Vec::new());
+        }
+        let events = self.sink.events.lock().unwrap().len();
+        Ok(events.to_le_bytes().to_vec())
+    }
+}
+
+impl RotationSource for FakeScreen {
+    fn current_rotation(&self) -> Result<CanonicalRotation> {
+        self.rotation.current_rotation()
+    }
+}
+
+impl Screen for FakeScreen {
+    fn refresh(&self) -> Result<ScreenInfo> {
+        self.refreshes.fetch_add(1, Ordering::SeqCst);
+        let rotation = self.rotation.current_rotation()?;
+        let (width, height) = match rotation {
+            CanonicalRotation::Upright | CanonicalRotation::UpsideDown => Self::SIZE,
+            CanonicalRotation::Clockwise | CanonicalRotation::CounterClockwise => {
+                (Self::SIZE.1, Self::SIZE.0)
+            }
+        };
+        Ok(ScreenInfo {
+            device_id: "fake".into(),
+            rotation,
+            width,
+            height,
+            native_touch: Default::default(),
+        })
+    }
+
+    /// A tiny image whose shade changes whenever the screen does
+    fn screenshot(&self, format: ImageFormat) -> Result<Vec<u8>> {
+        let shade = self.capture()?.first().copied().unwrap_or(255);
+        let image = DynamicImage::ImageLuma8(GrayImage::from_pixel(4, 4, Luma([shade])));
+        let mut bytes = Cursor::new(Vec::new());
+        image.write_to(&mut bytes, format)?;
+        Ok(bytes.into_inner())
+    }
+
+    fn canvas(&self) -> Result<Box<dyn CursorCanvas>> {
+        Ok(Box::new(self.canvas.clone()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CanvasCall {
+    SaveBackground,
+    /// Restore the background where the cursor was drawn at left, top
+    Restore(u16, u16),
+    Draw(u32, u32),
+}
+
+/// Records the drawing operations of the CursorManager. Clones share the same calls
+#[derive(Clone, Debug, Default)]
+pub struct FakeCanvas {
+    pub calls: Arc<Mutex<Vec<CanvasCall>>>,
+}
+
+impl FakeCanvas {
+    pub fn calls(&self) -> Vec<CanvasCall> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+impl CursorCanvas for FakeCanvas {
+    fn save_background(&mut self) -> Result<()> {
+        self.calls.lock().unwrap().push(CanvasCall::SaveBackground);
+        Ok(())
+    }
+
+    fn restore(&mut self, rect: FbInkRect) -> Result<()> {
+        let mut calls = self.calls.lock().unwrap();
+        if !calls.contains(&CanvasCall::SaveBackground) {
+            return Err(anyhow!("No background has been saved"));
+        }
+        calls.push(CanvasCall::Restore(rect.left, rect.top));
+        Ok(())
+    }
+
+    fn draw(&mut self, cursor: &DynamicImage, x: u32, y: u32) -> Result<FbInkRect> {
+        self.calls.lock().unwrap().push(CanvasCall::Draw(x, y));
+        Ok(FbInkRect {
+            left: x as u16,
+            top: y as u16,
+            width: cursor.width() as u16,
+            height: cursor.height() as u16,
+        })
+    }
+}
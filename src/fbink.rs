@@ -1,9 +1,11 @@
+use crate::actions::arbitrary::{CursorCanvas, FbInkCanvas, TouchTransform};
 use crate::config::Config;
 
 use std::sync::Arc;
 
 use anyhow::{bail, Context, Result};
-use fbink_rs::{config::Font, FbInk, FbInkConfig};
+use fbink_rs::{config::Font, image::ImageFormat, CanonicalRotation, FbInk, FbInkConfig};
+use num_enum::FromPrimitive;
 use tracing::error;
 
 #[derive(Clone, Debug)]
@@ -58,3 +60,121 @@ impl FbInkWrapper {
         matches!(self, Self::DisabledInConfig | Self::DisabledAtBuild)
     }
 }
+
+/// Detects the rotation of the screen, so actions are played in the rotation they were recorded in
+pub trait RotationSource: Send + Sync {
+    fn current_rotation(&self) -> Result<CanonicalRotation>;
+    /// How the touchscreen is transformed in its native rotation
+    fn native_touch_transform(&self) -> TouchTransform {
+        TouchTransform::default()
+    }
+}
+
+impl RotationSource for FbInkWrapper {
+    fn current_rotation(&self) -> Result<CanonicalRotation> {
+        // Use FBInk for rotation detection if possible. Otherwise read it from the framebuffer
+        // ourself.
+        if let Ok(fbink) = self.try_inner() {
+            Ok(fbink
+                .current_rotation()
+                .context("FBInk failed to read device rotation")?)
+        } else if let Ok(framebuffer) = framebuffer::Framebuffer::new("/dev/fb0") {
+            // This will be the native rotation rather than the canonical, and won't actually
+            // change when some models rotate. We'll just pretend it's the canonical rotation,
+            // so at the very least the remote functions in a single rotation on devices that
+            // can't detect it properly.
+            let rota: u8 = framebuffer
+                .var_screen_info
+                .rotate
+                .try_into()
+                .unwrap_or_default();
+            Ok(CanonicalRotation::from_primitive(rota))
+        } else {
+            Ok(CanonicalRotation::Upright)
+        }
+    }
+
+    fn native_touch_transform(&self) -> TouchTransform {
+        // Without FBInk, assume the touchscreen's axes match the screen's
+        self.try_inner()
+            .map(|fbink| TouchTransform::native(fbink))
+            .unwrap_or_default()
+    }
+}
+
+/// The fraction of the screen's height left out of captures at the top and bottom, where Nickel
+/// shows the clock, battery and reading progress
+const CAPTURE_MARGIN: f64 = 0.1;
+
+/// Captures what's on the screen, so a play can be checked for having had an effect
+pub trait ScreenSource: Send + Sync {
+    /// Captures are only compared with each other, so their format doesn't matter
+    fn capture(&self) -> Result<Vec<u8>>;
+}
+
+impl ScreenSource for FbInkWrapper {
+    /// The raw pixels of the middle of the screen. A dump skips the encoding a screenshot would
+    /// spend time on
+    fn capture(&self) -> Result<Vec<u8>> {
+        let fbink = self.try_inner()?;
+        let state = fbink.state();
+        let margin = (state.screen_height as f64 * CAPTURE_MARGIN) as u32;
+        let dump = fbink
+            .region_dump(
+                0,
+                margin as i16,
+                state.screen_width as u16,
+                (state.screen_height - margin * 2) as u16,
+            )
+            .context("Failed to get FBInk dump")?;
+        Ok(dump.data().to_vec())
+    }
+}
+
+/// What the routes that draw on or capture the screen need to know about it
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScreenInfo {
+    pub device_id: String,
+    pub rotation: CanonicalRotation,
+    pub width: u32,
+    pub height: u32,
+    /// How the touchscreen is transformed in its native rotation
+    pub native_touch: TouchTransform,
+}
+
+/// The FBInk operations used by the routes that show, draw on or tap the screen, so they can be
+/// tested without a framebuffer
+pub trait Screen: ScreenSource + RotationSource {
+    /// Reinitialize to pick up any change in rotation, then describe the screen
+    fn refresh(&self) -> Result<ScreenInfo>;
+    /// An image of the screen in the given format
+    fn screenshot(&self, format: ImageFormat) -> Result<Vec<u8>>;
+    /// Something to draw the cursor or calibration targets with. FBInk dumps might not be Send,
+    /// so it should be created on the thread that uses it
+    fn canvas(&self) -> Result<Box<dyn CursorCanvas>>;
+}
+
+impl Screen for FbInkWrapper {
+    fn refresh(&self) -> Result<ScreenInfo> {
+        let fbink = self.try_inner()?;
+        fbink.reinit().context("Failed to reinitialize FBInk")?;
+        let state = fbink.state();
+        Ok(ScreenInfo {
+            rotation: state.canonical_rotation(),
+            width: state.screen_width,
+            height: state.screen_height,
+            native_touch: TouchTransform::native(fbink),
+            device_id: state.device_id,
+        })
+    }
+
+    fn screenshot(&self, format: ImageFormat) -> Result<Vec<u8>> {
+        self.try_inner()?
+            .screenshot(format)
+            .context("FBInk failed to capture the screen")
+    }
+
+    fn canvas(&self) -> Result<Box<dyn CursorCanvas>> {
+        Ok(Box::new(FbInkCanvas::new(self.try_inner()?.clone())))
+    }
+}
@@ -26,6 +26,7 @@ pub struct IndexOptions {
     pub screenshot: IndexItemOptions,
     pub edit_config: IndexItemOptions,
     pub auto_turner: IndexItemOptions,
+    pub reading_stats: IndexItemOptions,
     pub voice_activation: IndexItemOptions,
     pub developer_settings: IndexItemOptions,
     pub troubleshooting: IndexItemOptions,
@@ -52,6 +53,7 @@ impl Default for IndexOptions {
             screenshot: opts("Screenshot", 50, true),
             edit_config: opts("Edit Config", 60, true),
             auto_turner: opts("Auto Turner", 70, true),
+            reading_stats: opts("Reading Stats", 75, true),
             voice_activation: opts("Voice Activation", 80, true),
             developer_settings: opts("Developer Settings", 90, true),
             troubleshooting: opts("Troubleshooting", 100, true),
@@ -61,7 +63,7 @@ impl Default for IndexOptions {
 
 impl IndexOptions {
     pub fn items(&self) -> Vec<IndexItem> {
-        let mut items = Vec::with_capacity(10);
+        let mut items = Vec::with_capacity(11);
         for (href, opts) in [
             ("setup", &self.setup),
             ("page-turner", &self.page_turner),
@@ -70,6 +72,7 @@ impl IndexOptions {
             ("edit-config", &self.edit_config),
             ("screenshot", &self.screenshot),
             ("auto-turner", &self.auto_turner),
+            ("reading-stats", &self.reading_stats),
             ("voice-activation", &self.voice_activation),
             ("developer-settings", &self.developer_settings),
             ("troubleshooting", &self.troubleshooting),
@@ -4,6 +4,7 @@ const toggle_btn = document.getElementById("toggle-auto-turner");
 const pause_btn = document.getElementById("pause-auto-turner");
 const next_btn = document.getElementById("next-page");
 const prev_btn = document.getElementById("prev-page");
+const pace_btn = document.getElementById("use-measured-pace");
 let interval = null;
 let counter = document.getElementById("next-turn-value");
 let isPaused = false;
@@ -17,6 +18,12 @@ next_btn.onclick = async () => {
 prev_btn.onclick = async () => {
   await playAction("prev-page");
 };
+if (pace_btn) {
+  pace_btn.onclick = () => {
+    document.getElementById("page-turn-delay").value = pace_btn.dataset.pace;
+    resetCounter();
+  };
+}
 
 function pause() {
   isPaused = true;
@@ -55,7 +62,8 @@ async function handleTick() {
   }
   timeUntilNext -= 1;
   if (timeUntilNext < 1) {
-    await playAction("next-page");
+    // Timed turns are left out of the measured reading pace
+    await playAction("next-page", "?automatic=true");
     resetCounter();
   } else {
     counter.innerHTML = timeUntilNext;
@@ -1,10 +1,10 @@
 // SPDX-FileCopyrightText: 2023 sublipri <sublipri@proton.me>
 // SPDX-License-Identifier: GPL-3.0-only
 
-export async function playAction(path_segment) {
+export async function playAction(path_segment, query = "") {
   var response;
   try {
-    response = await fetch(`/actions/${path_segment}`, {
+    response = await fetch(`/actions/${path_segment}${query}`, {
       signal: AbortSignal.timeout(5000),
     });
   } catch (err) {
@@ -67,6 +67,8 @@ export function processForm(form) {
     }
     if (input.className === "input-number") {
       data[input.name] = parseInt(input.value, 10);
+    } else if (input.className === "input-float") {
+      data[input.name] = parseFloat(input.value);
     } else if (input.type === "checkbox") {
       data[input.name] = input.checked;
     } else {
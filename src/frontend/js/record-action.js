@@ -3,6 +3,8 @@
 
 import { processForm, displayMsg } from "/js/lib.js";
 const timers = {};
+// The id of the recording each button started, so it can be aborted
+const recordings = {};
 
 function reset_alerts() {
   const buttons = document.getElementsByClassName("records-input");
@@ -26,6 +28,7 @@ export function setup() {
       <div class="modal-content">
         <span id="${button.id}-close-modal" class="close">&times;</span>
         <p>Recording Input</p>
+        <p id="${button.id}-progress">Waiting for input</p>
         <div id="${button.id}-countdown"></div>
       </div>
     </div>
@@ -33,11 +36,14 @@ export function setup() {
     document.body.insertAdjacentHTML("beforeend", alert_recording_modal);
 
     const close = document.getElementById(`${button.id}-close-modal`);
-    close.onclick = function () {
+    close.onclick = async function () {
       const modal = document.getElementById(`${button.id}-modal`);
       modal.style.display = "none";
       clearInterval(timers[button.id]);
-      window.stop();
+      const id = recordings[button.id];
+      if (id !== undefined) {
+        await fetch(`/actions/recordings/${id}`, { method: "DELETE" });
+      }
     };
   }
 }
@@ -45,6 +51,7 @@ export function setup() {
 export function alertRecording(timeleft, id) {
   const modal = document.getElementById(`${id}-modal`);
   const countdown = document.getElementById(`${id}-countdown`);
+  document.getElementById(`${id}-progress`).innerHTML = "Waiting for input";
   countdown.innerHTML = timeleft;
   modal.style.display = "block";
   const timer = setInterval(function () {
@@ -73,14 +80,27 @@ export async function recordAction(form, id) {
     },
     body: payload,
   };
+  const response = await fetch("/actions/recordings", config);
+  if (!response.ok) {
+    displayMsg(await response.text());
+    return;
+  }
+  const { id: recordingId } = await response.json();
+  recordings[id] = recordingId;
   alertRecording(data.no_input_timeout / 1000, id);
-  const response = await fetch("/actions", config);
+  const progress = await followRecording(recordingId, id);
+  delete recordings[id];
   reset_alerts();
-  if (response.ok) {
-    const recorded = await response.json();
+  if (progress.state === "finished") {
+    const recorded = progress.action;
     const is_page_turn =
       data.path_segment === "next-page" || data.path_segment === "prev-page";
-    if (is_page_turn && !recorded.was_optimized) {
+    if (recorded.resynced) {
+      displayMsg(
+        `Recorded ${recorded.name} in ${recorded.rotation} rotation. <strong>Warning</strong>:
+         Some input was lost while recording. If the action doesn't work as expected, record it again`,
+      );
+    } else if (is_page_turn && !recorded.was_optimized) {
       displayMsg(
         `Recorded ${recorded.name} in ${recorded.rotation} rotation. <strong>Warning</strong>: 
          Optimization failed. For faster page turns, try again and perform a quick swipe with a single finger`,
@@ -91,7 +111,31 @@ export async function recordAction(form, id) {
         1500,
       );
     }
-  } else {
-    displayMsg(await response.text());
+  } else if (progress.state === "failed") {
+    displayMsg(progress.error);
+  } else if (progress.state === "aborted") {
+    displayMsg("Recording aborted", 1000);
   }
 }
+
+// Show the progress of a recording until it's done, returning the final progress
+function followRecording(recordingId, id) {
+  return new Promise((resolve) => {
+    const status = document.getElementById(`${id}-progress`);
+    const socket = new WebSocket(
+      `ws://${location.host}/actions/recordings/${recordingId}`,
+    );
+    let last = { state: "failed", error: "Lost connection to the recording" };
+    socket.onmessage = (event) => {
+      last = JSON.parse(event.data);
+      if (last.state === "input_detected") {
+        // Input stops the countdown until there's no new input
+        clearInterval(timers[id]);
+        document.getElementById(`${id}-countdown`).innerHTML = "";
+        status.innerHTML = `Input detected on ${last.devices.join(", ")}
+          (${last.events} events)`;
+      }
+    };
+    socket.onclose = () => resolve(last);
+  });
+}
@@ -0,0 +1,62 @@
+// Show frames of the e-reader's screen as the server sends them
+import { displayMsg } from "/js/lib.js";
+
+const screen = document.getElementById("screen-mirror");
+const status = document.getElementById("screen-mirror-status");
+let frameUrl = null;
+
+function connect() {
+  const socket = new WebSocket(`ws://${location.host}/screen/stream`);
+  socket.binaryType = "blob";
+  socket.onmessage = (event) => {
+    const frame = new Blob([event.data], { type: screen.dataset.mime });
+    const url = URL.createObjectURL(frame);
+    screen.src = url;
+    if (frameUrl) {
+      URL.revokeObjectURL(frameUrl);
+    }
+    frameUrl = url;
+    status.style.display = "none";
+  };
+  socket.onclose = () => {
+    status.style.display = "block";
+    // Keep trying, e.g. after the e-reader wakes from sleep
+    setTimeout(connect, 5000);
+  };
+}
+
+connect();
+
+// Touch the e-reader at the same point, for as long as the image was pressed
+if (screen.classList.contains("tappable")) {
+  let pressed = null;
+  screen.addEventListener("contextmenu", (event) => event.preventDefault());
+  screen.addEventListener("pointerdown", (event) => {
+    event.preventDefault();
+    pressed = {
+      x: event.offsetX / screen.clientWidth,
+      y: event.offsetY / screen.clientHeight,
+      time: performance.now(),
+    };
+  });
+  screen.addEventListener("pointerup", async () => {
+    if (!pressed) {
+      return;
+    }
+    const tap = {
+      x: pressed.x,
+      y: pressed.y,
+      hold: Math.round(performance.now() - pressed.time),
+    };
+    pressed = null;
+    const response = await fetch("/screen/tap", {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify(tap),
+    });
+    if (!response.ok) {
+      await displayMsg(await response.text(), 5000);
+    }
+  });
+  screen.addEventListener("pointerleave", () => (pressed = null));
+}
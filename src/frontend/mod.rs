@@ -1,4 +1,11 @@
-use crate::{actions::ActionMsg, errors::AppError, kobo_config::KoboConfigFile, server::AppState};
+use crate::{
+    actions::{history, ActionMsg},
+    diagnostics,
+    errors::AppError,
+    kobo_config::KoboConfigFile,
+    server::AppState,
+    stats::PeriodStats,
+};
 
 use std::{collections::HashMap, fs};
 
@@ -25,10 +32,12 @@ pub fn routes() -> Router<AppState> {
             "/troubleshooting",
             get(|| async { templates::Troubleshooting {} }),
         )
+        .route("/doctor", get(doctor))
         .route("/custom-actions", get(custom_actions))
         .route("/manage-actions", get(manage_actions))
         .route("/remote-control", get(remote_control))
         .route("/auto-turner", get(auto_turner))
+        .route("/reading-stats", get(reading_stats))
         .route("/developer-settings", get(developer_settings))
         .route("/voice-activation", get(voice_activation))
         .route("/edit-config", get(edit_config))
@@ -39,6 +48,7 @@ pub fn routes() -> Router<AppState> {
         .route("/js/lib.js", get(lib_js))
         .route("/js/arbitrary-input.js", get(arbitrary_input_js))
         .route("/js/auto-turner.js", get(auto_turner_js))
+        .route("/js/screen-mirror.js", get(screen_mirror_js))
 }
 
 async fn main_css() -> impl IntoResponse {
@@ -117,13 +127,40 @@ async fn auto_turner(State(state): State<AppState>) -> Result<impl IntoResponse,
         next: page_turns.next,
         prev: page_turns.prev,
         delay: state.config().user.auto_turner.default_delay,
+        measured_pace: state.reading.measured_pace().map(|p| p.round() as i64),
+    })
+}
+
+async fn reading_stats(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let summary = state.reading.summary(14, 8);
+    Ok(templates::ReadingStats {
+        days: chart_bars(&summary.days, "%a %d"),
+        weeks: chart_bars(&summary.weeks, "%d %b"),
+        summary,
     })
 }
 
+fn chart_bars(periods: &[PeriodStats], label_format: &str) -> Vec<templates::ChartBar> {
+    let most_pages = periods.iter().map(|p| p.pages).max().unwrap_or(0).max(1);
+    periods
+        .iter()
+        .map(|p| templates::ChartBar {
+            label: p.start.format(label_format).to_string(),
+            pages: p.pages,
+            minutes: p.reading_time.num_minutes(),
+            width: p.pages * 100 / most_pages,
+        })
+        .collect()
+}
+
 async fn auto_turner_js() -> impl IntoResponse {
     (js_header(), include_str!("js/auto-turner.js"))
 }
 
+async fn screen_mirror_js() -> impl IntoResponse {
+    (js_header(), include_str!("js/screen-mirror.js"))
+}
+
 async fn developer_settings() -> Result<impl IntoResponse, AppError> {
     let config = KoboConfigFile::open(Default::default())?;
     let settings = config.get_values();
@@ -137,6 +174,12 @@ async fn voice_activation(State(state): State<AppState>) -> Result<impl IntoResp
     })
 }
 
+async fn doctor(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    Ok(templates::Doctor {
+        report: diagnostics::report(&state).await?,
+    })
+}
+
 async fn setup(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
     let (is_sunxi, device_name) = if let Ok(fbink) = state.fbink.try_inner() {
         let fb_state = fbink.state();
@@ -163,7 +206,8 @@ async fn manage_actions(State(state): State<AppState>) -> Result<impl IntoRespon
     let (tx, rx) = oneshot::channel();
     state.tx.send(ActionMsg::List { resp: tx }).await?;
     let actions = rx.await?;
-    Ok(templates::ManageActions { actions })
+    let stats = history::play_stats(&history::history(&state).await?);
+    Ok(templates::ManageActions { actions, stats })
 }
 
 async fn edit_config(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
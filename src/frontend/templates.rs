@@ -3,10 +3,12 @@ use chrono::Duration;
 
 use super::index::IndexItem;
 use crate::{
-    actions::ActionDetails,
+    actions::{history::ActionStats, ActionDetails},
     config::{CustomActionOptions, PageTurnerOptions, RemoteOptions, SetupOptions},
+    diagnostics::{CheckStatus, DiagnosticsReport},
     frontend::index::IndexOptions,
     kobo_config::KoboConfigSetting,
+    stats::ReadingSummary,
 };
 
 #[derive(Template)]
@@ -44,6 +46,12 @@ pub struct PageTurner {
 #[template(path = "troubleshooting.html")]
 pub struct Troubleshooting {}
 
+#[derive(Template)]
+#[template(path = "doctor.html")]
+pub struct Doctor {
+    pub report: DiagnosticsReport,
+}
+
 #[derive(Template)]
 #[template(path = "developer-settings.html")]
 pub struct DeveloperSettings {
@@ -62,6 +70,7 @@ pub struct RemoteControl {
 #[template(path = "manage-actions.html")]
 pub struct ManageActions {
     pub actions: Vec<ActionDetails>,
+    pub stats: Vec<ActionStats>,
 }
 
 #[derive(Template)]
@@ -70,6 +79,25 @@ pub struct AutoTurner {
     pub next: Option<ActionDetails>,
     pub prev: Option<ActionDetails>,
     pub delay: Duration,
+    /// The reader's measured seconds per page, rounded
+    pub measured_pace: Option<i64>,
+}
+
+#[derive(Template)]
+#[template(path = "reading-stats.html")]
+pub struct ReadingStats {
+    pub summary: ReadingSummary,
+    pub days: Vec<ChartBar>,
+    pub weeks: Vec<ChartBar>,
+}
+
+/// A bar in a chart of the pages read in a period
+pub struct ChartBar {
+    pub label: String,
+    pub pages: u32,
+    pub minutes: i64,
+    /// As a percentage of the longest bar
+    pub width: u32,
 }
 
 #[derive(Template)]
@@ -6,7 +6,7 @@ use std::{
     collections::HashSet,
     fs::{self, File},
     io::{BufRead, BufReader, LineWriter, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::Command,
     sync::Arc,
     thread::{self, sleep},
@@ -96,6 +96,23 @@ fn merge_files(old_files: PathBuf, new_files: PathBuf) -> Result<()> {
     Ok(())
 }
 
+/// Add a dynamically generated file to the list of files removed when uninstalling
+pub fn track_file(file_list: &Path, path: &Path) -> Result<()> {
+    let path = path.display().to_string();
+    let mut contents = fs::read_to_string(file_list).unwrap_or_default();
+    if contents.lines().any(|l| l == path) {
+        return Ok(());
+    }
+    debug!("Adding {path} to {}", file_list.display());
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    contents.push_str(&path);
+    contents.push('\n');
+    fs::write(file_list, contents)
+        .with_context(|| format!("Failed to write {}", file_list.display()))
+}
+
 fn set_force_wifi_on() -> Result<()> {
     let mut kobo_config = KoboConfigFile::open(Default::default())?;
     if kobo_config.set_value("DeveloperSettings", "ForceWifiOn", Some("true"))? {
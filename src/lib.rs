@@ -1,13 +1,19 @@
 pub mod actions;
 pub mod cli;
+pub mod client;
 pub mod config;
+pub mod diagnostics;
 pub mod errors;
+#[cfg(test)]
+pub mod fakes;
 pub mod fbink;
 pub mod frontend;
 pub mod init;
 pub mod kobo_config;
 pub mod logging;
 pub mod management;
+pub mod nickel_menu;
 pub mod screenshot;
 pub mod server;
+pub mod stats;
 pub mod util;
@@ -0,0 +1,205 @@
+//! Generates a NickelMenu config file with an entry for each action. Entries for toggling the
+//! server and taking screenshots aren't generated because the package already installs them in
+//! `.adds/nm/wifiremote`, and they'd appear twice
+use crate::actions::ActionDetails;
+use crate::config::Config;
+use crate::init::track_file;
+
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use strum::Display;
+use tracing::{debug, warn};
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct NickelMenuOptions {
+    pub enabled: bool,
+    pub location: NickelMenuLocation,
+    /// Trigger actions with a request to the server instead of running the CLI
+    pub use_http: bool,
+    /// How long in seconds to wait before playing the action, so the menu has time to close
+    pub delay: u64,
+}
+
+impl Default for NickelMenuOptions {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            location: NickelMenuLocation::Reader,
+            use_http: false,
+            delay: 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Display, PartialEq, Deserialize, Serialize)]
+#[strum(serialize_all = "lowercase")]
+pub enum NickelMenuLocation {
+    Main,
+    Reader,
+    Browser,
+    Library,
+}
+
+pub struct NickelMenu {
+    opts: NickelMenuOptions,
+    path: PathBuf,
+    file_list: PathBuf,
+    bin_path: PathBuf,
+    port: u32,
+}
+
+impl NickelMenu {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            opts: config.user.nickel_menu.clone(),
+            path: config.nickel_menu_file(),
+            file_list: config.file_list(),
+            bin_path: std::env::current_exe().unwrap_or("/opt/wifiremote/bin/wifiremote".into()),
+            port: config.app.port,
+        }
+    }
+
+    /// Write the config file, or remove it if the feature is disabled or there are no actions
+    pub fn sync(&self, actions: &[ActionDetails]) -> Result<()> {
+        let p = self.path.display();
+        if !self.opts.enabled || actions.is_empty() {
+            if self.path.exists() {
+                debug!("Removing NickelMenu config at {p}");
+                fs::remove_file(&self.path).with_context(|| format!("Failed to remove {p}"))?;
+            }
+            return Ok(());
+        }
+        let contents = self.generate(actions);
+        if fs::read_to_string(&self.path).is_ok_and(|c| c == contents) {
+            return Ok(());
+        }
+        debug!("Writing NickelMenu config to {p}");
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create {p}"))?;
+        }
+        fs::write(&self.path, contents).with_context(|| format!("Failed to write {p}"))?;
+        track_file(&self.file_list, &self.path)
+    }
+
+    fn generate(&self, actions: &[ActionDetails]) -> String {
+        let mut contents = String::from(
+            "# Generated by Wi-Fi Remote whenever actions change. Manual edits will be lost.\n",
+        );
+        let location = self.opts.location;
+        let delay = self.opts.delay;
+        let bin = quote(&self.bin_path.display().to_string());
+        for action in actions {
+            // Path segments are checked when actions are added, but the actions file can be
+            // edited by hand and a line break would end the menu item
+            let path_segment = &action.path_segment;
+            if path_segment.contains(|c: char| c.is_control()) {
+                warn!("Skipped NickelMenu entry for invalid path segment {path_segment:?}");
+                continue;
+            }
+            let cmd = if self.opts.use_http {
+                let url = format!("http://127.0.0.1:{}/actions/{path_segment}", self.port);
+                format!("sleep {delay}; wget -q -O /dev/null {}", quote(&url))
+            } else {
+                format!("{bin} play {} --delay {delay}", quote(path_segment))
+            };
+            // NickelMenu uses colons as separators and doesn't support escaping them in labels
+            let label = action
+                .name
+                .replace(|c: char| c == ':' || c.is_control(), " ");
+            contents.push_str(&format!(
+                "menu_item :{location} :{label} :cmd_spawn :quiet:{cmd}\n"
+            ));
+        }
+        contents
+    }
+}
+
+/// Quote an argument for the shell that cmd_spawn runs commands with
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NickelMenu, NickelMenuLocation, NickelMenuOptions};
+    use crate::actions::ActionDetails;
+
+    use chrono::Duration;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    fn action(name: &str, path_segment: &str) -> ActionDetails {
+        ActionDetails {
+            name: name.into(),
+            path_segment: path_segment.into(),
+            sort_value: name.into(),
+            keyboard_shortcut: None,
+            post_playback_delay: Duration::milliseconds(300),
+            voice_trigger: None,
+            speed: 1.0,
+            min_gap: None,
+            max_gap: None,
+            verify_effect: false,
+        }
+    }
+
+    fn nickel_menu(use_http: bool) -> NickelMenu {
+        NickelMenu {
+            opts: NickelMenuOptions {
+                enabled: true,
+                location: NickelMenuLocation::Reader,
+                use_http,
+                delay: 1,
+            },
+            path: "/mnt/onboard/.adds/nm/wifiremote-actions".into(),
+            file_list: "/opt/wifiremote/data/tracked_files".into(),
+            bin_path: "/opt/wifiremote/bin/wifiremote".into(),
+            port: 80,
+        }
+    }
+
+    #[test]
+    fn generate_cli_entries() {
+        let actions = [action("Next", "next-page"), action("Dark: on", "dark-on")];
+        let expected = indoc! {r#"
+            # Generated by Wi-Fi Remote whenever actions change. Manual edits will be lost.
+            menu_item :reader :Next :cmd_spawn :quiet:'/opt/wifiremote/bin/wifiremote' play 'next-page' --delay 1
+            menu_item :reader :Dark  on :cmd_spawn :quiet:'/opt/wifiremote/bin/wifiremote' play 'dark-on' --delay 1
+        "#};
+        assert_eq!(expected, nickel_menu(false).generate(&actions));
+    }
+
+    #[test]
+    fn generate_http_entries() {
+        let actions = [action("Next", "next-page")];
+        let expected = indoc! {r#"
+            # Generated by Wi-Fi Remote whenever actions change. Manual edits will be lost.
+            menu_item :reader :Next :cmd_spawn :quiet:sleep 1; wget -q -O /dev/null 'http://127.0.0.1:80/actions/next-page'
+        "#};
+        assert_eq!(expected, nickel_menu(true).generate(&actions));
+    }
+
+    #[test]
+    fn hostile_path_segments_are_quoted() {
+        let actions = [
+            action("Wipe", "x; rm -rf /mnt/onboard"),
+            action("Quote", "it's"),
+            action("Inject", "x\nmenu_item :main :Reboot :cmd_spawn :reboot"),
+        ];
+        let expected = indoc! {r#"
+            # Generated by Wi-Fi Remote whenever actions change. Manual edits will be lost.
+            menu_item :reader :Wipe :cmd_spawn :quiet:'/opt/wifiremote/bin/wifiremote' play 'x; rm -rf /mnt/onboard' --delay 1
+            menu_item :reader :Quote :cmd_spawn :quiet:'/opt/wifiremote/bin/wifiremote' play 'it'\''s' --delay 1
+        "#};
+        assert_eq!(expected, nickel_menu(false).generate(&actions));
+        let expected = indoc! {r#"
+            # Generated by Wi-Fi Remote whenever actions change. Manual edits will be lost.
+            menu_item :reader :Wipe :cmd_spawn :quiet:sleep 1; wget -q -O /dev/null 'http://127.0.0.1:80/actions/x; rm -rf /mnt/onboard'
+            menu_item :reader :Quote :cmd_spawn :quiet:sleep 1; wget -q -O /dev/null 'http://127.0.0.1:80/actions/it'\''s'
+        "#};
+        assert_eq!(expected, nickel_menu(true).generate(&actions));
+    }
+}
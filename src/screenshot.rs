@@ -1,29 +1,198 @@
+use crate::fbink::Screen;
 use crate::{errors::AppError, server::AppState};
 
+use std::io::Cursor;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
 use axum::{
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
     extract::State,
     http::{header, HeaderMap, HeaderValue},
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     routing::get,
     Router,
 };
 use chrono::Local;
-use fbink_rs::image::ImageFormat;
+use fbink_rs::image::{self, ImageFormat};
+use fbink_rs::CanonicalRotation;
+use serde::{Deserialize, Serialize};
 use slug::slugify;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{debug, warn};
+
+/// The lowest frame rate the screen stream can be limited to
+const MIN_STREAM_FPS: f64 = 0.1;
+/// The highest frame rate the screen stream can be limited to. An e-ink screen can't refresh
+/// faster than this anyway
+const MAX_STREAM_FPS: f64 = 10.0;
 
 pub fn routes() -> Router<AppState> {
-    Router::new().route("/screenshot", get(screenshot))
+    Router::new()
+        .route("/screenshot", get(screenshot))
+        .route("/screen/stream", get(stream_handler))
 }
 
 async fn screenshot(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
-    let fbink = state.fbink.try_inner()?;
-    fbink.reinit()?;
-    let bytes = fbink.screenshot(ImageFormat::Png)?;
+    let info = state.screen.refresh()?;
+    let bytes = state.screen.screenshot(ImageFormat::Png)?;
     let timestamp = Local::now().format("%Y%m%d-%H%M-%S");
-    let filename = format!("{}-{timestamp}.png", slugify(fbink.state().device_id));
+    let filename = format!("{}-{timestamp}.png", slugify(info.device_id));
     let mut headers = HeaderMap::new();
     headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("image/png"));
     let value = HeaderValue::from_str(&format!("inline; filename=\"{}\"", filename))?;
     headers.insert(header::CONTENT_DISPOSITION, value);
     Ok((headers, bytes))
 }
+
+/// How frames of the screen stream are encoded
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamFormat {
+    #[default]
+    Png,
+    /// Smaller frames for slow connections, at the cost of blurred text
+    Jpeg,
+}
+
+impl StreamFormat {
+    fn image_format(self) -> ImageFormat {
+        match self {
+            Self::Png => ImageFormat::Png,
+            Self::Jpeg => ImageFormat::Jpeg,
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+        }
+    }
+}
+
+/// Convert a capture of the screen to a grayscale frame for the stream
+pub fn encode_frame(capture: &[u8], format: StreamFormat) -> Result<Vec<u8>> {
+    let frame = image::load_from_memory(capture)
+        .context("Failed to decode screen capture")?
+        .grayscale();
+    let mut bytes = Cursor::new(Vec::new());
+    frame
+        .write_to(&mut bytes, format.image_format())
+        .context("Failed to encode frame")?;
+    Ok(bytes.into_inner())
+}
+
+/// Send frames of the screen as binary messages whenever it changes, at most `max_fps` a second
+async fn stream_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let info = state.screen.refresh()?;
+    let opts = state.config().user.remote_control.clone();
+    let screen = state.screen.clone();
+    Ok(ws.on_upgrade(move |socket| {
+        stream_screen(
+            socket,
+            screen,
+            info.rotation,
+            opts.screen_max_fps,
+            opts.screen_format,
+        )
+    }))
+}
+
+/// Changes are found by comparing captures, which are much cheaper than screenshots, and the
+/// screen is only refreshed again when it's rotated. Captures leave out the top and bottom of the
+/// screen, so a change only there, like the clock, is sent with the next change in the middle
+async fn stream_screen(
+    mut socket: WebSocket,
+    screen: Arc<dyn Screen>,
+    mut rotation: CanonicalRotation,
+    max_fps: f64,
+    format: StreamFormat,
+) {
+    // A zero or NaN period would panic
+    let max_fps = if max_fps.is_nan() {
+        MIN_STREAM_FPS
+    } else {
+        max_fps.clamp(MIN_STREAM_FPS, MAX_STREAM_FPS)
+    };
+    let period = std::time::Duration::from_secs_f64(1.0 / max_fps);
+    let mut ticker = interval(period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut last: Option<Vec<u8>> = None;
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            msg = socket.recv() => {
+                if !matches!(msg, Some(Ok(Message::Ping(_) | Message::Pong(_)))) {
+                    debug!("Screen stream client disconnected");
+                    return;
+                }
+                continue;
+            }
+        }
+        let screen = screen.clone();
+        let previous = last.take();
+        // Capturing and encoding block, so keep them off the async runtime
+        let result = tokio::task::spawn_blocking(move || {
+            let current = screen.current_rotation()?;
+            if current != rotation {
+                screen.refresh()?;
+            }
+            let capture = screen.capture()?;
+            let frame = if previous.as_ref() == Some(&capture) {
+                None
+            } else {
+                Some(encode_frame(&screen.screenshot(ImageFormat::Png)?, format)?)
+            };
+            anyhow::Ok((current, capture, frame))
+        })
+        .await;
+        let (current, capture, frame) = match result {
+            Ok(Ok(captured)) => captured,
+            Ok(Err(e)) => {
+                warn!("Stopping screen stream. {e:#}");
+                break;
+            }
+            Err(e) => {
+                warn!("Stopping screen stream. {e}");
+                break;
+            }
+        };
+        rotation = current;
+        last = Some(capture);
+        let Some(frame) = frame else {
+            continue;
+        };
+        if socket.send(Message::Binary(frame)).await.is_err() {
+            debug!("Screen stream client disconnected");
+            return;
+        }
+    }
+    if let Err(e) = socket.send(Message::Close(None)).await {
+        warn!("Failed to close screen stream WebSocket. {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fbink_rs::image::{ColorType, DynamicImage, Rgb, RgbImage};
+
+    #[test]
+    fn frames_are_grayscale() {
+        let capture = RgbImage::from_pixel(4, 3, Rgb([200, 30, 30]));
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(capture)
+            .write_to(&mut png, ImageFormat::Png)
+            .unwrap();
+        for format in [StreamFormat::Png, StreamFormat::Jpeg] {
+            let frame = encode_frame(png.get_ref(), format).unwrap();
+            let frame = image::load_from_memory_with_format(&frame, format.image_format()).unwrap();
+            assert_eq!(ColorType::L8, frame.color());
+            assert_eq!((4, 3), (frame.width(), frame.height()));
+        }
+    }
+}
@@ -1,14 +1,20 @@
 use crate::{
     actions::{
         arbitrary::{InputManager, InputMsg, InputMsgWrapper, InputSender},
-        ActionManager, ActionMsg,
+        history::PlayHistory,
+        inject::Injector,
+        recording::RecordingJobs,
+        ActionManager, ActionMsg, PlayCanceller,
     },
     config::Config,
-    fbink::FbInkWrapper,
+    fbink::{FbInkWrapper, Screen},
     init::init,
+    nickel_menu::NickelMenu,
+    stats::ReadingStats,
 };
 
 use std::{
+    net::SocketAddr,
     sync::{Arc, Mutex, MutexGuard},
     thread,
 };
@@ -29,12 +35,44 @@ use tracing::{error, info, warn};
 #[derive(Clone)]
 pub struct AppState {
     pub tx: mpsc::Sender<ActionMsg>,
+    pub plays: PlayCanceller,
+    pub recordings: RecordingJobs,
+    pub reading: ReadingStats,
     pub fbink: FbInkWrapper,
+    /// The screen as used by the routes that capture, draw on or tap it
+    pub screen: Arc<dyn Screen>,
+    /// Shared with the player, so arbitrary input reuses its uinput devices
+    pub injector: Injector,
     pub config: Arc<Mutex<Config>>,
     arbitrary_tx: Arc<tokio::sync::Mutex<Option<InputSender>>>,
 }
 
 impl AppState {
+    pub fn new(
+        tx: mpsc::Sender<ActionMsg>,
+        plays: PlayCanceller,
+        fbink: FbInkWrapper,
+        screen: Arc<dyn Screen>,
+        injector: Injector,
+        config: Config,
+    ) -> Self {
+        let reading = ReadingStats::load(&config.app.data_dir).unwrap_or_else(|e| {
+            error!("Failed to load reading stats. {e:#}");
+            ReadingStats::default()
+        });
+        Self {
+            tx,
+            plays,
+            recordings: RecordingJobs::default(),
+            reading,
+            fbink,
+            screen,
+            injector,
+            config: Arc::new(Mutex::new(config)),
+            arbitrary_tx: Arc::new(tokio::sync::Mutex::new(None)),
+        }
+    }
+
     pub fn config(&self) -> MutexGuard<'_, Config> {
         self.config.lock().expect("Failed to lock Config")
     }
@@ -51,10 +89,15 @@ impl AppState {
         rx.await?
     }
 
+    pub async fn arbitrary_input_running(&self) -> bool {
+        self.arbitrary_tx.lock().await.is_some()
+    }
+
     pub async fn start_arbitrary_input(&self) -> Result<()> {
         // Restart the InputManager if it's already running so that config changes take effect
         // and to help minimize the impact of any bugs.
-        let fbink = self.fbink.try_inner()?.clone();
+        // Fail early if there's no screen to draw the cursor on
+        self.screen.refresh()?;
         let _ = self.send_input_msg(InputMsg::Shutdown).await;
         let (resp, rx) = oneshot::channel();
         self.tx
@@ -67,7 +110,13 @@ impl AppState {
         if let Ok(template) = rx.await? {
             let (tx, rx) = tokio::sync::mpsc::channel(32);
             let config = self.config().clone();
-            match InputManager::new(template.clone(), fbink, config, rx) {
+            match InputManager::new(
+                template.clone(),
+                self.screen.clone(),
+                &self.injector,
+                config,
+                rx,
+            ) {
                 Ok(mut input_manager) => {
                     thread::spawn(move || input_manager.manage());
                     let mut arbitrary_tx = self.arbitrary_tx.lock().await;
@@ -82,6 +131,22 @@ impl AppState {
     }
 }
 
+/// Every route of the server
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .merge(crate::config::routes())
+        .merge(crate::actions::routes())
+        .merge(crate::frontend::routes())
+        .merge(crate::kobo_config::routes())
+        .merge(crate::screenshot::routes())
+        .merge(crate::logging::routes())
+        .merge(crate::management::routes())
+        .merge(crate::diagnostics::routes())
+        .merge(crate::stats::routes())
+        .merge(crate::actions::arbitrary::routes())
+        .merge(crate::actions::inspect::routes())
+}
+
 #[tokio::main(flavor = "current_thread")]
 pub async fn serve(config: &Config) -> Result<()> {
     let (tx, rx) = mpsc::channel(32);
@@ -91,26 +156,29 @@ pub async fn serve(config: &Config) -> Result<()> {
         config.action_file(),
         config.recordings_file(),
         fbink.clone(),
-        rx,
     )
     .context("Failed to start ActionManager")?;
-    let state = AppState {
+    manager.set_nickel_menu(NickelMenu::new(config));
+    // Created once so restarting arbitrary input doesn't create more uinput devices
+    let injector = Injector::new(config.app.injection_backend);
+    manager.set_injector(injector.clone());
+    manager.set_busy_wait(config.app.playback_busy_wait);
+    manager.set_effect_timeout(config.app.effect_timeout);
+    manager.set_coalesce_plays(config.app.coalesce_plays);
+    match PlayHistory::load(config.play_history_file(), config.app.play_history_limit) {
+        Ok(history) => manager.set_play_history(history),
+        Err(e) => error!("Failed to load play history. {e:#}"),
+    }
+    let state = AppState::new(
         tx,
-        fbink: fbink.clone(),
-        config: Arc::new(Mutex::new(config.clone())),
-        arbitrary_tx: Arc::new(tokio::sync::Mutex::new(None)),
-    };
-    thread::spawn(move || manager.manage());
-    let app = Router::new()
-        .merge(crate::config::routes())
-        .merge(crate::actions::routes())
-        .merge(crate::frontend::routes())
-        .merge(crate::kobo_config::routes())
-        .merge(crate::screenshot::routes())
-        .merge(crate::logging::routes())
-        .merge(crate::management::routes())
-        .merge(crate::actions::arbitrary::routes())
-        .with_state(state);
+        manager.play_canceller(),
+        fbink.clone(),
+        Arc::new(fbink.clone()),
+        injector,
+        config.clone(),
+    );
+    thread::spawn(move || manager.manage(rx));
+    let app = router().with_state(state);
 
     let app = NormalizePathLayer::trim_trailing_slash().layer(app);
     let app = SetResponseHeaderLayer::overriding(
@@ -118,7 +186,8 @@ pub async fn serve(config: &Config) -> Result<()> {
         HeaderValue::from_static("no-cache"),
     )
     .layer(app);
-    let app = ServiceExt::<Request>::into_make_service(app);
+    // The client's address is logged with each play
+    let app = ServiceExt::<Request>::into_make_service_with_connect_info::<SocketAddr>(app);
     let host = format!("0.0.0.0:{}", config.app.port);
     let listener = tokio::net::TcpListener::bind(&host)
         .await
@@ -129,3 +198,862 @@ pub async fn serve(config: &Config) -> Result<()> {
         .context("Failed to start Axum server")?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{router, AppState};
+    use crate::actions::inject::{Injector, MemorySink};
+    use crate::actions::recording::{RecordingJobs, RecordingProgress};
+    use crate::actions::ActionManager;
+    use crate::config::Config;
+    use crate::fakes::{CanvasCall, FakeInputDevices, FakeRotation, FakeScreen};
+    use crate::fbink::FbInkWrapper;
+
+    use std::fs;
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Method, Request, StatusCode};
+    use axum::Router;
+    use evdev_rs::enums::EventCode::{self, EV_ABS, EV_KEY, EV_SYN};
+    use evdev_rs::enums::EV_ABS::{ABS_MT_POSITION_X, ABS_MT_POSITION_Y, ABS_MT_TRACKING_ID};
+    use evdev_rs::enums::EV_KEY::BTN_TOUCH;
+    use evdev_rs::enums::EV_SYN::SYN_REPORT;
+    use evdev_rs::{InputEvent, TimeVal};
+    use fbink_rs::image::{self, GenericImageView};
+    use fbink_rs::CanonicalRotation;
+    use futures_util::{SinkExt, StreamExt};
+    use pretty_assertions::assert_eq;
+    use serde_json::{json, Value};
+    use tokio::sync::mpsc;
+    use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+    use tower::ServiceExt;
+
+    struct TestServer {
+        app: Router,
+        state: AppState,
+        devices: FakeInputDevices,
+        rotation: FakeRotation,
+        sink: MemorySink,
+        screen: FakeScreen,
+        recordings: RecordingJobs,
+        dir: PathBuf,
+    }
+
+    impl TestServer {
+        fn new(name: &str) -> Self {
+            Self::with_manager(name, |_| ())
+        }
+
+        /// Start a server after changing the ActionManager's settings
+        fn with_manager(name: &str, configure: impl FnOnce(&mut ActionManager)) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("wifiremote-test-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            let mut config = Config::default();
+            config.app.user_dir = dir.clone();
+            config.app.data_dir = dir.clone();
+            config.user_config_path = dir.join("user-config.toml");
+
+            let devices = FakeInputDevices::default();
+            let rotation = FakeRotation::new(CanonicalRotation::Upright);
+            let sink = MemorySink::default();
+            let screen = FakeScreen::new(sink.clone(), rotation.clone());
+            let mut manager = ActionManager::from_path(
+                config.action_file(),
+                config.recordings_file(),
+                FbInkWrapper::DisabledInConfig,
+            )
+            .unwrap();
+            manager.set_input_devices(devices.clone());
+            manager.set_rotation_source(rotation.clone());
+            manager.set_injector(Injector::memory(sink.clone()));
+            manager.set_screen_source(screen.clone());
+            manager.set_effect_timeout(chrono::Duration::milliseconds(200));
+            configure(&mut manager);
+            let plays = manager.play_canceller();
+            let (tx, rx) = mpsc::channel(32);
+            thread::spawn(move || manager.manage(rx));
+            let state = AppState::new(
+                tx,
+                plays,
+                FbInkWrapper::DisabledInConfig,
+                Arc::new(screen.clone()),
+                Injector::memory(sink.clone()),
+                config,
+            );
+            Self {
+                recordings: state.recordings.clone(),
+                app: router().with_state(state.clone()),
+                state,
+                devices,
+                rotation,
+                sink,
+                screen,
+                dir,
+            }
+        }
+
+        async fn request(
+            &self,
+            method: Method,
+            uri: &str,
+            body: Option<Value>,
+        ) -> (StatusCode, Value) {
+            let request = Request::builder().method(method).uri(uri);
+            let request = match body {
+                Some(body) => request
+                    .header("content-type", "application/json")
+                    .body(Body::from(body.to_string())),
+                None => request.body(Body::empty()),
+            }
+            .unwrap();
+            let response = self.app.clone().oneshot(request).await.unwrap();
+            let status = response.status();
+            let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+            (status, body)
+        }
+
+        /// Serve the app on a local port, for routes that upgrade to a WebSocket
+        async fn listen(&self) -> SocketAddr {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let app = self.app.clone();
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            addr
+        }
+
+        fn written(&self) -> Vec<(EventCode, i32)> {
+            self.sink
+                .take()
+                .iter()
+                .map(|ev| (ev.event_code, ev.value))
+                .collect()
+        }
+    }
+
+    impl Drop for TestServer {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Wait for something done on another thread, failing if it takes too long
+    async fn wait_until(mut done: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done() {
+            assert!(Instant::now() < deadline, "Timed out waiting");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    fn tap() -> Vec<(EventCode, i32)> {
+        vec![
+            (EV_ABS(ABS_MT_TRACKING_ID), 7),
+            (EV_ABS(ABS_MT_POSITION_X), 522),
+            (EV_ABS(ABS_MT_POSITION_Y), 51),
+            (EV_KEY(BTN_TOUCH), 1),
+            (EV_SYN(SYN_REPORT), 0),
+            (EV_ABS(ABS_MT_TRACKING_ID), -1),
+            (EV_KEY(BTN_TOUCH), 0),
+            (EV_SYN(SYN_REPORT), 0),
+        ]
+    }
+
+    fn input_events(events: &[(EventCode, i32)]) -> Vec<InputEvent> {
+        let time = TimeVal {
+            tv_sec: 1705963814,
+            tv_usec: 769621,
+        };
+        events
+            .iter()
+            .map(|&(event_code, value)| InputEvent {
+                time,
+                event_code,
+                value,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn record_list_and_play() {
+        let server = TestServer::new("record-list-and-play");
+        server.devices.push(input_events(&tap()));
+
+        let (status, body) = server
+            .request(
+                Method::POST,
+                "/actions",
+                Some(json!({"name": "Next Page", "path_segment": "next-page"})),
+            )
+            .await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("next-page", body["path_segment"]);
+        assert_eq!("/dev/input/event1 (Fake touchscreen)", body["device"]);
+
+        let (status, body) = server.request(Method::GET, "/actions", None).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("Next Page", body[0]["name"]);
+
+        let (status, body) = server.request(Method::GET, "/right", None).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("next-page", body["path_segment"]);
+        assert!(body["drift"]["max"].is_i64());
+        assert_eq!(tap(), server.written());
+    }
+
+    #[tokio::test]
+    async fn play_needs_recording_for_rotation() {
+        let server = TestServer::new("play-needs-recording-for-rotation");
+        server.devices.push(input_events(&tap()));
+        let (status, _) = server
+            .request(Method::POST, "/actions", Some(json!({"name": "Tap"})))
+            .await;
+        assert_eq!(StatusCode::OK, status);
+
+        let (status, _) = server
+            .request(Method::GET, "/actions/tap?speed=0", None)
+            .await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+        let (status, _) = server
+            .request(Method::GET, "/actions/tap?speed=2", None)
+            .await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(tap(), server.written());
+
+        server.rotation.set(CanonicalRotation::Clockwise);
+        let (status, _) = server.request(Method::GET, "/actions/tap", None).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+        assert_eq!(Vec::<(EventCode, i32)>::new(), server.written());
+
+        let (status, _) = server.request(Method::GET, "/actions/missing", None).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+    }
+
+    #[tokio::test]
+    async fn record_without_input() {
+        let server = TestServer::new("record-without-input");
+        let (status, _) = server
+            .request(Method::POST, "/actions", Some(json!({"name": "Tap"})))
+            .await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+    }
+
+    #[tokio::test]
+    async fn generate_and_play() {
+        let server = TestServer::new("generate-and-play");
+        let (status, body) = server
+            .request(
+                Method::POST,
+                "/actions/generate",
+                Some(json!({"name": "Zoom In", "action": {"type": "gesture", "kind": "spread"}})),
+            )
+            .await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("zoom-in", body["path_segment"]);
+
+        let (status, _) = server.request(Method::GET, "/actions/zoom-in", None).await;
+        assert_eq!(StatusCode::OK, status);
+        let written = server.written();
+        assert!(written.contains(&(EV_ABS(ABS_MT_TRACKING_ID), -1)));
+        assert_eq!(Some(&(EV_SYN(SYN_REPORT), 0)), written.last());
+    }
+
+    #[tokio::test]
+    async fn rename_export_and_import() {
+        let server = TestServer::new("rename-export-and-import");
+        server.devices.push(input_events(&tap()));
+        let (status, _) = server
+            .request(Method::POST, "/actions", Some(json!({"name": "Tap"})))
+            .await;
+        assert_eq!(StatusCode::OK, status);
+
+        let body = json!({"name": "Next Page", "path_segment": "next-page"});
+        let (status, body) = server
+            .request(Method::POST, "/actions/tap/rename", Some(body))
+            .await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("next-page", body);
+        let (status, _) = server
+            .request(Method::GET, "/actions/next-page", None)
+            .await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(tap(), server.written());
+
+        let (status, export) = server.request(Method::GET, "/actions/export", None).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("Next Page", export["actions"]["next-page"]["name"]);
+
+        // Existing actions are only replaced when overwriting
+        let (status, _) = server
+            .request(Method::DELETE, "/actions/next-page", None)
+            .await;
+        assert_eq!(StatusCode::OK, status);
+        let (status, body) = server
+            .request(Method::POST, "/actions/import", Some(export.clone()))
+            .await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!(["next-page"]), body);
+        let (_, body) = server
+            .request(Method::POST, "/actions/import", Some(export.clone()))
+            .await;
+        assert_eq!(json!([]), body);
+        let (_, body) = server
+            .request(Method::POST, "/actions/import?overwrite=true", Some(export))
+            .await;
+        assert_eq!(json!(["next-page"]), body);
+        let (status, _) = server
+            .request(Method::GET, "/actions/next-page", None)
+            .await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(tap(), server.written());
+    }
+
+    #[tokio::test]
+    async fn rerecord_legacy_path_segment() {
+        let server = TestServer::with_manager("rerecord-legacy-path-segment", |manager| {
+            let opts = json!({
+                "name": "Old Page",
+                "sort_value": "Old Page",
+                "keyboard_shortcut": null,
+                "post_playback_delay": 0,
+                "voice_trigger": null,
+            });
+            let opts = serde_json::from_value(opts).unwrap();
+            manager.actions.data.insert("Old_Page".to_string(), opts);
+        });
+        server.devices.push(input_events(&tap()));
+        let body = json!({"name": "Old Page", "path_segment": "Old_Page"});
+        let (status, body) = server.request(Method::POST, "/actions", Some(body)).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("Old_Page", body["path_segment"]);
+        let body = json!({"name": "Old Page", "path_segment": "Old_Page", "action": {"type": "gesture", "kind": "spread"}});
+        let (status, _) = server
+            .request(Method::POST, "/actions/generate", Some(body))
+            .await;
+        assert_eq!(StatusCode::OK, status);
+    }
+
+    #[tokio::test]
+    async fn reject_invalid_path_segments() {
+        let server = TestServer::new("reject-invalid-path-segments");
+        let invalid = [
+            "x; rm -rf /mnt/onboard",
+            "a/b",
+            "a?b",
+            "a b",
+            "a\nb",
+            "Tap",
+            "export",
+        ];
+        for path_segment in invalid {
+            let body = json!({"name": "Tap", "path_segment": path_segment});
+            let (status, _) = server.request(Method::POST, "/actions", Some(body)).await;
+            assert_eq!(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                status,
+                "{path_segment:?}"
+            );
+            let body = json!({"name": "Tap", "path_segment": path_segment, "action": {"type": "gesture", "kind": "spread"}});
+            let (status, _) = server
+                .request(Method::POST, "/actions/generate", Some(body))
+                .await;
+            assert_eq!(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                status,
+                "{path_segment:?}"
+            );
+        }
+
+        server.devices.push(input_events(&tap()));
+        let (status, _) = server
+            .request(Method::POST, "/actions", Some(json!({"name": "Tap"})))
+            .await;
+        assert_eq!(StatusCode::OK, status);
+        let (status, _) = server
+            .request(
+                Method::POST,
+                "/actions/tap/rename",
+                Some(json!({"path_segment": "x\nmenu_item"})),
+            )
+            .await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+
+        let (_, mut export) = server.request(Method::GET, "/actions/export", None).await;
+        let action = export["actions"]["tap"].take();
+        export["actions"] = json!({"x;reboot": action});
+        let (status, _) = server
+            .request(Method::POST, "/actions/import", Some(export))
+            .await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+        let (_, body) = server.request(Method::GET, "/actions", None).await;
+        assert_eq!(1, body.as_array().unwrap().len());
+    }
+
+    /// Record a tap that plays immediately after the previous action
+    async fn record_tap(server: &TestServer, name: &str, hold: i64) {
+        let mut events = input_events(&tap());
+        for ev in &mut events[5..] {
+            ev.time.tv_usec += (hold * 1000) as nix::libc::suseconds_t;
+            while ev.time.tv_usec >= 1_000_000 {
+                ev.time.tv_sec += 1;
+                ev.time.tv_usec -= 1_000_000;
+            }
+        }
+        server.devices.push(events);
+        let body = json!({"name": name, "post_playback_delay": 0});
+        let (status, _) = server.request(Method::POST, "/actions", Some(body)).await;
+        assert_eq!(StatusCode::OK, status);
+    }
+
+    #[tokio::test]
+    async fn play_count() {
+        let server = TestServer::new("play-count");
+        record_tap(&server, "Tap", 0).await;
+        let (status, body) = server
+            .request(Method::GET, "/actions/tap?count=3", None)
+            .await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(3, body["played"]);
+        assert_eq!(tap().repeat(3), server.written());
+
+        let (status, _) = server
+            .request(Method::GET, "/actions/tap?count=101", None)
+            .await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+        assert_eq!(Vec::<(EventCode, i32)>::new(), server.written());
+    }
+
+    /// Wait until the player has started writing the events of a play
+    async fn wait_for_play(server: &TestServer) {
+        wait_until(|| !server.sink.events.lock().unwrap().is_empty()).await;
+    }
+
+    #[tokio::test]
+    async fn cancel_repeated_play() {
+        let server = TestServer::new("cancel-repeated-play");
+        record_tap(&server, "Long Press", 100).await;
+        let play = server.request(Method::GET, "/actions/long-press?count=20", None);
+        let cancel = async {
+            wait_for_play(&server).await;
+            server.request(Method::POST, "/actions/cancel", None).await
+        };
+        let ((status, body), (cancel_status, _)) = tokio::join!(play, cancel);
+        assert_eq!(StatusCode::OK, cancel_status);
+        assert_eq!(StatusCode::OK, status);
+        let played = body["played"].as_u64().unwrap();
+        assert!((1..20).contains(&played), "played {played} times");
+
+        // Plays requested after cancelling aren't affected
+        let (_, body) = server
+            .request(Method::GET, "/actions/long-press", None)
+            .await;
+        assert_eq!(1, body["played"]);
+    }
+
+    #[tokio::test]
+    async fn coalesce_queued_plays() {
+        let server = TestServer::with_manager("coalesce-queued-plays", |manager| {
+            manager.set_coalesce_plays(true)
+        });
+        record_tap(&server, "Long Press", 1000).await;
+        record_tap(&server, "Tap", 0).await;
+        // The taps queue up while the long press is playing, then are played as one batch
+        let long_press = server.request(Method::GET, "/actions/long-press", None);
+        let taps = async {
+            wait_for_play(&server).await;
+            tokio::join!(
+                server.request(Method::GET, "/actions/tap", None),
+                server.request(Method::GET, "/actions/tap?count=2", None),
+            )
+        };
+        let (_, ((_, first), (_, second))) = tokio::join!(long_press, taps);
+        // Each request is given its own plays from the batch
+        assert_eq!(1, first["played"]);
+        assert_eq!(2, second["played"]);
+    }
+
+    #[tokio::test]
+    async fn list_while_playing() {
+        let server = TestServer::new("list-while-playing");
+        record_tap(&server, "Long Press", 300).await;
+        let play = async {
+            server
+                .request(Method::GET, "/actions/long-press", None)
+                .await;
+            Instant::now()
+        };
+        let list = async {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            let (status, body) = server.request(Method::GET, "/actions", None).await;
+            assert_eq!(StatusCode::OK, status);
+            assert_eq!("Long Press", body[0]["name"]);
+            Instant::now()
+        };
+        let (played, listed) = tokio::join!(play, list);
+        assert!(listed < played, "Listing waited for the play to finish");
+    }
+
+    /// Start a recording in the background and wait for it to finish
+    async fn background_recording(server: &TestServer, abort: bool) -> RecordingProgress {
+        let body = json!({"name": "Tap"});
+        let (status, body) = server
+            .request(Method::POST, "/actions/recordings", Some(body))
+            .await;
+        assert_eq!(StatusCode::OK, status);
+        let id = body["id"].as_u64().unwrap();
+        if abort {
+            let uri = format!("/actions/recordings/{id}");
+            let (status, _) = server.request(Method::DELETE, &uri, None).await;
+            assert_eq!(StatusCode::OK, status);
+        }
+        let mut progress = server.recordings.get(id).unwrap().subscribe();
+        let done = progress.wait_for(|p| p.is_done()).await.unwrap().clone();
+        done
+    }
+
+    #[tokio::test]
+    async fn record_in_background() {
+        let server = TestServer::new("record-in-background");
+        server.devices.push(input_events(&tap()));
+        let progress = background_recording(&server, false).await;
+        let RecordingProgress::Finished { action } = progress else {
+            panic!("Recording didn't finish: {progress:?}");
+        };
+        assert_eq!("tap", action.path_segment);
+        let (_, body) = server.request(Method::GET, "/actions", None).await;
+        assert_eq!("Tap", body[0]["name"]);
+    }
+
+    #[tokio::test]
+    async fn abort_recording() {
+        let server = TestServer::new("abort-recording");
+        server.devices.hold_until_aborted();
+        let progress = background_recording(&server, true).await;
+        assert!(
+            matches!(progress, RecordingProgress::Aborted),
+            "{progress:?}"
+        );
+        let (_, body) = server.request(Method::GET, "/actions", None).await;
+        assert_eq!(json!([]), body);
+
+        let (status, _) = server
+            .request(Method::DELETE, "/actions/recordings/100", None)
+            .await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+    }
+
+    #[tokio::test]
+    async fn play_history() {
+        let server = TestServer::new("play-history");
+        record_tap(&server, "Tap", 0).await;
+        server.request(Method::GET, "/actions/tap", None).await;
+        server.request(Method::GET, "/actions/missing", None).await;
+
+        let (status, body) = server.request(Method::GET, "/actions/history", None).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("tap", body[0]["path_segment"]);
+        assert!(body[0]["rotation"].is_string());
+        assert_eq!(Value::Null, body[0]["error"]);
+        assert!(body[1]["error"].is_string());
+
+        let (_, body) = server
+            .request(Method::GET, "/actions/history/stats", None)
+            .await;
+        assert_eq!("missing", body[0]["path_segment"]);
+        assert_eq!(1.0, body[0]["failure_rate"]);
+        assert_eq!(1, body[1]["plays"]);
+        assert!(body[1]["p50"].is_i64());
+    }
+
+    #[tokio::test]
+    async fn verify_effect() {
+        let server = TestServer::new("verify-effect");
+        record_tap(&server, "Tap", 0).await;
+        let (_, body) = server.request(Method::GET, "/actions/tap", None).await;
+        assert_eq!(Value::Null, body["effect"]);
+        server.written();
+
+        let opts = json!({
+            "name": "Tap",
+            "sort_value": "Tap",
+            "post_playback_delay": 0,
+            "verify_effect": true,
+        });
+        let (status, _) = server
+            .request(Method::PUT, "/actions/tap", Some(opts))
+            .await;
+        assert_eq!(StatusCode::OK, status);
+        let (_, body) = server.request(Method::GET, "/actions/tap", None).await;
+        assert_eq!(true, body["effect"]);
+        assert_eq!(tap(), server.written());
+
+        // A play that doesn't change the screen is retried once
+        server.screen.freeze();
+        let (status, body) = server.request(Method::GET, "/actions/tap", None).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(false, body["effect"]);
+        assert_eq!(tap().repeat(2), server.written());
+
+        let (_, body) = server.request(Method::GET, "/actions/history", None).await;
+        assert_eq!(false, body[2]["effect"]);
+    }
+
+    #[tokio::test]
+    async fn benchmark() {
+        let server = TestServer::new("benchmark");
+        record_tap(&server, "Tap", 0).await;
+        let opts = json!({"runs": 3, "timeout": 500, "settle_time": 50});
+        let (status, body) = server
+            .request(Method::POST, "/actions/tap/benchmark", Some(opts))
+            .await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(3, body["runs"].as_array().unwrap().len());
+        assert_eq!(0, body["missed"]);
+        assert!(body["first_change"]["p95"].is_i64());
+        assert!(body["suggested"]["post_playback_delay"].is_i64());
+        assert_eq!(tap().repeat(3), server.written());
+    }
+
+    #[tokio::test]
+    async fn cancel_benchmark() {
+        let server = TestServer::new("cancel-benchmark");
+        record_tap(&server, "Tap", 0).await;
+        let opts = json!({"runs": 100, "timeout": 500, "settle_time": 50});
+        let benchmark = server.request(Method::POST, "/actions/tap/benchmark", Some(opts));
+        let cancel = async {
+            wait_for_play(&server).await;
+            server.request(Method::POST, "/actions/cancel", None).await
+        };
+        let ((status, body), _) = tokio::join!(benchmark, cancel);
+        assert_eq!(StatusCode::OK, status);
+        let runs = body["runs"].as_array().unwrap().len();
+        assert!(runs < 100, "ran {runs} times");
+
+        let opts = json!({"runs": 101});
+        let (status, _) = server
+            .request(Method::POST, "/actions/tap/benchmark", Some(opts))
+            .await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+    }
+
+    /// Record a next-page action that's optimized like the page turns set up on the e-reader, so
+    /// it can be used as a template for arbitrary input
+    async fn record_next_page(server: &TestServer) {
+        server.devices.push(input_events(&tap()));
+        let body = json!({"name": "Next Page", "optimize": true, "post_playback_delay": 0});
+        let (status, body) = server.request(Method::POST, "/actions", Some(body)).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(true, body["was_optimized"]);
+    }
+
+    #[tokio::test]
+    async fn screenshot() {
+        let server = TestServer::new("screenshot");
+        let request = Request::get("/screenshot").body(Body::empty()).unwrap();
+        let response = server.app.clone().oneshot(request).await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        let headers = response.headers();
+        assert_eq!("image/png", headers["content-type"]);
+        let disposition = headers["content-disposition"].to_str().unwrap();
+        assert!(
+            disposition.starts_with("inline; filename=\"fake-"),
+            "{disposition}"
+        );
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let image = image::load_from_memory(&bytes).unwrap();
+        assert_eq!((4, 4), image.dimensions());
+    }
+
+    /// The shade of the next frame of a screen stream, which is the same all over the fake screen
+    async fn next_shade(
+        socket: &mut (impl StreamExt<Item = Result<Message, WsError>> + Unpin),
+    ) -> u8 {
+        let Some(Ok(Message::Binary(frame))) = socket.next().await else {
+            panic!("No frame was sent");
+        };
+        image::load_from_memory(&frame).unwrap().to_luma8()[(0, 0)].0[0]
+    }
+
+    #[tokio::test]
+    async fn list_input_devices() {
+        let server = TestServer::new("list-input-devices");
+        let (status, body) = server.request(Method::GET, "/input/devices", None).await;
+        assert_eq!(StatusCode::OK, status);
+        let touch: Vec<_> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|d| d["is_touch"] == true)
+            .map(|d| d["path"].as_str().unwrap())
+            .collect();
+        assert_eq!(vec!["/dev/input/event1"], touch);
+    }
+
+    #[tokio::test]
+    async fn monitor_input_device() {
+        let server = TestServer::new("monitor-input-device");
+        server.devices.push(input_events(&tap()));
+        let addr = server.listen().await;
+        let url = format!("ws://{addr}/input/monitor?path=/dev/input/event1&duration=1");
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let Some(Ok(Message::Text(json))) = socket.next().await else {
+            panic!("No event was sent");
+        };
+        let event: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!("ABS_MT_TRACKING_ID", event["code"]);
+        assert_eq!(7, event["value"]);
+        assert_eq!(false, event["resync"]);
+
+        // Only input devices can be opened
+        let url = format!("ws://{addr}/input/monitor?path=/etc/passwd");
+        assert!(tokio_tungstenite::connect_async(url).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn screen_stream() {
+        let server = TestServer::new("screen-stream");
+        // Limited to the highest rate rather than a zero period
+        server.state.config().user.remote_control.screen_max_fps = f64::INFINITY;
+        record_tap(&server, "Next Page", 0).await;
+        let addr = server.listen().await;
+        let url = format!("ws://{addr}/screen/stream");
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let first = next_shade(&mut socket).await;
+        // A frame is only sent when the screen changes
+        let (status, _) = server
+            .request(Method::GET, "/actions/next-page", None)
+            .await;
+        assert_eq!(StatusCode::OK, status);
+        let second = next_shade(&mut socket).await;
+        assert_ne!(first, second);
+        // The screen is only refreshed when the stream starts and when it's rotated
+        assert_eq!(1, server.screen.refreshes.load(Ordering::SeqCst));
+        server.rotation.set(CanonicalRotation::Clockwise);
+        server
+            .sink
+            .events
+            .lock()
+            .unwrap()
+            .extend(input_events(&tap()));
+        next_shade(&mut socket).await;
+        assert_eq!(2, server.screen.refreshes.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn tap_on_screen() {
+        let server = TestServer::new("tap-on-screen");
+        let tap = json!({"x": 0.5, "y": 0.25, "hold": 0});
+        server.state.config().user.remote_control.tap_on_screen = true;
+        let (status, _) = server
+            .request(Method::POST, "/screen/tap", Some(tap.clone()))
+            .await;
+        // The next-page action is used as a template
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+
+        record_next_page(&server).await;
+        let (status, _) = server.request(Method::POST, "/screen/tap", Some(tap)).await;
+        assert_eq!(StatusCode::OK, status);
+        let written = server.written();
+        assert!(
+            written.contains(&(EV_ABS(ABS_MT_POSITION_X), 536)),
+            "{written:?}"
+        );
+        assert!(
+            written.contains(&(EV_ABS(ABS_MT_POSITION_Y), 362)),
+            "{written:?}"
+        );
+        assert_eq!(Some(&(EV_SYN(SYN_REPORT), 0)), written.last());
+    }
+
+    #[tokio::test]
+    async fn tap_on_screen_is_off_by_default() {
+        let server = TestServer::new("tap-on-screen-off");
+        record_next_page(&server).await;
+        let tap = json!({"x": 0.5, "y": 0.25, "hold": 0});
+        let (status, _) = server.request(Method::POST, "/screen/tap", Some(tap)).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+        assert!(server.written().is_empty());
+        assert!(!server.state.arbitrary_input_running().await);
+    }
+
+    #[tokio::test]
+    async fn trackpad() {
+        let server = TestServer::new("trackpad");
+        record_next_page(&server).await;
+        server.state.start_arbitrary_input().await.unwrap();
+        let addr = server.listen().await;
+        let url = format!("ws://{addr}/ws");
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        // The cursor is drawn when the client connects
+        wait_until(|| {
+            server
+                .screen
+                .canvas
+                .calls()
+                .iter()
+                .any(|c| matches!(c, CanvasCall::Draw(..)))
+        })
+        .await;
+
+        for msg in [
+            json!({"MoveAbsolute": {"x": 100.0, "y": 200.0}}),
+            json!({"StartInput": null}),
+            json!({"StopInput": null}),
+        ] {
+            socket.send(Message::Text(msg.to_string())).await.unwrap();
+        }
+        let mut written = Vec::new();
+        wait_until(|| {
+            written.extend(server.written());
+            written.last() == Some(&(EV_SYN(SYN_REPORT), 0)) && written.len() > 5
+        })
+        .await;
+        assert!(
+            written.contains(&(EV_ABS(ABS_MT_POSITION_X), 100)),
+            "{written:?}"
+        );
+        assert!(
+            written.contains(&(EV_ABS(ABS_MT_POSITION_Y), 200)),
+            "{written:?}"
+        );
+        socket.close(None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn calibration() {
+        let server = TestServer::new("calibration");
+        // The touchscreen's x axis runs down the screen
+        for (x, y) in [(362, 268), (362, 804), (1086, 268)] {
+            server.devices.push(input_events(&[
+                (EV_ABS(ABS_MT_POSITION_X), x),
+                (EV_ABS(ABS_MT_POSITION_Y), y),
+                (EV_SYN(SYN_REPORT), 0),
+            ]));
+        }
+        let (status, body) = server.request(Method::POST, "/calibration", None).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("Upright", body["rotation"]);
+        assert_eq!(true, body["transform"]["swap_axes"]);
+        assert_eq!(3, body["points"].as_array().unwrap().len());
+        let calls = server.screen.canvas.calls();
+        assert_eq!(CanvasCall::Draw(238, 332), calls[1]);
+        assert_eq!(CanvasCall::Restore(238, 332), calls[2]);
+        let config = fs::read_to_string(server.dir.join("user-config.toml")).unwrap();
+        assert!(
+            config.contains("[arbitrary_input.calibration.upright]"),
+            "{config}"
+        );
+
+        // Calibration fails without touches
+        let (status, _) = server.request(Method::POST, "/calibration", None).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+    }
+}
@@ -0,0 +1,439 @@
+//! Reading sessions worked out from page turns, so readers can see how much and how fast they read
+use crate::errors::AppError;
+use crate::server::AppState;
+
+use std::collections::BTreeSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use chrono::{DateTime, Datelike, Days, Duration, Local, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DurationMilliSeconds, DurationSeconds};
+use tracing::{debug, error, warn};
+
+/// A longer gap between page turns ends the reading session
+const SESSION_TIMEOUT: Duration = Duration::minutes(10);
+/// How far back to look when measuring the reading pace
+const PACE_WINDOW: Duration = Duration::days(30);
+/// Fewer timed page turns than this are too noisy to give a pace
+const MIN_PACED_TURNS: u32 = 5;
+/// Quicker page turns are skimming or a double press rather than reading, so they aren't timed
+const MIN_PACED_GAP: Duration = Duration::seconds(1);
+/// How many of the latest sessions to include in a summary
+const RECENT_SESSIONS: usize = 20;
+/// The most days and weeks a summary covers
+const MAX_SUMMARY_DAYS: u32 = 366;
+const MAX_SUMMARY_WEEKS: u32 = 104;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/stats", get(get_stats))
+}
+
+#[serde_with::serde_as]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReadingSession {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Page turns forward
+    pub pages: u32,
+    /// Page turns back
+    pub back: u32,
+    /// The time spent on pages the reader turned forward from. Turns back, turns by the
+    /// auto-turner, ones that skip several pages at once and ones within [MIN_PACED_GAP] of the
+    /// last turn aren't timed
+    #[serde_as(as = "DurationMilliSeconds<i64>")]
+    pub paced_time: Duration,
+    pub paced_turns: u32,
+}
+
+impl ReadingSession {
+    fn new(time: DateTime<Utc>) -> Self {
+        Self {
+            start: time,
+            end: time,
+            pages: 0,
+            back: 0,
+            paced_time: Duration::zero(),
+            paced_turns: 0,
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+
+    pub fn seconds_per_page(&self) -> Option<f64> {
+        (self.paced_turns > 0)
+            .then(|| self.paced_time.num_milliseconds() as f64 / 1000.0 / self.paced_turns as f64)
+    }
+
+    fn date(&self) -> NaiveDate {
+        self.start.with_timezone(&Local).date_naive()
+    }
+}
+
+/// The reading sessions so far. Clones share the same sessions
+#[derive(Clone, Debug, Default)]
+pub struct ReadingStats(Arc<Mutex<SessionsFile>>);
+
+#[derive(Debug, Default)]
+struct SessionsFile {
+    /// Where sessions are saved. They're only kept in memory without one
+    dir: Option<PathBuf>,
+    finished: Vec<ReadingSession>,
+    /// Saved on its own after each page turn, so finished sessions only need appending
+    current: Option<ReadingSession>,
+}
+
+impl ReadingStats {
+    pub fn load(data_dir: &Path) -> Result<Self> {
+        let mut file = SessionsFile {
+            dir: Some(data_dir.to_path_buf()),
+            ..Default::default()
+        };
+        let finished_path = file.finished_path().unwrap();
+        if finished_path.exists() {
+            debug!("Loading reading sessions from {}", finished_path.display());
+            let lines = fs::read_to_string(&finished_path).with_context(|| {
+                format!("Failed to read sessions from {}", finished_path.display())
+            })?;
+            for line in lines.lines() {
+                match serde_json::from_str(line) {
+                    Ok(session) => file.finished.push(session),
+                    Err(e) => warn!("Skipped invalid reading session. {e}"),
+                }
+            }
+        }
+        let current_path = file.current_path().unwrap();
+        if current_path.exists() {
+            let current = fs::read_to_string(&current_path)
+                .context("Failed to read current reading session")?;
+            match serde_json::from_str(&current) {
+                Ok(session) => file.current = Some(session),
+                Err(e) => warn!("Skipped invalid current reading session. {e}"),
+            }
+        }
+        Ok(Self(Arc::new(Mutex::new(file))))
+    }
+
+    /// Count turns of the next-page or prev-page action. `automatic` turns are counted as pages
+    /// but left out of the reading pace
+    pub fn page_turned(&self, forward: bool, played: u32, automatic: bool) {
+        self.page_turned_at(forward, played, automatic, Utc::now())
+    }
+
+    fn page_turned_at(&self, forward: bool, played: u32, automatic: bool, time: DateTime<Utc>) {
+        let mut file = self.0.lock().unwrap();
+        if let Err(e) = file.page_turned(forward, played, automatic, time) {
+            error!("Failed to save reading session. {e:#}");
+        }
+    }
+
+    /// How long the reader spends on a page, from recent sessions
+    pub fn measured_pace(&self) -> Option<f64> {
+        self.0.lock().unwrap().measured_pace(Utc::now())
+    }
+
+    pub fn summary(&self, days: u32, weeks: u32) -> ReadingSummary {
+        self.0.lock().unwrap().summary(Local::now(), days, weeks)
+    }
+}
+
+impl SessionsFile {
+    fn finished_path(&self) -> Option<PathBuf> {
+        self.dir.as_ref().map(|d| d.join("reading-sessions.jsonl"))
+    }
+
+    fn current_path(&self) -> Option<PathBuf> {
+        self.dir.as_ref().map(|d| d.join("reading-session.json"))
+    }
+
+    fn page_turned(
+        &mut self,
+        forward: bool,
+        played: u32,
+        automatic: bool,
+        time: DateTime<Utc>,
+    ) -> Result<()> {
+        match &mut self.current {
+            Some(session) if time - session.end <= SESSION_TIMEOUT => {
+                let gap = time - session.end;
+                if forward && played == 1 && !automatic && gap >= MIN_PACED_GAP {
+                    session.paced_time += gap;
+                    session.paced_turns += 1;
+                }
+                session.end = time;
+            }
+            _ => {
+                self.finish_current()?;
+                self.current = Some(ReadingSession::new(time));
+            }
+        }
+        let path = self.current_path();
+        let session = self.current.as_mut().unwrap();
+        if forward {
+            session.pages += played;
+        } else {
+            session.back += played;
+        }
+        if let Some(path) = path {
+            let json = serde_json::to_string(session).context("Failed to serialize session")?;
+            fs::write(&path, json)
+                .with_context(|| format!("Failed to write session to {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    fn finish_current(&mut self) -> Result<()> {
+        let Some(session) = self.current.take() else {
+            return Ok(());
+        };
+        let result = match self.finished_path() {
+            Some(path) => append_session(&path, &session),
+            None => Ok(()),
+        };
+        // Keep the session for this run even if it couldn't be saved
+        self.finished.push(session);
+        result
+    }
+
+    fn sessions(&self) -> impl DoubleEndedIterator<Item = &ReadingSession> {
+        self.finished.iter().chain(self.current.as_ref())
+    }
+
+    fn measured_pace(&self, now: DateTime<Utc>) -> Option<f64> {
+        let (time, turns) = self
+            .sessions()
+            .filter(|s| now - s.end <= PACE_WINDOW)
+            .fold((Duration::zero(), 0), |(time, turns), s| {
+                (time + s.paced_time, turns + s.paced_turns)
+            });
+        (turns >= MIN_PACED_TURNS).then(|| time.num_milliseconds() as f64 / 1000.0 / turns as f64)
+    }
+
+    fn summary(&self, now: DateTime<Local>, days: u32, weeks: u32) -> ReadingSummary {
+        let today = now.date_naive();
+        let day_starts = (0..days.min(MAX_SUMMARY_DAYS))
+            .rev()
+            .filter_map(|d| today.checked_sub_days(Days::new(d.into())));
+        let this_week = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+        let week_starts = (0..weeks.min(MAX_SUMMARY_WEEKS))
+            .rev()
+            .filter_map(|w| this_week.checked_sub_days(Days::new(u64::from(w) * 7)));
+        let reading_days: BTreeSet<_> = self
+            .sessions()
+            .filter(|s| s.pages > 0)
+            .map(|s| s.date())
+            .collect();
+        let (longest_streak, current_streak) = streaks(&reading_days, today);
+        ReadingSummary {
+            sessions: self
+                .sessions()
+                .rev()
+                .take(RECENT_SESSIONS)
+                .cloned()
+                .collect(),
+            days: day_starts
+                .map(|start| self.period(start, start + Duration::days(1)))
+                .collect(),
+            weeks: week_starts
+                .map(|start| self.period(start, start + Duration::weeks(1)))
+                .collect(),
+            seconds_per_page: self.measured_pace(now.with_timezone(&Utc)),
+            longest_streak,
+            current_streak,
+            total_pages: self.sessions().map(|s| s.pages).sum(),
+        }
+    }
+
+    fn period(&self, start: NaiveDate, end: NaiveDate) -> PeriodStats {
+        let mut stats = PeriodStats {
+            start,
+            pages: 0,
+            reading_time: Duration::zero(),
+            sessions: 0,
+        };
+        for session in self.sessions().filter(|s| (start..end).contains(&s.date())) {
+            stats.pages += session.pages;
+            stats.reading_time += session.duration();
+            stats.sessions += 1;
+        }
+        stats
+    }
+}
+
+fn append_session(path: &Path, session: &ReadingSession) -> Result<()> {
+    let line = serde_json::to_string(session).context("Failed to serialize session")?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    writeln!(file, "{line}").context("Failed to append reading session")
+}
+
+/// The longest run of consecutive reading days, and the run that includes today or yesterday
+fn streaks(days: &BTreeSet<NaiveDate>, today: NaiveDate) -> (u32, u32) {
+    let mut longest = 0;
+    let mut run = 0;
+    let mut previous: Option<NaiveDate> = None;
+    for &day in days {
+        run = match previous {
+            Some(p) if day - p == Duration::days(1) => run + 1,
+            _ => 1,
+        };
+        longest = longest.max(run);
+        previous = Some(day);
+    }
+    let current = match previous {
+        Some(last) if today - last <= Duration::days(1) => run,
+        _ => 0,
+    };
+    (longest, current)
+}
+
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PeriodStats {
+    /// The first day of the period
+    pub start: NaiveDate,
+    pub pages: u32,
+    /// How long the sessions that started in the period lasted
+    #[serde_as(as = "DurationSeconds<i64>")]
+    pub reading_time: Duration,
+    pub sessions: u32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReadingSummary {
+    /// The latest sessions, most recent first
+    pub sessions: Vec<ReadingSession>,
+    /// Oldest first, ending today
+    pub days: Vec<PeriodStats>,
+    /// Oldest first, ending with the current week. Weeks start on Monday
+    pub weeks: Vec<PeriodStats>,
+    /// The average time spent on a page over the last 30 days
+    pub seconds_per_page: Option<f64>,
+    /// In days
+    pub longest_streak: u32,
+    pub current_streak: u32,
+    pub total_pages: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct StatsQuery {
+    days: u32,
+    weeks: u32,
+}
+
+impl Default for StatsQuery {
+    fn default() -> Self {
+        Self { days: 14, weeks: 8 }
+    }
+}
+
+async fn get_stats(
+    State(state): State<AppState>,
+    Query(query): Query<StatsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(state.reading.summary(query.days, query.weeks)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn sessions_from_page_turns() {
+        let stats = ReadingStats::default();
+        let start = Utc::now() - Duration::hours(2);
+        let at = |secs| start + Duration::seconds(secs);
+        for secs in [0, 30, 60, 90, 120, 150] {
+            stats.page_turned_at(true, 1, false, at(secs));
+        }
+        // Going back a page and the auto-turner count as turns but aren't timed
+        stats.page_turned_at(false, 1, false, at(155));
+        stats.page_turned_at(true, 1, true, at(300));
+        // Skipping pages isn't timed either
+        stats.page_turned_at(true, 3, false, at(310));
+        // A long break starts a new session
+        stats.page_turned_at(true, 1, false, at(3600));
+
+        let summary = stats.summary(14, 8);
+        assert_eq!(2, summary.sessions.len());
+        let first = &summary.sessions[1];
+        assert_eq!(10, first.pages);
+        assert_eq!(1, first.back);
+        assert_eq!(Duration::seconds(310), first.duration());
+        assert_eq!(Some(30.0), first.seconds_per_page());
+        assert_eq!(Some(30.0), summary.seconds_per_page);
+        assert_eq!(Some(30.0), stats.measured_pace());
+        assert_eq!(11, summary.total_pages);
+        assert_eq!(14, summary.days.len());
+        assert_eq!(8, summary.weeks.len());
+        assert_eq!(11, summary.days.iter().map(|d| d.pages).sum::<u32>());
+    }
+
+    #[test]
+    fn quick_turns_arent_timed() {
+        let stats = ReadingStats::default();
+        let start = Utc::now() - Duration::hours(1);
+        for i in 0..10 {
+            stats.page_turned_at(true, 1, false, start + Duration::milliseconds(i * 200));
+        }
+        let summary = stats.summary(14, 8);
+        assert_eq!(10, summary.total_pages);
+        assert_eq!(None, summary.sessions[0].seconds_per_page());
+        assert_eq!(None, stats.measured_pace());
+    }
+
+    #[test]
+    fn reading_streaks() {
+        let today = NaiveDate::from_ymd_opt(2024, 3, 10).unwrap();
+        let days: BTreeSet<_> = [1, 2, 3, 4, 7, 9, 10]
+            .into_iter()
+            .map(|d| NaiveDate::from_ymd_opt(2024, 3, d).unwrap())
+            .collect();
+        assert_eq!((4, 2), streaks(&days, today));
+        assert_eq!((4, 0), streaks(&days, today + Duration::days(2)));
+        assert_eq!((0, 0), streaks(&BTreeSet::new(), today));
+    }
+
+    #[test]
+    fn sessions_are_saved() {
+        let dir = std::env::temp_dir().join(format!("wifiremote-stats-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let stats = ReadingStats::load(&dir).unwrap();
+        let start = Utc::now() - Duration::hours(1);
+        stats.page_turned_at(true, 1, false, start);
+        stats.page_turned_at(true, 1, false, start + Duration::minutes(30));
+        stats.page_turned_at(true, 1, false, start + Duration::minutes(31));
+
+        let loaded = ReadingStats::load(&dir).unwrap().summary(1, 1);
+        assert_eq!(
+            stats.summary(1, 1).sessions,
+            loaded.sessions,
+            "Both the finished and current sessions are loaded"
+        );
+        assert_eq!(2, loaded.sessions.len());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn summary_periods_are_bounded() {
+        let summary = ReadingStats::default().summary(u32::MAX, u32::MAX);
+        assert_eq!(366, summary.days.len());
+        assert_eq!(104, summary.weeks.len());
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::Instant;
+
 use chrono::Duration;
 use tracing::trace;
 
@@ -7,3 +9,20 @@ pub fn sleep(duration: Duration) {
         std::thread::sleep(duration.to_std().unwrap());
     }
 }
+
+/// Sleep until `deadline`. With `busy_wait`, spin for the last millisecond instead, as short sleeps
+/// tend to overshoot by more than the gaps between events
+pub fn sleep_until(deadline: Instant, busy_wait: bool) {
+    let spin = if busy_wait {
+        std::time::Duration::from_millis(1)
+    } else {
+        std::time::Duration::ZERO
+    };
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining > spin {
+        std::thread::sleep(remaining - spin);
+    }
+    while busy_wait && Instant::now() < deadline {
+        std::hint::spin_loop();
+    }
+}